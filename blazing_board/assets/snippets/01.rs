@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+/// Counts how often each word appears in `text`.
+fn word_counts(text: &str) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for word in text.split_whitespace() {
+        let word = word.to_lowercase();
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    let counts = word_counts("the quick brown fox jumps over the lazy dog");
+    let mut sorted: Vec<_> = counts.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (word, count) in sorted.iter().take(3) {
+        println!("{word}: {count}");
+    }
+}
@@ -11,7 +11,6 @@ use axum::{
     response::{IntoResponse, Redirect, Response},
 };
 use chrono::{DateTime, Duration, Utc};
-use firestore::paths;
 use oauth2::{
     AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
     PkceCodeVerifier, RedirectUrl, TokenResponse, TokenUrl, basic::BasicClient,
@@ -20,30 +19,42 @@ use oauth2::{
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::{backend::get_client_db, models::UserProfile};
+use crate::{
+    models::{FollowedPlayer, MAX_FOLLOWS, UserProfile},
+    storage::storage,
+};
 
 const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
 const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
 const GITHUB_USER_URL: &str = "https://api.github.com/user";
+const GITHUB_FOLLOWING_URL: &str = "https://api.github.com/user/following";
+/// Largest page GitHub serves for the following list.
+const GITHUB_FOLLOWING_PAGE_SIZE: usize = 100;
 const DEFAULT_CALLBACK_URL: &str = "https://blazingboard.ch/auth/github/callback";
 const OAUTH_STATE_COOKIE: &str = "bb_oauth_state";
 const SESSION_COOKIE: &str = "bb_session";
-const OAUTH_STATES_COLLECTION: &str = "oauth_states";
-const SESSIONS_COLLECTION: &str = "sessions";
-const USERS_COLLECTION: &str = "users";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-struct OAuthStateRecord {
-    pkce_verifier: String,
+pub(crate) struct OAuthStateRecord {
+    pub(crate) pkce_verifier: String,
     #[serde(with = "firestore::serialize_as_timestamp")]
-    expires_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+    /// Set when the player asked to follow everyone they follow on GitHub.
+    #[serde(default)]
+    pub(crate) import_following: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-struct SessionRecord {
-    user_id: String,
+pub(crate) struct SessionRecord {
+    pub(crate) user_id: String,
     #[serde(with = "firestore::serialize_as_timestamp")]
-    expires_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GithubLoginQuery {
+    #[serde(default)]
+    import_following: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,6 +72,12 @@ struct GithubUser {
     avatar_url: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct GithubFollowedUser {
+    id: i64,
+    login: String,
+}
+
 struct OAuthConfig {
     client_id: String,
     client_secret: String,
@@ -85,8 +102,8 @@ impl OAuthConfig {
     }
 }
 
-pub(crate) async fn github_login() -> Response {
-    match begin_github_login().await {
+pub(crate) async fn github_login(Query(query): Query<GithubLoginQuery>) -> Response {
+    match begin_github_login(query.import_following).await {
         Ok(response) => response,
         Err(message) => {
             eprintln!("Unable to start GitHub login: {message}");
@@ -99,7 +116,7 @@ pub(crate) async fn github_login() -> Response {
     }
 }
 
-async fn begin_github_login() -> Result<Response, String> {
+async fn begin_github_login(import_following: bool) -> Result<Response, String> {
     let config = OAuthConfig::from_env()?;
     let client = oauth_client(&config)?;
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
@@ -111,17 +128,13 @@ async fn begin_github_login() -> Result<Response, String> {
     let record = OAuthStateRecord {
         pkce_verifier: pkce_verifier.secret().to_string(),
         expires_at: Utc::now() + Duration::minutes(10),
+        import_following,
     };
     let state = csrf_token.secret();
 
-    get_client_db()
+    storage()
         .await
-        .fluent()
-        .update()
-        .in_col(OAUTH_STATES_COLLECTION)
-        .document_id(token_hash(state))
-        .object(&record)
-        .execute::<()>()
+        .put_oauth_state(&token_hash(state), &record)
         .await
         .map_err(|error| format!("Unable to store OAuth state: {error}"))?;
 
@@ -170,26 +183,13 @@ async fn complete_github_login(
         return Err("OAuth state does not match".to_string());
     }
 
-    let db = get_client_db().await;
-    let state_document_id = token_hash(&returned_state);
-    let state_record = db
-        .fluent()
-        .select()
-        .by_id_in(OAUTH_STATES_COLLECTION)
-        .obj::<OAuthStateRecord>()
-        .one(&state_document_id)
+    let storage = storage().await;
+    let state_record = storage
+        .take_oauth_state(&token_hash(&returned_state))
         .await
-        .map_err(|error| format!("Unable to load OAuth state: {error}"))?
+        .map_err(|error| format!("Unable to consume OAuth state: {error}"))?
         .ok_or_else(|| "OAuth state has already been used or expired".to_string())?;
 
-    db.fluent()
-        .delete()
-        .from(OAUTH_STATES_COLLECTION)
-        .document_id(&state_document_id)
-        .execute()
-        .await
-        .map_err(|error| format!("Unable to consume OAuth state: {error}"))?;
-
     if state_record.expires_at <= Utc::now() {
         return Err("OAuth state expired".to_string());
     }
@@ -222,18 +222,22 @@ async fn complete_github_login(
         .map_err(|_| "Unable to decode the GitHub profile".to_string())?;
 
     let user_id = upsert_github_user(github_user).await?;
+    if state_record.import_following {
+        // The login itself succeeded, so a failed import does not undo it.
+        if let Err(message) =
+            import_github_following(&http_client, token.access_token().secret(), &user_id).await
+        {
+            eprintln!("Unable to import GitHub follows: {message}");
+        }
+    }
     let session_token = CsrfToken::new_random().secret().to_string();
     let session = SessionRecord {
         user_id,
         expires_at: Utc::now() + Duration::days(30),
     };
 
-    db.fluent()
-        .update()
-        .in_col(SESSIONS_COLLECTION)
-        .document_id(token_hash(&session_token))
-        .object(&session)
-        .execute::<()>()
+    storage
+        .put_session(&token_hash(&session_token), &session)
         .await
         .map_err(|error| format!("Unable to store the session: {error}"))?;
 
@@ -251,42 +255,30 @@ async fn complete_github_login(
 }
 
 async fn upsert_github_user(github_user: GithubUser) -> Result<String, String> {
-    let db = get_client_db().await;
+    let storage = storage().await;
     let github_id = github_user.id.to_string();
-    let existing = db
-        .fluent()
-        .select()
-        .by_id_in(USERS_COLLECTION)
-        .obj::<UserProfile>()
-        .one(&github_id)
+    let existing = storage
+        .get_user(&github_id)
         .await
         .map_err(|error| format!("Unable to load the user profile: {error}"))?;
     let now = Utc::now();
     if let Some(existing) = existing {
         let profile = UserProfile {
+            login_lowercase: github_user.login.to_lowercase(),
             login: github_user.login,
             display_name: github_user.name,
             avatar_url: github_user.avatar_url,
             last_login_at: now,
             ..existing
         };
-        db.fluent()
-            .update()
-            .fields(paths!(UserProfile::{
-                login,
-                display_name,
-                avatar_url,
-                last_login_at
-            }))
-            .in_col(USERS_COLLECTION)
-            .document_id(&github_id)
-            .object(&profile)
-            .execute::<()>()
+        storage
+            .update_user_identity(&profile)
             .await
             .map_err(|error| format!("Unable to update the user profile: {error}"))?;
     } else {
         let profile = UserProfile {
             github_id: github_id.clone(),
+            login_lowercase: github_user.login.to_lowercase(),
             login: github_user.login,
             display_name: github_user.name,
             avatar_url: github_user.avatar_url,
@@ -296,13 +288,11 @@ async fn upsert_github_user(github_user: GithubUser) -> Result<String, String> {
             best_wpm: 0.0,
             best_accuracy: 0.0,
             best_score: 0,
+            keyboard_layout: None,
+            hide_ghosts: false,
         };
-        db.fluent()
-            .update()
-            .in_col(USERS_COLLECTION)
-            .document_id(&github_id)
-            .object(&profile)
-            .execute::<()>()
+        storage
+            .create_user(&profile)
             .await
             .map_err(|error| format!("Unable to create the user profile: {error}"))?;
     }
@@ -310,15 +300,51 @@ async fn upsert_github_user(github_user: GithubUser) -> Result<String, String> {
     Ok(github_id)
 }
 
+/// Follows every GitHub account the player follows, up to the follow limit.
+async fn import_github_following(
+    http_client: &reqwest::Client,
+    access_token: &str,
+    user_id: &str,
+) -> Result<(), String> {
+    let mut players = Vec::new();
+    for page in 1..=MAX_FOLLOWS.div_ceil(GITHUB_FOLLOWING_PAGE_SIZE) {
+        let followed = http_client
+            .get(GITHUB_FOLLOWING_URL)
+            .query(&[("per_page", GITHUB_FOLLOWING_PAGE_SIZE), ("page", page)])
+            .bearer_auth(access_token)
+            .header(USER_AGENT, "BlazingBoard")
+            .header(ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .map_err(|_| "Unable to request the GitHub following list".to_string())?
+            .error_for_status()
+            .map_err(|_| "GitHub rejected the following list request".to_string())?
+            .json::<Vec<GithubFollowedUser>>()
+            .await
+            .map_err(|_| "Unable to decode the GitHub following list".to_string())?;
+        let last_page = followed.len() < GITHUB_FOLLOWING_PAGE_SIZE;
+        players.extend(followed.into_iter().map(|user| FollowedPlayer {
+            github_id: user.id.to_string(),
+            login: user.login,
+        }));
+        if last_page {
+            break;
+        }
+    }
+
+    storage()
+        .await
+        .follow_players(user_id, players)
+        .await
+        .map(|_| ())
+        .map_err(|error| format!("Unable to store the follows: {error}"))
+}
+
 pub(crate) async fn github_logout(headers: HeaderMap) -> Response {
     if let Some(session_token) = cookie_value(&headers, SESSION_COOKIE) {
-        let _ = get_client_db()
+        let _ = storage()
             .await
-            .fluent()
-            .delete()
-            .from(SESSIONS_COLLECTION)
-            .document_id(token_hash(&session_token))
-            .execute()
+            .delete_session(&token_hash(&session_token))
             .await;
     }
 
@@ -333,13 +359,9 @@ pub(crate) async fn authenticated_user_id(headers: &HeaderMap) -> Result<Option<
     };
 
     let document_id = token_hash(&session_token);
-    let db = get_client_db().await;
-    let session = db
-        .fluent()
-        .select()
-        .by_id_in(SESSIONS_COLLECTION)
-        .obj::<SessionRecord>()
-        .one(&document_id)
+    let storage = storage().await;
+    let session = storage
+        .get_session(&document_id)
         .await
         .map_err(|error| format!("Unable to load session: {error}"))?;
 
@@ -347,13 +369,7 @@ pub(crate) async fn authenticated_user_id(headers: &HeaderMap) -> Result<Option<
         return Ok(None);
     };
     if session.expires_at <= Utc::now() {
-        let _ = db
-            .fluent()
-            .delete()
-            .from(SESSIONS_COLLECTION)
-            .document_id(document_id)
-            .execute()
-            .await;
+        let _ = storage.delete_session(&document_id).await;
         return Ok(None);
     }
 
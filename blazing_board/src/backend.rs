@@ -1,12 +1,18 @@
 #[cfg(feature = "server")]
 use chrono::{NaiveDate, NaiveTime, Utc};
 use dioxus::prelude::*;
-use models::{Leaderboard, PrivateProfile, Story, TypingResult, TypingSubmission};
 #[cfg(feature = "server")]
 use models::{
-    LEADERBOARD_TOP_N, LeaderboardEntry, LeaderboardScope, LeaderboardStoredEntry,
-    RECENT_LEADERBOARD_DAYS, board_id_for_scope, challenge_date_string, is_allowed_recent_day,
-    leaderboard_label, leaderboard_sort_key, parse_challenge_date, recent_challenge_dates,
+    AROUND_ME_NEIGHBORS, DailyChallenge, GhostTimeline, KeyStats, LEADERBOARD_TOP_N,
+    LeaderboardCursor, LeaderboardEntry, LeaderboardScope, LeaderboardStoredEntry, MAX_FOLLOWS,
+    MAX_LEADERBOARD_PAGE_SIZE, MAX_TEAMS_PER_PLAYER, PlayerStanding, RECENT_LEADERBOARD_DAYS,
+    RaceSpec, TestMode, TextKind, board_id_for_scope, challenge_date_string, is_allowed_recent_day,
+    layout_board_id, leaderboard_label, parse_challenge_date, prepare_github_login,
+    prepare_team_name, recent_challenge_dates, team_board_id, typable_text,
+};
+use models::{
+    CodeSnippet, FollowedPlayer, KeyboardLayout, Language, Leaderboard, PrivateProfile, RunGhost,
+    RunTicket, Story, Team, TypingResult, TypingSubmission,
 };
 #[cfg(feature = "server")]
 use std::collections::HashMap;
@@ -15,13 +21,6 @@ use std::sync::Arc;
 #[cfg(feature = "server")]
 use std::time::{Duration as StdDuration, Instant};
 
-#[cfg(feature = "server")]
-use firestore::{FirestoreDb, FirestoreDbOptions, FirestoreQueryDirection, FirestoreTimestamp};
-#[cfg(feature = "server")]
-use futures::stream::StreamExt;
-
-#[cfg(feature = "server")]
-use std::env;
 #[cfg(feature = "server")]
 use tokio::sync::{Mutex, OnceCell};
 
@@ -29,24 +28,22 @@ use crate::models;
 #[cfg(feature = "server")]
 use crate::{
     auth::authenticated_user_id,
-    models::{UserProfile, calculate_typing_metrics, validate_run_id},
+    models::{
+        calculate_typing_metrics, prepare_custom_text, validate_run_id, verify_keystroke_log,
+    },
+    races,
+    storage::{ResultWrite, Storage, StorageError, TeamJoin, storage},
+    tickets,
 };
 
-#[cfg(feature = "server")]
-const USERS_COLLECTION: &str = "users";
-#[cfg(feature = "server")]
-const TYPING_RESULTS_COLLECTION: &str = "typing_results";
-#[cfg(feature = "server")]
-const LEADERBOARDS_COLLECTION: &str = "leaderboards";
-#[cfg(feature = "server")]
-const LEADERBOARD_ENTRIES_COLLECTION: &str = "entries";
 #[cfg(feature = "server")]
 const LEADERBOARD_CACHE_TTL: StdDuration = StdDuration::from_secs(45);
-
 #[cfg(feature = "server")]
-static CLIENT: OnceCell<FirestoreDb> = OnceCell::const_new();
+const TEAM_ID_LEN: usize = 10;
+/// Long enough that invite links cannot be guessed.
 #[cfg(feature = "server")]
-static STORY_CACHE: OnceCell<Arc<Mutex<HashMap<NaiveDate, Story>>>> = OnceCell::const_new();
+const TEAM_INVITE_CODE_LEN: usize = 24;
+
 #[cfg(feature = "server")]
 static LEADERBOARD_CACHE: OnceCell<Arc<Mutex<HashMap<String, CachedLeaderboard>>>> =
     OnceCell::const_new();
@@ -58,14 +55,17 @@ struct CachedLeaderboard {
     board: Leaderboard,
 }
 
+/// `(board_id, user_id) -> standing`, kept as long as cached boards and
+/// dropped with their board.
 #[cfg(feature = "server")]
-async fn initialize_story_cache() -> Arc<Mutex<HashMap<NaiveDate, Story>>> {
-    Arc::new(Mutex::new(HashMap::new()))
-}
+static STANDING_CACHE: OnceCell<Arc<Mutex<HashMap<(String, String), CachedStanding>>>> =
+    OnceCell::const_new();
 
 #[cfg(feature = "server")]
-async fn get_story_cache() -> Arc<Mutex<HashMap<NaiveDate, Story>>> {
-    STORY_CACHE.get_or_init(initialize_story_cache).await.clone()
+#[derive(Clone)]
+struct CachedStanding {
+    fetched_at: Instant,
+    standing: Option<PlayerStanding>,
 }
 
 #[cfg(feature = "server")]
@@ -81,31 +81,82 @@ async fn get_leaderboard_cache() -> Arc<Mutex<HashMap<String, CachedLeaderboard>
         .clone()
 }
 
-#[get("/api/story?day")]
-pub async fn get_story(day: Option<String>) -> Result<Story, ServerFnError> {
+#[cfg(feature = "server")]
+async fn initialize_standing_cache() -> Arc<Mutex<HashMap<(String, String), CachedStanding>>> {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+#[cfg(feature = "server")]
+async fn get_standing_cache() -> Arc<Mutex<HashMap<(String, String), CachedStanding>>> {
+    STANDING_CACHE
+        .get_or_init(initialize_standing_cache)
+        .await
+        .clone()
+}
+
+#[get("/api/story?day&language")]
+pub async fn get_story(
+    day: Option<String>,
+    language: Option<String>,
+) -> Result<Story, ServerFnError> {
+    let today = Utc::now().date_naive();
+    let challenge_date = resolve_challenge_day(day.as_deref(), today)?;
+    let language = resolve_language(language.as_deref())?;
+    Ok(
+        cached_challenge_for_day(storage().await, challenge_date, language)
+            .await?
+            .story,
+    )
+}
+
+/// The code snippet of a challenge day, the same in every story language.
+#[get("/api/snippet?day")]
+pub async fn get_snippet(day: Option<String>) -> Result<CodeSnippet, ServerFnError> {
     let today = Utc::now().date_naive();
     let challenge_date = resolve_challenge_day(day.as_deref(), today)?;
+    Ok(
+        cached_challenge_for_day(storage().await, challenge_date, Language::default())
+            .await?
+            .snippet,
+    )
+}
 
-    let cache = get_story_cache().await;
+#[cfg(feature = "server")]
+async fn cached_challenge_for_day(
+    storage: &dyn Storage,
+    challenge_date: NaiveDate,
+    language: Language,
+) -> Result<DailyChallenge, ServerFnError> {
+    let cache = storage.challenge_cache();
     {
         let guard = cache.lock().await;
-        if let Some(story) = guard.get(&challenge_date) {
-            return Ok(story.clone());
+        if let Some(challenge) = guard.get(&(challenge_date, language)) {
+            return Ok(challenge.clone());
         }
     }
 
-    let story = load_story_for_day(challenge_date)
+    let challenge = load_challenge_for_day(storage, challenge_date, language)
         .await
         .map_err(|e| ServerFnError::new(e.to_string()))?;
-    cache.lock().await.insert(challenge_date, story.clone());
-    Ok(story)
+    cache
+        .lock()
+        .await
+        .insert((challenge_date, language), challenge.clone());
+    Ok(challenge)
 }
 
 #[cfg(feature = "server")]
-fn resolve_challenge_day(
-    day: Option<&str>,
-    today: NaiveDate,
-) -> Result<NaiveDate, ServerFnError> {
+fn resolve_language(language: Option<&str>) -> Result<Language, ServerFnError> {
+    match language {
+        Some(language) => {
+            Language::parse(language).ok_or_else(|| ServerFnError::new("Unknown story language"))
+        }
+        None => Ok(Language::default()),
+    }
+}
+
+#[cfg(feature = "server")]
+fn resolve_challenge_day(day: Option<&str>, today: NaiveDate) -> Result<NaiveDate, ServerFnError> {
     let challenge_date = match day {
         Some(value) => parse_challenge_date(value)
             .ok_or_else(|| ServerFnError::new("Challenge day must use YYYY-MM-DD"))?,
@@ -120,44 +171,50 @@ fn resolve_challenge_day(
 }
 
 #[cfg(feature = "server")]
-async fn load_story_for_day(challenge_date: NaiveDate) -> Result<Story, String> {
-    let db = get_client_db().await;
+async fn load_challenge_for_day(
+    storage: &dyn Storage,
+    challenge_date: NaiveDate,
+    language: Language,
+) -> Result<DailyChallenge, String> {
     // Freeze each challenge at the start of its UTC day. Using the end of the
     // selected day here can assign the same late-published story to both today
     // and yesterday until the next story is generated.
     let story_cutoff = story_cutoff_for_day(challenge_date);
 
-    let mut story_stream = db
-        .fluent()
-        .select()
-        .from("texts")
-        .filter(|q| q.field("when").less_than(FirestoreTimestamp(story_cutoff)))
-        .order_by([("when", FirestoreQueryDirection::Descending)])
-        .limit(1)
-        .obj::<Story>()
-        .stream_query()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    match story_stream.next().await {
-        Some(latest_story) => {
-            let filtered_story = latest_story
-                .story
-                .replace('\n', " ")
-                .replace(",", "")
-                .replace(".", "")
-                .replace(":", "")
-                .replace(";", "")
-                .replace("’", "'");
-            Ok(Story {
-                title: latest_story.title,
-                sources: latest_story.sources,
-                story: filtered_story,
-                when: latest_story.when,
-            })
-        }
-        None => Err("No stories found".to_string()),
-    }
+    // Languages nobody has generated a story in yet serve the bundled one.
+    let latest_story = storage
+        .latest_story_before(story_cutoff, language)
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|| Story::fallback(language));
+    let snippet = storage
+        .latest_snippet_before(story_cutoff)
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+
+    let filtered_story = latest_story
+        .story
+        .replace('\n', " ")
+        .replace(",", "")
+        .replace(".", "")
+        .replace(":", "")
+        .replace(";", "")
+        .replace("’", "'");
+    Ok(DailyChallenge {
+        story: Story {
+            title: latest_story.title,
+            sources: latest_story.sources,
+            story: filtered_story,
+            raw_story: latest_story.raw_story.as_deref().map(typable_text),
+            when: latest_story.when,
+            language,
+        },
+        snippet: CodeSnippet {
+            code: typable_text(&snippet.code.replace("\r\n", "\n")),
+            ..snippet
+        },
+    })
 }
 
 #[cfg(feature = "server")]
@@ -177,60 +234,488 @@ pub async fn get_private_profile() -> Result<Option<PrivateProfile>, ServerFnErr
         return Ok(None);
     };
 
-    let db = get_client_db().await;
-    let user = db
-        .fluent()
-        .select()
-        .by_id_in(USERS_COLLECTION)
-        .obj::<UserProfile>()
-        .one(&user_id)
+    let storage = storage().await;
+    let user = storage
+        .get_user(&user_id)
         .await
         .map_err(private_server_error)?
         .ok_or_else(|| ServerFnError::new("The signed-in profile no longer exists"))?;
-    let parent = db
-        .parent_path(USERS_COLLECTION, &user_id)
+    let history = storage
+        .recent_typing_results(&user_id, 20)
+        .await
         .map_err(private_server_error)?;
-    let history = db
-        .fluent()
-        .select()
-        .from(TYPING_RESULTS_COLLECTION)
-        .parent(&parent)
-        .order_by([(
-            "created_at_epoch_seconds",
-            FirestoreQueryDirection::Descending,
-        )])
-        .limit(20)
-        .obj::<TypingResult>()
-        .query()
+    let key_stats = storage
+        .get_key_stats(&user_id)
+        .await
+        .map_err(private_server_error)?;
+    let teams = storage
+        .teams_for_user(&user_id)
+        .await
+        .map_err(private_server_error)?;
+    let following = storage
+        .followed_players(&user_id)
         .await
         .map_err(private_server_error)?;
 
-    Ok(Some(PrivateProfile { user, history }))
+    Ok(Some(PrivateProfile {
+        user,
+        history,
+        key_stats,
+        teams,
+        following,
+    }))
 }
 
-#[get("/api/leaderboard?scope&day")]
+/// Which board a leaderboard request reads.
+#[cfg(feature = "server")]
+#[derive(Debug, Clone)]
+struct BoardSelection {
+    scope: LeaderboardScope,
+    mode: TestMode,
+    text: TextKind,
+    language: Language,
+    /// `None` ranks runs on every layout together.
+    keyboard_layout: Option<KeyboardLayout>,
+    /// Set to rank only this team's members. Team boards span every layout.
+    team_id: Option<String>,
+    /// Set to the viewer's ID to rank only them and the players they follow.
+    friends_of: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl BoardSelection {
+    fn board_id(&self, challenge_date: NaiveDate) -> String {
+        let board_id = board_id_for_scope(
+            self.scope,
+            challenge_date,
+            self.mode,
+            self.text,
+            self.language,
+        );
+        match (&self.team_id, self.keyboard_layout) {
+            (Some(team_id), _) => team_board_id(team_id, &board_id),
+            (None, Some(layout)) => layout_board_id(&board_id, layout),
+            (None, None) => board_id,
+        }
+    }
+}
+
+#[get(
+    "/api/leaderboard?scope&day&mode&text&language&layout&team&friends&after_key&after_id&limit",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
 pub async fn get_leaderboard(
     scope: String,
     day: Option<String>,
+    mode: Option<String>,
+    text: Option<String>,
+    language: Option<String>,
+    layout: Option<String>,
+    team: Option<String>,
+    friends: Option<bool>,
+    after_key: Option<i64>,
+    after_id: Option<String>,
+    limit: Option<u32>,
 ) -> Result<Leaderboard, ServerFnError> {
     let scope = LeaderboardScope::parse(&scope)
         .ok_or_else(|| ServerFnError::new("Leaderboard scope must be day, week, or global"))?;
+    let mode = match mode {
+        Some(mode) => {
+            TestMode::parse(&mode).ok_or_else(|| ServerFnError::new("Unknown test mode"))?
+        }
+        None => TestMode::default(),
+    };
+    let text = match text {
+        Some(text) => {
+            TextKind::parse(&text).ok_or_else(|| ServerFnError::new("Unknown text kind"))?
+        }
+        None => TextKind::Plain,
+    };
+    let language = resolve_language(language.as_deref())?;
+    let keyboard_layout = match layout {
+        Some(layout) => Some(
+            KeyboardLayout::parse(&layout)
+                .ok_or_else(|| ServerFnError::new("Unknown keyboard layout"))?,
+        ),
+        None => None,
+    };
+    let limit = limit.unwrap_or(LEADERBOARD_TOP_N);
+    if limit == 0 || limit > MAX_LEADERBOARD_PAGE_SIZE {
+        return Err(ServerFnError::new(format!(
+            "Leaderboard pages hold 1 to {MAX_LEADERBOARD_PAGE_SIZE} entries"
+        )));
+    }
+    let after = match (after_key, after_id) {
+        (Some(sort_key), Some(github_id)) => Some(LeaderboardCursor {
+            sort_key,
+            github_id,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(ServerFnError::new(
+                "A leaderboard cursor needs both a sort key and a GitHub ID",
+            ));
+        }
+    };
+    // Only team boards, friends boards and the standing on a first page
+    // depend on who is asking.
+    let viewer_id = if team.is_some() || friends.unwrap_or(false) || after.is_none() {
+        authenticated_user_id(&headers)
+            .await
+            .map_err(private_server_error)?
+    } else {
+        None
+    };
+    if let Some(team_id) = team.as_deref() {
+        check_team_board(storage().await, viewer_id.as_deref(), team_id, scope).await?;
+    }
+    let friends_of = if friends.unwrap_or(false) {
+        if after.is_some() {
+            return Err(ServerFnError::new(
+                "The board of the players you follow has a single page",
+            ));
+        }
+        Some(
+            viewer_id
+                .clone()
+                .ok_or_else(|| ServerFnError::new("Sign in to rank the players you follow"))?,
+        )
+    } else {
+        None
+    };
+    let selection = BoardSelection {
+        scope,
+        mode,
+        text,
+        language,
+        // Team boards are not split by layout.
+        keyboard_layout: keyboard_layout.filter(|_| team.is_none()),
+        team_id: team,
+        friends_of,
+    };
     let today = Utc::now().date_naive();
     let challenge_date = if scope == LeaderboardScope::Global {
         today
     } else {
         resolve_challenge_day(day.as_deref(), today)?
     };
-    let board_id = board_id_for_scope(scope, challenge_date);
-    if let Some(cached) = cached_leaderboard(&board_id).await {
-        return Ok(cached);
+    // Friends boards differ for every viewer, so only the first page of shared
+    // boards is cached.
+    let shared = selection.friends_of.is_none() && after.is_none() && limit == LEADERBOARD_TOP_N;
+    let cached = if shared {
+        cached_leaderboard(&selection.board_id(challenge_date)).await
+    } else {
+        None
+    };
+    let mut board = match cached {
+        Some(cached) => cached,
+        None => {
+            let board = load_leaderboard_page(
+                storage().await,
+                &selection,
+                challenge_date,
+                after.as_ref(),
+                limit,
+            )
+            .await
+            .map_err(private_server_error)?;
+            if shared {
+                store_leaderboard_cache(board.clone()).await;
+            }
+            board
+        }
+    };
+    // A friends board already lists everyone the viewer compares against, and
+    // later pages leave the standing to the first one.
+    if let Some(user_id) = viewer_id.filter(|_| !board.friends && after.is_none()) {
+        board.around_me = cached_player_standing(storage().await, &board.board_id, &user_id)
+            .await
+            .map_err(private_server_error)?;
     }
+    Ok(board)
+}
 
-    let board = load_leaderboard_from_firestore(scope, &board_id, challenge_date)
+/// The player's standing, read from storage at most once per cache TTL unless
+/// a saved run changes the board.
+#[cfg(feature = "server")]
+async fn cached_player_standing(
+    storage: &dyn Storage,
+    board_id: &str,
+    user_id: &str,
+) -> Result<Option<PlayerStanding>, StorageError> {
+    let key = (board_id.to_string(), user_id.to_string());
+    let cache = get_standing_cache().await;
+    if let Some(cached) = cache
+        .lock()
+        .await
+        .get(&key)
+        .filter(|cached| cached.fetched_at.elapsed() <= LEADERBOARD_CACHE_TTL)
+    {
+        return Ok(cached.standing.clone());
+    }
+    let standing = load_player_standing(storage, board_id, user_id).await?;
+    let mut guard = cache.lock().await;
+    guard.retain(|_, cached| cached.fetched_at.elapsed() <= LEADERBOARD_CACHE_TTL);
+    guard.insert(
+        key,
+        CachedStanding {
+            fetched_at: Instant::now(),
+            standing: standing.clone(),
+        },
+    );
+    Ok(standing)
+}
+
+/// Records the keyboard layout the signed-in player types on.
+#[post(
+    "/api/profile/keyboard-layout",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn set_keyboard_layout(layout: KeyboardLayout) -> Result<(), ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to save your keyboard layout"))?;
+    storage()
+        .await
+        .set_keyboard_layout(&user_id, layout)
+        .await
+        .map_err(private_server_error)
+}
+
+/// Lets other players race the signed-in player's runs, or stops them.
+#[post(
+    "/api/profile/hide-ghosts",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn set_hide_ghosts(hide_ghosts: bool) -> Result<(), ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to change who can race your runs"))?;
+    storage()
+        .await
+        .set_hide_ghosts(&user_id, hide_ghosts)
+        .await
+        .map_err(private_server_error)
+}
+
+/// Starts a private team with the signed-in player as its first member.
+#[post(
+    "/api/teams",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn create_team(name: String) -> Result<Team, ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to create a team"))?;
+    start_team(storage().await, &user_id, &name).await
+}
+
+/// Adds the signed-in player to the team the invite code belongs to.
+#[post(
+    "/api/teams/join",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn join_team(invite_code: String) -> Result<Team, ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to join a team"))?;
+    join_team_by_invite(storage().await, &user_id, &invite_code).await
+}
+
+/// Follows the player signed in to BlazingBoard as `login` on GitHub.
+#[post(
+    "/api/follows",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn follow_player(login: String) -> Result<FollowedPlayer, ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to follow players"))?;
+    follow_login(storage().await, &user_id, &login).await
+}
+
+#[post(
+    "/api/follows/remove",
+    headers: dioxus::prelude::dioxus_fullstack::HeaderMap
+)]
+pub async fn unfollow_player(github_id: String) -> Result<(), ServerFnError> {
+    let user_id = authenticated_user_id(&headers)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("Sign in to follow players"))?;
+    storage()
+        .await
+        .unfollow_player(&user_id, &github_id)
+        .await
+        .map_err(private_server_error)
+}
+
+#[cfg(feature = "server")]
+async fn follow_login(
+    storage: &dyn Storage,
+    user_id: &str,
+    login: &str,
+) -> Result<FollowedPlayer, ServerFnError> {
+    let login = prepare_github_login(login).map_err(ServerFnError::new)?;
+    let user = storage
+        .user_by_login(&login)
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new(format!("@{login} has not played yet")))?;
+    if user.github_id == user_id {
+        return Err(ServerFnError::new("You are always on your friends board"));
+    }
+    let player = FollowedPlayer {
+        github_id: user.github_id,
+        login: user.login,
+    };
+    let followed = storage
+        .follow_players(user_id, vec![player.clone()])
         .await
         .map_err(private_server_error)?;
-    store_leaderboard_cache(board.clone()).await;
-    Ok(board)
+    if followed.is_empty() {
+        return Err(ServerFnError::new(format!(
+            "Players can follow at most {MAX_FOLLOWS} others"
+        )));
+    }
+    Ok(player)
+}
+
+#[cfg(feature = "server")]
+async fn start_team(
+    storage: &dyn Storage,
+    user_id: &str,
+    name: &str,
+) -> Result<Team, ServerFnError> {
+    let name = prepare_team_name(name).map_err(ServerFnError::new)?;
+    check_team_slots(storage, user_id).await?;
+    let team = Team {
+        id: random_id(TEAM_ID_LEN),
+        name,
+        invite_code: random_id(TEAM_INVITE_CODE_LEN),
+        owner_id: user_id.to_string(),
+        member_ids: vec![user_id.to_string()],
+        created_at: Utc::now(),
+    };
+    storage
+        .create_team(&team)
+        .await
+        .map_err(private_server_error)?;
+    Ok(team)
+}
+
+#[cfg(feature = "server")]
+async fn join_team_by_invite(
+    storage: &dyn Storage,
+    user_id: &str,
+    invite_code: &str,
+) -> Result<Team, ServerFnError> {
+    let team = storage
+        .team_by_invite(invite_code.trim())
+        .await
+        .map_err(private_server_error)?
+        .ok_or_else(|| ServerFnError::new("This invite link is not valid"))?;
+    // The team size and the player's team count are checked as they join.
+    match storage
+        .add_team_member(&team.id, user_id)
+        .await
+        .map_err(private_server_error)?
+    {
+        TeamJoin::Joined(team) => Ok(team),
+        TeamJoin::NoSuchTeam => Err(ServerFnError::new("This invite link is not valid")),
+        TeamJoin::TeamFull => Err(ServerFnError::new("This team is full")),
+        TeamJoin::TooManyTeams => Err(too_many_teams()),
+    }
+}
+
+/// Every team adds boards to each saved run, so players can only be in a few.
+#[cfg(feature = "server")]
+async fn check_team_slots(storage: &dyn Storage, user_id: &str) -> Result<(), ServerFnError> {
+    let teams = storage
+        .teams_for_user(user_id)
+        .await
+        .map_err(private_server_error)?;
+    if teams.len() >= MAX_TEAMS_PER_PLAYER {
+        return Err(too_many_teams());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn too_many_teams() -> ServerFnError {
+    ServerFnError::new(format!(
+        "Players can be in at most {MAX_TEAMS_PER_PLAYER} teams"
+    ))
+}
+
+/// Team boards are only shown to the team's members, and only exist per day
+/// and per week.
+#[cfg(feature = "server")]
+async fn check_team_board(
+    storage: &dyn Storage,
+    user_id: Option<&str>,
+    team_id: &str,
+    scope: LeaderboardScope,
+) -> Result<(), ServerFnError> {
+    if scope == LeaderboardScope::Global {
+        return Err(ServerFnError::new("Team boards are daily or weekly"));
+    }
+    let Some(user_id) = user_id else {
+        return Err(ServerFnError::new("Sign in to see your team's boards"));
+    };
+    let teams = storage
+        .teams_for_user(user_id)
+        .await
+        .map_err(private_server_error)?;
+    if !teams.iter().any(|team| team.id == team_id) {
+        return Err(ServerFnError::new("You are not a member of this team"));
+    }
+    Ok(())
+}
+
+/// Lowercase letters and digits cut from a random token.
+#[cfg(feature = "server")]
+fn random_id(len: usize) -> String {
+    std::iter::repeat_with(|| oauth2::CsrfToken::new_random().secret().clone())
+        .flat_map(String::into_bytes)
+        .filter(u8::is_ascii_alphanumeric)
+        .map(|byte| char::from(byte.to_ascii_lowercase()))
+        .take(len)
+        .collect()
+}
+
+/// The timeline of a leaderboard run, to race as a ghost on the same story.
+#[get("/api/runs/ghost?run_id")]
+pub async fn get_run_ghost(run_id: String) -> Result<RunGhost, ServerFnError> {
+    load_run_ghost(storage().await, &run_id).await
+}
+
+/// Serves a run's ghost unless its player hid their ghosts or its challenge
+/// day can no longer be played.
+#[cfg(feature = "server")]
+async fn load_run_ghost(storage: &dyn Storage, run_id: &str) -> Result<RunGhost, ServerFnError> {
+    validate_run_id(run_id).map_err(ServerFnError::new)?;
+    let Some(ghost) = storage
+        .get_run_ghost(run_id)
+        .await
+        .map_err(private_server_error)?
+    else {
+        return Err(ServerFnError::new("This run cannot be raced"));
+    };
+    let owner = storage
+        .get_user(&ghost.github_id)
+        .await
+        .map_err(private_server_error)?;
+    if owner.is_none_or(|owner| owner.hide_ghosts) {
+        return Err(ServerFnError::new("This run cannot be raced"));
+    }
+    resolve_challenge_day(Some(ghost.challenge_date.as_str()), Utc::now().date_naive())?;
+    Ok(ghost)
 }
 
 #[get("/api/leaderboard/recent-days")]
@@ -242,6 +727,21 @@ pub async fn get_recent_leaderboard_days() -> Result<Vec<String>, ServerFnError>
         .collect())
 }
 
+/// Starts a run on the challenge day's story in `language` and returns the
+/// ticket its result must be saved with.
+#[post("/api/runs")]
+pub async fn start_run(day: String, language: Language) -> Result<RunTicket, ServerFnError> {
+    let today = Utc::now().date_naive();
+    let challenge_date = resolve_challenge_day(Some(day.as_str()), today)?;
+    let challenge = cached_challenge_for_day(storage().await, challenge_date, language).await?;
+    Ok(tickets::issue(
+        tickets::secret(),
+        &challenge_date_string(challenge_date),
+        &tickets::story_hash(&challenge.story.story),
+        Utc::now(),
+    ))
+}
+
 #[post(
     "/api/typing-results",
     headers: dioxus::prelude::dioxus_fullstack::HeaderMap
@@ -254,43 +754,136 @@ pub async fn save_typing_result(
         .map_err(private_server_error)?
         .ok_or_else(|| ServerFnError::new("Sign in to save typing history"))?;
 
+    let storage = storage().await;
+    let (saved, touched_boards) = record_typing_result(storage, &user_id, submission).await?;
+    invalidate_leaderboard_cache(&touched_boards).await;
+    Ok(saved)
+}
+
+/// Validates a submission against the challenge story and stores it.
+/// Returns the saved result and the boards whose cached copy is now stale.
+#[cfg(feature = "server")]
+async fn record_typing_result(
+    storage: &dyn Storage,
+    user_id: &str,
+    submission: TypingSubmission,
+) -> Result<(TypingResult, Vec<String>), ServerFnError> {
     validate_run_id(&submission.run_id).map_err(ServerFnError::new)?;
+    let ticket =
+        tickets::verify(tickets::secret(), &submission.ticket).map_err(ServerFnError::new)?;
+    if ticket.run_id != submission.run_id || ticket.challenge_date != submission.challenge_date {
+        return Err(ServerFnError::new("Run ticket belongs to a different run"));
+    }
     let today = Utc::now().date_naive();
     let challenge_date = resolve_challenge_day(Some(submission.challenge_date.as_str()), today)?;
-    let metrics = calculate_typing_metrics(
-        submission.correct_words,
-        submission.wrong_words,
-        submission.duration_seconds,
-    )
-    .map_err(ServerFnError::new)?;
 
-    let story = get_story(Some(challenge_date_string(challenge_date))).await?;
-    if story.when.timestamp() != submission.story_when.timestamp() {
+    let challenge = cached_challenge_for_day(storage, challenge_date, submission.language).await?;
+    let story = &challenge.story;
+    if story.when.timestamp() != submission.story_when.timestamp()
+        || ticket.story_hash != tickets::story_hash(&story.story)
+    {
         return Err(ServerFnError::new(
             "The typing story changed before this result was saved",
         ));
     }
 
+    let custom_text = if submission.text.is_submitted() {
+        Some(
+            prepare_custom_text(submission.custom_text.as_deref().unwrap_or_default())
+                .map_err(ServerFnError::new)?,
+        )
+    } else {
+        None
+    };
+    let story_words = custom_text
+        .as_deref()
+        .unwrap_or_else(|| challenge.text(submission.text))
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let run = verify_keystroke_log(&submission.keystrokes, &story_words, submission.mode)
+        .map_err(ServerFnError::new)?;
+    let metrics = calculate_typing_metrics(&run).map_err(ServerFnError::new)?;
+    // Pasted passages can hold any characters, so only the daily texts and
+    // drills add to the key totals.
+    let key_stats = if submission.text.is_submitted() {
+        KeyStats::default()
+    } else {
+        submission
+            .keystrokes
+            .key_stats(&story_words, submission.keyboard_layout)
+    };
+    let ghost = submission.keystrokes.ghost_timeline();
+
     let created_at = chrono::Utc::now();
+    let run_ms = (run.duration_seconds * 1_000).max(submission.keystrokes.duration_ms());
+    ticket
+        .check_elapsed(run_ms, created_at)
+        .map_err(ServerFnError::new)?;
+
+    let race_spec = RaceSpec {
+        challenge_date: submission.challenge_date.clone(),
+        language: submission.language,
+        mode: submission.mode,
+        text: submission.text,
+    };
+    let race_run = races::RaceRun {
+        run_id: submission.run_id.clone(),
+        issued_at: ticket.issued_at,
+        correct_words: run.correct_words,
+        duration_ms: submission.keystrokes.duration_ms(),
+    };
     let result = TypingResult {
         run_id: submission.run_id,
-        story_title: story.title.unwrap_or_else(|| "Daily story".to_string()),
+        story_title: if submission.text.is_submitted() {
+            submission.text.label().to_string()
+        } else {
+            story
+                .title
+                .clone()
+                .unwrap_or_else(|| "Daily story".to_string())
+        },
         story_when: story.when,
-        correct_words: submission.correct_words,
-        wrong_words: submission.wrong_words,
-        duration_seconds: submission.duration_seconds,
+        correct_words: run.correct_words,
+        wrong_words: run.wrong_words,
+        duration_seconds: run.duration_seconds,
         accuracy: metrics.accuracy,
         wpm: metrics.wpm,
+        raw_wpm: metrics.raw_wpm,
+        cpm: metrics.cpm,
+        consistency: metrics.consistency,
+        corrected_errors: run.corrected_errors,
+        uncorrected_errors: run.uncorrected_errors,
         score: metrics.score,
         created_at,
         created_at_epoch_seconds: created_at.timestamp(),
+        mode: submission.mode,
+        text: submission.text,
+        language: submission.language,
+        keyboard_layout: submission.keyboard_layout,
+        timeline: run.timeline,
+        race: None,
     };
 
-    let (saved, touched_boards) = save_result_transaction(&user_id, result, challenge_date)
-        .await
-        .map_err(private_server_error)?;
-    invalidate_leaderboard_cache(&touched_boards).await;
-    Ok(saved)
+    let (mut saved, touched_boards) = save_result_transaction(
+        storage,
+        user_id,
+        result,
+        key_stats,
+        ghost,
+        challenge_date,
+        ticket.expires_at(),
+    )
+    .await
+    .map_err(private_server_error)?
+    .ok_or_else(|| ServerFnError::new("Run ticket has already been used"))?;
+
+    // Only a committed run takes a place, so a failed save never holds one.
+    if let Some(code) = submission.race_code.as_deref() {
+        saved.race = races::place_run(storage, code, user_id, &race_spec, race_run)
+            .await
+            .map_err(private_server_error)?;
+    }
+    Ok((saved, touched_boards))
 }
 
 #[cfg(feature = "server")]
@@ -329,185 +922,230 @@ async fn invalidate_leaderboard_cache(board_ids: &[String]) {
     for board_id in board_ids {
         guard.remove(board_id);
     }
+    // Any saved run on a board can move every player's standing on it.
+    get_standing_cache()
+        .await
+        .lock()
+        .await
+        .retain(|(board_id, _), _| !board_ids.contains(board_id));
 }
 
 #[cfg(feature = "server")]
-async fn load_leaderboard_from_firestore(
-    scope: LeaderboardScope,
-    board_id: &str,
+async fn load_leaderboard_from_storage(
+    storage: &dyn Storage,
+    selection: &BoardSelection,
     challenge_date: NaiveDate,
-) -> firestore::FirestoreResult<Leaderboard> {
-    let db = get_client_db().await;
-    let parent = db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
-    let stored = db
-        .fluent()
-        .select()
-        .from(LEADERBOARD_ENTRIES_COLLECTION)
-        .parent(&parent)
-        .order_by([("sort_key", FirestoreQueryDirection::Descending)])
-        .limit(LEADERBOARD_TOP_N)
-        .obj::<LeaderboardStoredEntry>()
-        .query()
-        .await?;
+) -> Result<Leaderboard, StorageError> {
+    load_leaderboard_page(storage, selection, challenge_date, None, LEADERBOARD_TOP_N).await
+}
+
+/// Up to `limit` entries following `after`, or from the top of the board.
+/// Friends boards always come whole.
+#[cfg(feature = "server")]
+async fn load_leaderboard_page(
+    storage: &dyn Storage,
+    selection: &BoardSelection,
+    challenge_date: NaiveDate,
+    after: Option<&LeaderboardCursor>,
+    limit: u32,
+) -> Result<Leaderboard, StorageError> {
+    let board_id = selection.board_id(challenge_date);
+    let (stored, first_rank, next_cursor) = match &selection.friends_of {
+        Some(user_id) => {
+            let mut github_ids = vec![user_id.clone()];
+            github_ids.extend(
+                storage
+                    .followed_players(user_id)
+                    .await?
+                    .into_iter()
+                    .map(|player| player.github_id),
+            );
+            let stored = storage
+                .leaderboard_entries_for(&board_id, &github_ids)
+                .await?;
+            (stored, 1, None)
+        }
+        None => {
+            // One entry past the page tells whether another page follows.
+            let mut stored = match after {
+                Some(cursor) => {
+                    storage
+                        .leaderboard_entries_after(&board_id, cursor, limit + 1)
+                        .await?
+                }
+                None => {
+                    storage
+                        .top_leaderboard_entries(&board_id, limit + 1)
+                        .await?
+                }
+            };
+            let next_cursor = if stored.len() > limit as usize {
+                stored.truncate(limit as usize);
+                stored.last().map(LeaderboardCursor::of)
+            } else {
+                None
+            };
+            // The cursor's own entry may have moved since, so ranks count
+            // from the page's first entry.
+            let first_rank = match (after, stored.first()) {
+                (Some(_), Some(first)) => {
+                    storage
+                        .count_leaderboard_entries(&board_id, Some(&LeaderboardCursor::of(first)))
+                        .await?
+                        + 1
+                }
+                _ => 1,
+            };
+            (stored, first_rank, next_cursor)
+        }
+    };
 
     let entries = stored
         .into_iter()
-        .enumerate()
-        .map(|(index, entry)| LeaderboardEntry {
-            rank: (index + 1) as i32,
-            github_id: entry.github_id,
-            login: entry.login,
-            avatar_url: entry.avatar_url,
-            score: entry.score,
-            wpm: entry.wpm,
-            accuracy: entry.accuracy,
-            run_id: entry.run_id,
-        })
+        .zip(first_rank..)
+        .map(|(entry, rank)| ranked_entry(rank, entry))
         .collect();
 
     Ok(Leaderboard {
-        scope,
-        board_id: board_id.to_string(),
-        label: leaderboard_label(scope, challenge_date),
-        challenge_date: match scope {
+        scope: selection.scope,
+        mode: selection.mode,
+        text: selection.text,
+        language: selection.language,
+        keyboard_layout: selection.keyboard_layout,
+        team_id: selection.team_id.clone(),
+        friends: selection.friends_of.is_some(),
+        board_id,
+        label: leaderboard_label(selection.scope, challenge_date),
+        challenge_date: match selection.scope {
             LeaderboardScope::Global => None,
             LeaderboardScope::Day | LeaderboardScope::Week => {
                 Some(challenge_date_string(challenge_date))
             }
         },
         entries,
+        around_me: None,
+        next_cursor,
     })
 }
 
+/// The player's rank on the board, with up to [`AROUND_ME_NEIGHBORS`] entries
+/// on each side of theirs.
 #[cfg(feature = "server")]
-async fn save_result_transaction(
+async fn load_player_standing(
+    storage: &dyn Storage,
+    board_id: &str,
     user_id: &str,
-    result: TypingResult,
-    challenge_date: NaiveDate,
-) -> firestore::FirestoreResult<(TypingResult, Vec<String>)> {
-    let db = get_client_db().await;
-    let user_id = user_id.to_string();
-
-    db.run_transaction(move |db, transaction| {
-        let user_id = user_id.clone();
-        let result = result.clone();
-        Box::pin(async move {
-            let parent = db.parent_path(USERS_COLLECTION, &user_id)?;
-            let existing = db
-                .fluent()
-                .select()
-                .by_id_in(TYPING_RESULTS_COLLECTION)
-                .parent(&parent)
-                .obj::<TypingResult>()
-                .one(&result.run_id)
-                .await?;
-            if let Some(existing) = existing {
-                return Ok((existing, Vec::new()));
-            }
-
-            let mut user = db
-                .fluent()
-                .select()
-                .by_id_in(USERS_COLLECTION)
-                .obj::<UserProfile>()
-                .one(&user_id)
-                .await?
-                .ok_or_else(|| {
-                    firestore::errors::FirestoreError::DataNotFoundError(
-                        firestore::errors::FirestoreDataNotFoundError {
-                            public: firestore::errors::FirestoreErrorPublicGenericDetails {
-                                code: "profile_missing".to_string(),
-                            },
-                            data_detail_message: format!(
-                                "Authenticated profile {user_id} no longer exists"
-                            ),
-                        },
-                    )
-                })?;
-
-            user.total_runs += 1;
-            user.best_wpm = user.best_wpm.max(result.wpm);
-            user.best_accuracy = user.best_accuracy.max(result.accuracy);
-            user.best_score = user.best_score.max(result.score);
-
-            db.fluent()
-                .update()
-                .in_col(TYPING_RESULTS_COLLECTION)
-                .document_id(&result.run_id)
-                .parent(&parent)
-                .object(&result)
-                .add_to_transaction(transaction)?;
-            db.fluent()
-                .update()
-                .in_col(USERS_COLLECTION)
-                .document_id(&user_id)
-                .object(&user)
-                .add_to_transaction(transaction)?;
-
-            let challenge_date_str = challenge_date_string(challenge_date);
-            let candidate = LeaderboardStoredEntry {
-                github_id: user.github_id.clone(),
-                login: user.login.clone(),
-                avatar_url: user.avatar_url.clone(),
-                score: result.score,
-                wpm: result.wpm,
-                accuracy: result.accuracy,
-                run_id: result.run_id.clone(),
-                challenge_date: challenge_date_str,
-                sort_key: leaderboard_sort_key(result.score, result.wpm),
-                updated_at: result.created_at,
-            };
-
-            let board_ids = [
-                board_id_for_scope(LeaderboardScope::Day, challenge_date),
-                board_id_for_scope(LeaderboardScope::Week, challenge_date),
-                board_id_for_scope(LeaderboardScope::Global, challenge_date),
-            ];
-            let mut touched_boards = Vec::new();
-            for board_id in board_ids {
-                if upsert_leaderboard_entry(&db, transaction, &board_id, &candidate).await? {
-                    touched_boards.push(board_id);
-                }
-            }
+) -> Result<Option<PlayerStanding>, StorageError> {
+    let Some(entry) = storage.leaderboard_entry(board_id, user_id).await? else {
+        return Ok(None);
+    };
+    let cursor = LeaderboardCursor::of(&entry);
+    let rank = storage
+        .count_leaderboard_entries(board_id, Some(&cursor))
+        .await?
+        + 1;
+    let players = storage.count_leaderboard_entries(board_id, None).await?;
+    let above = storage
+        .leaderboard_entries_before(board_id, &cursor, AROUND_ME_NEIGHBORS)
+        .await?;
+    let below = storage
+        .leaderboard_entries_after(board_id, &cursor, AROUND_ME_NEIGHBORS)
+        .await?;
+    let first_rank = rank - above.len() as u32;
+    let entries = above
+        .into_iter()
+        .chain([entry])
+        .chain(below)
+        .zip(first_rank..)
+        .map(|(entry, rank)| ranked_entry(rank, entry))
+        .collect();
 
-            Ok((result, touched_boards))
-        })
-    })
-    .await
+    Ok(Some(PlayerStanding {
+        rank: rank as i32,
+        players: players as i32,
+        percentile: PlayerStanding::percentile_for(rank as i32, players as i32),
+        entries,
+    }))
 }
 
 #[cfg(feature = "server")]
-async fn upsert_leaderboard_entry(
-    db: &FirestoreDb,
-    transaction: &mut firestore::FirestoreTransaction<'_>,
-    board_id: &str,
-    candidate: &LeaderboardStoredEntry,
-) -> firestore::FirestoreResult<bool> {
-    let parent = db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
-    let existing = db
-        .fluent()
-        .select()
-        .by_id_in(LEADERBOARD_ENTRIES_COLLECTION)
-        .parent(&parent)
-        .obj::<LeaderboardStoredEntry>()
-        .one(&candidate.github_id)
-        .await?;
-
-    if existing
-        .as_ref()
-        .is_some_and(|entry| entry.sort_key >= candidate.sort_key)
-    {
-        return Ok(false);
+fn ranked_entry(rank: u32, entry: LeaderboardStoredEntry) -> LeaderboardEntry {
+    LeaderboardEntry {
+        rank: rank as i32,
+        github_id: entry.github_id,
+        login: entry.login,
+        avatar_url: entry.avatar_url,
+        score: entry.score,
+        wpm: entry.wpm,
+        raw_wpm: entry.raw_wpm,
+        cpm: entry.cpm,
+        consistency: entry.consistency,
+        accuracy: entry.accuracy,
+        run_id: entry.run_id,
+        keyboard_layout: entry.keyboard_layout,
     }
+}
 
-    db.fluent()
-        .update()
-        .in_col(LEADERBOARD_ENTRIES_COLLECTION)
-        .document_id(&candidate.github_id)
-        .parent(&parent)
-        .object(candidate)
-        .add_to_transaction(transaction)?;
-    Ok(true)
+#[cfg(feature = "server")]
+async fn save_result_transaction(
+    storage: &dyn Storage,
+    user_id: &str,
+    result: TypingResult,
+    key_stats: KeyStats,
+    ghost: GhostTimeline,
+    challenge_date: NaiveDate,
+    ticket_expires_at: chrono::DateTime<Utc>,
+) -> Result<Option<(TypingResult, Vec<String>)>, StorageError> {
+    // A run only competes against runs of the same mode, on the boards for
+    // every layout and for its own, and on the day and week boards of each of
+    // the player's teams. Custom text stays in the private history.
+    let ranked = result.text.is_ranked();
+    let board_ids = if ranked {
+        let team_ids = storage
+            .teams_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|team| team.id)
+            .collect::<Vec<_>>();
+        [
+            LeaderboardScope::Day,
+            LeaderboardScope::Week,
+            LeaderboardScope::Global,
+        ]
+        .into_iter()
+        .flat_map(|scope| {
+            let board_id = board_id_for_scope(
+                scope,
+                challenge_date,
+                result.mode,
+                result.text,
+                result.language,
+            );
+            let layout_board = layout_board_id(&board_id, result.keyboard_layout);
+            let team_boards = team_ids
+                .iter()
+                .filter(|_| scope != LeaderboardScope::Global)
+                .map(|team_id| team_board_id(team_id, &board_id))
+                .collect::<Vec<_>>();
+            [board_id, layout_board].into_iter().chain(team_boards)
+        })
+        .collect()
+    } else {
+        Vec::new()
+    };
+    storage
+        .save_typing_result(ResultWrite {
+            user_id: user_id.to_string(),
+            result,
+            challenge_date: challenge_date_string(challenge_date),
+            board_ids,
+            key_stats,
+            ghost: ranked.then_some(ghost),
+            ghost_expires_at: story_cutoff_for_day(challenge_date)
+                + chrono::Duration::days(RECENT_LEADERBOARD_DAYS),
+            ticket_expires_at,
+        })
+        .await
 }
 
 #[cfg(feature = "server")]
@@ -516,42 +1154,699 @@ fn private_server_error(error: impl std::fmt::Display) -> ServerFnError {
     ServerFnError::new("The server could not complete this request")
 }
 
-#[cfg(feature = "server")]
-pub(crate) async fn get_client_db() -> &'static FirestoreDb {
-    CLIENT
-        .get_or_init(|| async {
-            dotenvy::dotenv().ok();
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::{
+        BoardSelection, cached_challenge_for_day, check_team_board, follow_login,
+        join_team_by_invite, load_leaderboard_from_storage, load_leaderboard_page,
+        load_player_standing, load_run_ghost, record_typing_result, start_team,
+        story_cutoff_for_day,
+    };
+    use crate::{
+        models::{
+            FollowedPlayer, KeyboardLayout, KeystrokeLog, Language, Leaderboard, LeaderboardScope,
+            MAX_FOLLOWS, MAX_TEAM_MEMBERS, Story, Team, TestMode, TextKind, TypingSubmission,
+            UserProfile, board_id_for_scope, challenge_date_string, team_board_id,
+        },
+        storage::{MemoryStorage, Storage},
+        tickets,
+    };
+    use chrono::{DateTime, Duration, NaiveDate, Utc};
 
-            let project_id = env::var("PROJECT_ID").expect("PROJECT_ID not set");
-            let database_id = env::var("DATABASE_ID").expect("DATABASE_ID not set");
+    /// Today's one-minute plain board, for every layout or just `keyboard_layout`.
+    fn day_board(keyboard_layout: Option<KeyboardLayout>) -> BoardSelection {
+        BoardSelection {
+            scope: LeaderboardScope::Day,
+            mode: TestMode::default(),
+            text: TextKind::Plain,
+            language: Language::English,
+            keyboard_layout,
+            team_id: None,
+            friends_of: None,
+        }
+    }
 
-            // Check for the "IAMTHEDEV" environment variable
-            if env::var("IAMTHEDEV").is_err() {
-                let db = FirestoreDb::with_options(
-                    FirestoreDbOptions::new(project_id).with_database_id(database_id),
-                )
+    async fn storage_with_players(ids: &[&str]) -> MemoryStorage {
+        let storage = MemoryStorage::default();
+        for id in ids {
+            storage
+                .create_user(&UserProfile {
+                    github_id: id.to_string(),
+                    login: format!("player{id}"),
+                    login_lowercase: format!("player{id}"),
+                    display_name: None,
+                    avatar_url: format!("https://avatars.example/{id}"),
+                    created_at: Utc::now(),
+                    last_login_at: Utc::now(),
+                    total_runs: 0,
+                    best_wpm: 0.0,
+                    best_accuracy: 0.0,
+                    best_score: 0,
+                    keyboard_layout: None,
+                    hide_ghosts: false,
+                })
                 .await
-                .expect("Failed to initialize FirestoreDb using PROJECT_ID and DATABASE_ID");
-                return db;
+                .unwrap();
+        }
+        storage
+    }
+
+    async fn submission(
+        storage: &dyn Storage,
+        correct_words: usize,
+        wrong_words: usize,
+    ) -> TypingSubmission {
+        submission_in(storage, Language::English, correct_words, wrong_words).await
+    }
+
+    /// Types the first `correct_words` words of today's story in `language`,
+    /// then `wrong_words` misspelled ones, one key every 100 ms, under a ticket
+    /// issued a minute ago.
+    async fn submission_in(
+        storage: &dyn Storage,
+        language: Language,
+        correct_words: usize,
+        wrong_words: usize,
+    ) -> TypingSubmission {
+        let today = Utc::now().date_naive();
+        let story = cached_challenge_for_day(storage, today, language)
+            .await
+            .unwrap()
+            .story
+            .story;
+        let challenge_date = challenge_date_string(today);
+        let ticket = tickets::issue(
+            tickets::secret(),
+            &challenge_date,
+            &tickets::story_hash(&story),
+            Utc::now() - Duration::seconds(61),
+        );
+        let mut keystrokes = KeystrokeLog::default();
+        let mut at_ms = 0;
+        for (word_index, word) in story
+            .split_whitespace()
+            .take(correct_words + wrong_words)
+            .enumerate()
+        {
+            let typed = if word_index < correct_words {
+                format!("{word} ")
+            } else {
+                format!("{word}x ")
+            };
+            let mut input = String::new();
+            for character in typed.chars() {
+                let previous = input.clone();
+                input.push(character);
+                keystrokes.record(
+                    at_ms,
+                    character.to_string(),
+                    word_index as u32,
+                    &previous,
+                    &input,
+                );
+                at_ms += 100;
             }
+        }
 
-            // Initialize Firestore client using service account key file
-            let db = FirestoreDb::with_options_service_account_key_file(
-                FirestoreDbOptions::new(project_id).with_database_id(database_id),
-                "key.json".into(),
-            )
+        TypingSubmission {
+            run_id: ticket.run_id,
+            challenge_date,
+            story_when: Story::default().when,
+            mode: TestMode::default(),
+            text: TextKind::Plain,
+            language,
+            keyboard_layout: KeyboardLayout::Qwerty,
+            ticket: ticket.token,
+            custom_text: None,
+            keystrokes,
+            race_code: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_result_and_updates_profile_and_boards() {
+        let storage = storage_with_players(&["1"]).await;
+
+        let run = submission(&storage, 45, 5).await;
+        let (saved, touched) = record_typing_result(&storage, "1", run).await.unwrap();
+
+        let story = cached_challenge_for_day(&storage, Utc::now().date_naive(), Language::English)
             .await
-            .expect("Failed to initialize FirestoreDb using service account key file");
+            .unwrap()
+            .story;
+        let correct_chars = story
+            .split_whitespace()
+            .take(45)
+            .map(|word| word.chars().count() + 1)
+            .sum::<usize>() as f64;
+        // Each misspelled word carries one extra `x` that was never deleted.
+        let keystrokes = story
+            .split_whitespace()
+            .take(50)
+            .map(|word| word.chars().count() + 1)
+            .sum::<usize>() as f64
+            + 5.0;
+        let accuracy = (keystrokes - 5.0) / keystrokes;
+        assert!((saved.cpm - correct_chars).abs() < 1e-9);
+        assert!((saved.accuracy - accuracy).abs() < 1e-9);
+        assert_eq!(saved.score, (correct_chars / 5.0 * accuracy).round() as i64);
+        assert_eq!((saved.corrected_errors, saved.uncorrected_errors), (0, 5));
+        assert!(saved.raw_wpm > saved.wpm);
+        assert_eq!(saved.timeline.len() as i64, saved.duration_seconds);
+        assert_eq!(touched.len(), 6);
+        let profile = storage.get_user("1").await.unwrap().unwrap();
+        assert_eq!(profile.total_runs, 1);
+        assert_eq!(profile.best_score, saved.score);
+        assert_eq!(
+            storage.recent_typing_results("1", 20).await.unwrap(),
+            vec![saved]
+        );
+    }
 
-            db
-        })
+    #[tokio::test]
+    async fn each_storage_serves_its_own_stories() {
+        let story = |text: &str| Story {
+            story: text.to_string(),
+            when: Utc::now() - Duration::days(2),
+            ..Story::fallback(Language::English)
+        };
+        let first = MemoryStorage::with_stories(vec![story("first story")]);
+        let second = MemoryStorage::with_stories(vec![story("second story")]);
+
+        let today = Utc::now().date_naive();
+        for (storage, text) in [(&first, "first story"), (&second, "second story")] {
+            let served = cached_challenge_for_day(storage, today, Language::English)
+                .await
+                .unwrap();
+            assert_eq!(served.story.story, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn languages_without_a_story_serve_the_bundled_one() {
+        let english = Story {
+            when: Utc::now() - Duration::days(2),
+            ..Story::fallback(Language::English)
+        };
+        let storage = MemoryStorage::with_stories(vec![english.clone()]);
+
+        let today = Utc::now().date_naive();
+        let served = cached_challenge_for_day(&storage, today, Language::German)
+            .await
+            .unwrap();
+        assert_eq!(served.story.language, Language::German);
+        assert_eq!(served.story.when, Story::fallback(Language::German).when);
+        let served = cached_challenge_for_day(&storage, today, Language::English)
+            .await
+            .unwrap();
+        assert_eq!(served.story.when, english.when);
+    }
+
+    #[tokio::test]
+    async fn run_tickets_are_single_use() {
+        let storage = storage_with_players(&["1", "2"]).await;
+        let run = submission(&storage, 45, 5).await;
+
+        record_typing_result(&storage, "1", run.clone())
+            .await
+            .unwrap();
+        assert!(
+            record_typing_result(&storage, "1", run.clone())
+                .await
+                .is_err()
+        );
+        assert!(record_typing_result(&storage, "2", run).await.is_err());
+        assert_eq!(storage.get_user("1").await.unwrap().unwrap().total_runs, 1);
+        assert_eq!(storage.get_user("2").await.unwrap().unwrap().total_runs, 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_tickets_for_another_run_or_a_shorter_wait() {
+        let storage = storage_with_players(&["1"]).await;
+        let mut swapped = submission(&storage, 45, 5).await;
+        swapped.run_id = submission(&storage, 45, 5).await.run_id;
+        assert!(record_typing_result(&storage, "1", swapped).await.is_err());
+
+        let today = Utc::now().date_naive();
+        let mut fresh = submission(&storage, 45, 5).await;
+        let ticket = tickets::issue(
+            tickets::secret(),
+            &fresh.challenge_date,
+            &tickets::story_hash(
+                &cached_challenge_for_day(&storage, today, Language::English)
+                    .await
+                    .unwrap()
+                    .story
+                    .story,
+            ),
+            Utc::now(),
+        );
+        fresh.run_id = ticket.run_id;
+        fresh.ticket = ticket.token;
+        assert!(record_typing_result(&storage, "1", fresh).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_results_for_a_different_story() {
+        let storage = storage_with_players(&["1"]).await;
+        let mut stale = submission(&storage, 45, 5).await;
+        stale.story_when = Utc::now();
+
+        assert!(record_typing_result(&storage, "1", stale).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_runs_without_a_keystroke_log() {
+        let storage = storage_with_players(&["1"]).await;
+        let mut empty = submission(&storage, 45, 5).await;
+        empty.keystrokes = KeystrokeLog::default();
+
+        assert!(record_typing_result(&storage, "1", empty).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn leaderboard_keeps_each_players_best_run_in_score_order() {
+        let storage = storage_with_players(&["1", "2"]).await;
+        let mut run_ids = Vec::new();
+        for (user_id, correct, wrong) in [("1", 30, 0), ("2", 50, 0), ("1", 60, 0), ("1", 10, 10)] {
+            let run = submission(&storage, correct, wrong).await;
+            let (saved, _) = record_typing_result(&storage, user_id, run).await.unwrap();
+            run_ids.push(saved.run_id);
+        }
+
+        let today = Utc::now().date_naive();
+        let board = load_leaderboard_from_storage(&storage, &day_board(None), today)
+            .await
+            .unwrap();
+
+        let ranked = board
+            .entries
+            .iter()
+            .map(|entry| (entry.rank, entry.github_id.as_str(), entry.run_id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked,
+            vec![(1, "1", run_ids[2].as_str()), (2, "2", run_ids[1].as_str())]
+        );
+        assert_eq!(board.challenge_date, Some(challenge_date_string(today)));
+    }
+
+    #[tokio::test]
+    async fn runs_only_compete_on_boards_of_their_mode() {
+        let storage = storage_with_players(&["1"]).await;
+        let mut sprint = submission(&storage, 10, 0).await;
+        sprint.mode = TestMode::Words(10);
+
+        let (saved, touched) = record_typing_result(&storage, "1", sprint).await.unwrap();
+
+        let today = Utc::now().date_naive();
+        assert_eq!(saved.mode, TestMode::Words(10));
+        assert!(touched.contains(&board_id_for_scope(
+            LeaderboardScope::Day,
+            today,
+            TestMode::Words(10),
+            TextKind::Plain,
+            Language::English
+        )));
+        let minute_board = board_id_for_scope(
+            LeaderboardScope::Day,
+            today,
+            TestMode::default(),
+            TextKind::Plain,
+            Language::English,
+        );
+        assert!(!touched.contains(&minute_board));
+        assert!(
+            storage
+                .top_leaderboard_entries(&minute_board, 50)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn layout_boards_only_rank_runs_typed_on_that_layout() {
+        let storage = storage_with_players(&["1", "2"]).await;
+        let mut dvorak = submission(&storage, 30, 0).await;
+        dvorak.keyboard_layout = KeyboardLayout::Dvorak;
+        let (saved, _) = record_typing_result(&storage, "1", dvorak).await.unwrap();
+        let qwerty = submission(&storage, 40, 0).await;
+        record_typing_result(&storage, "2", qwerty).await.unwrap();
+
+        let today = Utc::now().date_naive();
+        let players = |board: Leaderboard| {
+            board
+                .entries
+                .into_iter()
+                .map(|entry| (entry.github_id, entry.keyboard_layout))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(saved.keyboard_layout, KeyboardLayout::Dvorak);
+        let everyone = load_leaderboard_from_storage(&storage, &day_board(None), today)
+            .await
+            .unwrap();
+        assert_eq!(
+            players(everyone),
+            vec![
+                ("2".to_string(), KeyboardLayout::Qwerty),
+                ("1".to_string(), KeyboardLayout::Dvorak)
+            ]
+        );
+        let dvorak_board = load_leaderboard_from_storage(
+            &storage,
+            &day_board(Some(KeyboardLayout::Dvorak)),
+            today,
+        )
         .await
-}
+        .unwrap();
+        assert_eq!(dvorak_board.keyboard_layout, Some(KeyboardLayout::Dvorak));
+        assert_eq!(
+            players(dvorak_board),
+            vec![("1".to_string(), KeyboardLayout::Dvorak)]
+        );
+    }
 
-#[cfg(all(test, feature = "server"))]
-mod tests {
-    use super::story_cutoff_for_day;
-    use chrono::{DateTime, NaiveDate, Utc};
+    #[tokio::test]
+    async fn team_boards_rank_members_and_are_shown_only_to_them() {
+        let storage = storage_with_players(&["1", "2", "3"]).await;
+        let team = start_team(&storage, "1", "  Platform  team ")
+            .await
+            .unwrap();
+        assert_eq!(team.name, "Platform team");
+        assert!(
+            join_team_by_invite(&storage, "2", "not-an-invite")
+                .await
+                .is_err()
+        );
+        let joined = join_team_by_invite(&storage, "2", &team.invite_code)
+            .await
+            .unwrap();
+        assert_eq!(joined.member_ids, ["1", "2"]);
+        for (user_id, correct) in [("1", 30), ("2", 40), ("3", 50)] {
+            let run = submission(&storage, correct, 0).await;
+            record_typing_result(&storage, user_id, run).await.unwrap();
+        }
+
+        let today = Utc::now().date_naive();
+        let team_board = BoardSelection {
+            team_id: Some(team.id.clone()),
+            ..day_board(None)
+        };
+        let board = load_leaderboard_from_storage(&storage, &team_board, today)
+            .await
+            .unwrap();
+        assert_eq!(
+            board.board_id,
+            team_board_id(&team.id, &day_board(None).board_id(today))
+        );
+        assert_eq!(
+            board
+                .entries
+                .iter()
+                .map(|entry| entry.github_id.as_str())
+                .collect::<Vec<_>>(),
+            ["2", "1"]
+        );
+        let everyone = load_leaderboard_from_storage(&storage, &day_board(None), today)
+            .await
+            .unwrap();
+        assert_eq!(everyone.entries.len(), 3);
+
+        let check = |user_id, scope| check_team_board(&storage, user_id, &team.id, scope);
+        assert!(check(Some("2"), LeaderboardScope::Week).await.is_ok());
+        assert!(check(Some("3"), LeaderboardScope::Day).await.is_err());
+        assert!(check(None, LeaderboardScope::Day).await.is_err());
+        assert!(check(Some("1"), LeaderboardScope::Global).await.is_err());
+
+        let full = Team {
+            id: "full".to_string(),
+            invite_code: "full-invite".to_string(),
+            member_ids: (0..MAX_TEAM_MEMBERS).map(|id| format!("m{id}")).collect(),
+            ..team.clone()
+        };
+        storage.create_team(&full).await.unwrap();
+        assert!(
+            join_team_by_invite(&storage, "3", "full-invite")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn friends_boards_rank_followed_players_and_yourself() {
+        let storage = storage_with_players(&["1", "2", "3"]).await;
+        assert!(follow_login(&storage, "1", "nobody").await.is_err());
+        assert!(follow_login(&storage, "1", "player1").await.is_err());
+        let followed = follow_login(&storage, "1", "@Player3").await.unwrap();
+        assert_eq!(followed.github_id, "3");
+        for (user_id, correct) in [("1", 30), ("2", 40), ("3", 50)] {
+            let run = submission(&storage, correct, 0).await;
+            record_typing_result(&storage, user_id, run).await.unwrap();
+        }
+
+        let today = Utc::now().date_naive();
+        for scope in [
+            LeaderboardScope::Day,
+            LeaderboardScope::Week,
+            LeaderboardScope::Global,
+        ] {
+            let friends = BoardSelection {
+                scope,
+                friends_of: Some("1".to_string()),
+                ..day_board(None)
+            };
+            let board = load_leaderboard_from_storage(&storage, &friends, today)
+                .await
+                .unwrap();
+            assert!(board.friends);
+            assert_eq!(
+                board
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.rank, entry.github_id.as_str()))
+                    .collect::<Vec<_>>(),
+                [(1, "3"), (2, "1")]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn standing_gives_the_exact_rank_and_the_entries_around_it() {
+        let ids = ["1", "2", "3", "4", "5", "6"];
+        let storage = storage_with_players(&ids).await;
+        for (correct, user_id) in (10..).step_by(10).zip(ids) {
+            let run = submission(&storage, correct, 0).await;
+            record_typing_result(&storage, user_id, run).await.unwrap();
+        }
+
+        let board_id = day_board(None).board_id(Utc::now().date_naive());
+        // Player 2 has the second lowest score.
+        let standing = load_player_standing(&storage, &board_id, "2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((standing.rank, standing.players), (5, 6));
+        assert_eq!(standing.percentile, 20.0);
+        assert_eq!(
+            standing
+                .entries
+                .iter()
+                .map(|entry| (entry.rank, entry.github_id.as_str()))
+                .collect::<Vec<_>>(),
+            [(2, "5"), (3, "4"), (4, "3"), (5, "2"), (6, "1")]
+        );
+        let leader = load_player_standing(&storage, &board_id, "6")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((leader.rank, leader.percentile), (1, 100.0));
+        assert_eq!(leader.entries.len(), 4);
+        assert!(
+            load_player_standing(&storage, &board_id, "7")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_continue_after_the_cursor_with_exact_ranks() {
+        let ids = ["1", "2", "3", "4", "5"];
+        let storage = storage_with_players(&ids).await;
+        for (correct, user_id) in (10..).step_by(10).zip(ids) {
+            let run = submission(&storage, correct, 0).await;
+            record_typing_result(&storage, user_id, run).await.unwrap();
+        }
+        let today = Utc::now().date_naive();
+        let ranks = |board: &Leaderboard| {
+            board
+                .entries
+                .iter()
+                .map(|entry| (entry.rank, entry.github_id.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut after = None;
+        let mut pages = Vec::new();
+        loop {
+            let page = load_leaderboard_page(&storage, &day_board(None), today, after.as_ref(), 2)
+                .await
+                .unwrap();
+            pages.push(ranks(&page));
+            after = page.next_cursor;
+            if after.is_none() {
+                break;
+            }
+        }
+        let rank = |rank: i32, github_id: &str| (rank, github_id.to_string());
+        assert_eq!(
+            pages,
+            [
+                vec![rank(1, "5"), rank(2, "4")],
+                vec![rank(3, "3"), rank(4, "2")],
+                vec![rank(5, "1")],
+            ]
+        );
+        // A full board fits on the default page and has no next page.
+        let board = load_leaderboard_from_storage(&storage, &day_board(None), today)
+            .await
+            .unwrap();
+        assert_eq!(board.entries.len(), 5);
+        assert!(board.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn follows_stop_at_the_limit() {
+        let storage = storage_with_players(&["1"]).await;
+        let players = (0..MAX_FOLLOWS + 5)
+            .map(|index| FollowedPlayer {
+                github_id: format!("gh{index}"),
+                login: format!("octo{index}"),
+            })
+            .chain([FollowedPlayer {
+                github_id: "1".to_string(),
+                login: "player1".to_string(),
+            }])
+            .collect::<Vec<_>>();
+
+        let followed = storage.follow_players("1", players.clone()).await.unwrap();
+        assert_eq!(followed.len(), MAX_FOLLOWS);
+        assert!(followed.iter().all(|player| player.github_id != "1"));
+        // Players already followed still get their login refreshed at the limit.
+        let renamed = FollowedPlayer {
+            login: "renamed".to_string(),
+            ..players[0].clone()
+        };
+        let refreshed = storage
+            .follow_players("1", vec![renamed, players[MAX_FOLLOWS].clone()])
+            .await
+            .unwrap();
+        assert_eq!(refreshed.len(), 1);
+        assert!(
+            storage
+                .followed_players("1")
+                .await
+                .unwrap()
+                .iter()
+                .any(|player| player.login == "renamed")
+        );
+    }
+
+    #[tokio::test]
+    async fn stories_in_other_languages_compete_on_their_own_boards() {
+        let storage = storage_with_players(&["1"]).await;
+        let french = submission_in(&storage, Language::French, 20, 0).await;
+
+        let (saved, touched) = record_typing_result(&storage, "1", french).await.unwrap();
+
+        let today = Utc::now().date_naive();
+        let board = |language| {
+            board_id_for_scope(
+                LeaderboardScope::Day,
+                today,
+                TestMode::default(),
+                TextKind::Plain,
+                language,
+            )
+        };
+        assert_eq!(saved.language, Language::French);
+        assert_eq!(saved.correct_words, 20);
+        assert!(touched.contains(&board(Language::French)));
+        assert!(!touched.contains(&board(Language::English)));
+
+        // The ticket pins the story, so a French run cannot be filed as English.
+        let mut relabeled = submission_in(&storage, Language::French, 20, 0).await;
+        relabeled.language = Language::English;
+        assert!(
+            record_typing_result(&storage, "1", relabeled)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_text_runs_are_saved_privately() {
+        let storage = storage_with_players(&["1"]).await;
+        let mut custom = submission(&storage, 5, 0).await;
+        let today = Utc::now().date_naive();
+        let story = cached_challenge_for_day(&storage, today, Language::English)
+            .await
+            .unwrap()
+            .story
+            .story;
+        custom.text = TextKind::Custom;
+        custom.custom_text = Some(
+            story
+                .split_whitespace()
+                .take(5)
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+
+        let (saved, touched) = record_typing_result(&storage, "1", custom).await.unwrap();
+
+        assert_eq!(saved.text, TextKind::Custom);
+        assert_eq!(saved.correct_words, 5);
+        assert!(touched.is_empty());
+        assert!(load_run_ghost(&storage, &saved.run_id).await.is_err());
+        assert_eq!(
+            storage.recent_typing_results("1", 20).await.unwrap().len(),
+            1
+        );
+        let profile = storage.get_user("1").await.unwrap().unwrap();
+        assert_eq!((profile.total_runs, profile.best_score), (0, 0));
+        assert_eq!(profile.best_wpm, 0.0);
+
+        let mut missing = submission(&storage, 5, 0).await;
+        missing.text = TextKind::Custom;
+        assert!(record_typing_result(&storage, "1", missing).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ranked_runs_can_be_raced_until_their_player_hides_them() {
+        let storage = storage_with_players(&["1"]).await;
+        let run = submission(&storage, 10, 0).await;
+        let (saved, _) = record_typing_result(&storage, "1", run).await.unwrap();
+
+        let ghost = load_run_ghost(&storage, &saved.run_id).await.unwrap();
+        assert_eq!(ghost.login, "player1");
+        assert_eq!(ghost.score, saved.score);
+        assert_eq!(
+            ghost.challenge_date,
+            challenge_date_string(Utc::now().date_naive())
+        );
+        assert_eq!(ghost.timeline.position_at(u32::MAX).word_index, 10);
+        // A slower run improves none of the boards, so it keeps no ghost.
+        let slower = submission(&storage, 5, 0).await;
+        let (slower, touched) = record_typing_result(&storage, "1", slower).await.unwrap();
+        assert!(touched.is_empty());
+        assert!(load_run_ghost(&storage, &slower.run_id).await.is_err());
+
+        storage.set_hide_ghosts("1", true).await.unwrap();
+        assert!(load_run_ghost(&storage, &saved.run_id).await.is_err());
+        assert!(load_run_ghost(&storage, "run-missing").await.is_err());
+    }
 
     #[test]
     fn consecutive_challenges_have_distinct_story_cutoffs() {
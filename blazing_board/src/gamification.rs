@@ -1,9 +1,19 @@
 use chrono::{Duration, NaiveDate, Utc};
+#[cfg(target_arch = "wasm32")]
+use dioxus::logger::tracing::warn;
 use serde::{Deserialize, Serialize};
 
+use crate::models::{CHARS_PER_WORD, GhostTimeline, KeyboardLayout, Language, TestMode, TextKind};
+
 #[cfg(target_arch = "wasm32")]
 const LOCAL_STATS_KEY: &str = "blazing-board.stats";
 const LOCAL_STATS_VERSION: u8 = 1;
+#[cfg(target_arch = "wasm32")]
+const GHOSTS_KEY: &str = "blazing-board.ghosts";
+/// Best runs kept for ghost racing; the least recently improved drop out first.
+const MAX_GHOSTS: usize = 30;
+/// Kept ghosts store where the run stood every quarter second, not every key.
+const GHOST_STEP_MS: u32 = 250;
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 pub enum Badge {
@@ -52,6 +62,10 @@ pub struct LocalStats {
     pub best_wpm: f64,
     pub best_accuracy: f64,
     pub best_score: i64,
+    /// Lets Backspace on an empty input reopen the previous word.
+    pub allow_previous_word: bool,
+    /// Layout picked on this device; falls back to the profile's when unset.
+    pub keyboard_layout: Option<KeyboardLayout>,
 }
 
 impl Default for LocalStats {
@@ -64,6 +78,8 @@ impl Default for LocalStats {
             best_wpm: 0.0,
             best_accuracy: 0.0,
             best_score: 0,
+            allow_previous_word: false,
+            keyboard_layout: None,
         }
     }
 }
@@ -136,12 +152,12 @@ pub fn update_personal_bests(stats: &mut LocalStats, wpm: f64, accuracy: f64, sc
     is_new_record
 }
 
-pub fn pace_vs_best(correct_words: i64, elapsed_seconds: i64, best_wpm: f64) -> Option<PaceStatus> {
-    if best_wpm <= 0.0 || elapsed_seconds < 3 || correct_words < 0 {
+pub fn pace_vs_best(correct_chars: i64, elapsed_seconds: i64, best_wpm: f64) -> Option<PaceStatus> {
+    if best_wpm <= 0.0 || elapsed_seconds < 3 || correct_chars < 0 {
         return None;
     }
 
-    let current_wpm = correct_words as f64 / (elapsed_seconds as f64 / 60.0);
+    let current_wpm = correct_chars as f64 / CHARS_PER_WORD / (elapsed_seconds as f64 / 60.0);
     let delta = current_wpm - best_wpm;
     if delta > 1.0 {
         Some(PaceStatus::Ahead)
@@ -152,6 +168,64 @@ pub fn pace_vs_best(correct_words: i64, elapsed_seconds: i64, best_wpm: f64) ->
     }
 }
 
+/// The player's best run on one text, raced as a ghost the next time they type it.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PersonalGhost {
+    /// [`ghost_key`] of the text the run typed.
+    pub key: String,
+    pub score: i64,
+    pub wpm: f64,
+    pub timeline: GhostTimeline,
+}
+
+/// Runs only race a ghost that typed the same words: the same challenge day,
+/// story language, text and mode.
+pub fn ghost_key(
+    challenge_date: &str,
+    language: Language,
+    mode: TestMode,
+    text: TextKind,
+) -> String {
+    format!(
+        "{challenge_date}-{}-{}-{}",
+        language.id(),
+        mode.id(),
+        text.id()
+    )
+}
+
+/// Keeps `ghost` when it beats the stored run for its key. Returns whether it did.
+pub fn record_ghost(ghosts: &mut Vec<PersonalGhost>, ghost: PersonalGhost) -> bool {
+    if let Some(index) = ghosts.iter().position(|stored| stored.key == ghost.key) {
+        if ghosts[index].score >= ghost.score {
+            return false;
+        }
+        ghosts.remove(index);
+    }
+    ghosts.push(ghost);
+    if ghosts.len() > MAX_GHOSTS {
+        ghosts.remove(0);
+    }
+    true
+}
+
+pub fn load_ghost(key: &str) -> Option<PersonalGhost> {
+    load_ghosts_from_browser()
+        .into_iter()
+        .find(|ghost| ghost.key == key)
+}
+
+/// Stores `ghost` if it is the best run on its text. Returns whether it was.
+pub fn save_ghost(mut ghost: PersonalGhost) -> bool {
+    ghost.timeline = ghost.timeline.compact(GHOST_STEP_MS);
+    let mut ghosts = load_ghosts_from_browser();
+    let improved = record_ghost(&mut ghosts, ghost);
+    if improved {
+        save_ghosts_to_browser(&ghosts);
+    }
+    improved
+}
+
 pub fn award_badges(stats: &mut LocalStats, accuracy: f64, wpm: f64) -> Option<Badge> {
     let qualifying = [
         Badge::FirstSpark,
@@ -219,12 +293,58 @@ fn save_local_stats_to_browser(stats: &LocalStats) {
 #[cfg(not(target_arch = "wasm32"))]
 fn save_local_stats_to_browser(_stats: &LocalStats) {}
 
+#[cfg(target_arch = "wasm32")]
+fn load_ghosts_from_browser() -> Vec<PersonalGhost> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(GHOSTS_KEY).ok().flatten())
+        .and_then(|serialized| serde_json::from_str(&serialized).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_ghosts_from_browser() -> Vec<PersonalGhost> {
+    Vec::new()
+}
+
+/// Ghosts are ordered oldest first; when local storage is full the oldest
+/// make room for the rest.
+#[cfg(target_arch = "wasm32")]
+fn save_ghosts_to_browser(ghosts: &[PersonalGhost]) {
+    let Some(storage) = web_sys::window()
+        .and_then(|window| window.local_storage().ok())
+        .flatten()
+    else {
+        warn!("Local storage is unavailable, so ghosts are not kept");
+        return;
+    };
+    for dropped in 0..ghosts.len() {
+        let Ok(serialized) = serde_json::to_string(&ghosts[dropped..]) else {
+            warn!("Unable to serialize ghosts");
+            return;
+        };
+        if storage.set_item(GHOSTS_KEY, &serialized).is_ok() {
+            if dropped > 0 {
+                warn!("Local storage is full; dropped the {dropped} oldest ghosts");
+            }
+            return;
+        }
+    }
+    warn!("Local storage is full; the new ghost was not kept");
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_ghosts_to_browser(_ghosts: &[PersonalGhost]) {}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        Badge, LocalStats, PaceStatus, award_badges, complete_daily_challenge, pace_vs_best,
-        record_combo_word, update_personal_bests,
+        Badge, LocalStats, MAX_GHOSTS, PaceStatus, PersonalGhost, award_badges,
+        complete_daily_challenge, pace_vs_best, record_combo_word, record_ghost,
+        update_personal_bests,
     };
+    use crate::models::GhostTimeline;
 
     #[test]
     fn combo_reports_milestones_and_resets_on_a_mistake() {
@@ -291,9 +411,31 @@ mod tests {
 
     #[test]
     fn pace_compares_current_wpm_to_personal_best() {
-        assert_eq!(pace_vs_best(20, 15, 60.0), Some(PaceStatus::Ahead));
-        assert_eq!(pace_vs_best(10, 15, 60.0), Some(PaceStatus::Behind));
-        assert_eq!(pace_vs_best(15, 15, 60.0), Some(PaceStatus::Even));
-        assert_eq!(pace_vs_best(20, 2, 60.0), None);
+        assert_eq!(pace_vs_best(100, 15, 60.0), Some(PaceStatus::Ahead));
+        assert_eq!(pace_vs_best(50, 15, 60.0), Some(PaceStatus::Behind));
+        assert_eq!(pace_vs_best(75, 15, 60.0), Some(PaceStatus::Even));
+        assert_eq!(pace_vs_best(100, 2, 60.0), None);
+    }
+
+    #[test]
+    fn keeps_only_the_best_ghost_per_text() {
+        let ghost = |key: &str, score| PersonalGhost {
+            key: key.to_string(),
+            score,
+            wpm: score as f64,
+            timeline: GhostTimeline::default(),
+        };
+        let mut ghosts = Vec::new();
+
+        assert!(record_ghost(&mut ghosts, ghost("a", 40)));
+        assert!(!record_ghost(&mut ghosts, ghost("a", 40)));
+        assert!(record_ghost(&mut ghosts, ghost("a", 55)));
+        assert_eq!(ghosts, vec![ghost("a", 55)]);
+
+        for index in 0..MAX_GHOSTS {
+            record_ghost(&mut ghosts, ghost(&format!("day-{index}"), 10));
+        }
+        assert_eq!(ghosts.len(), MAX_GHOSTS);
+        assert!(ghosts.iter().all(|stored| stored.key != "a"));
     }
 }
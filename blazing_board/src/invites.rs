@@ -0,0 +1,59 @@
+//! Team invite links: `/?team_invite={invite_code}` on the site's origin.
+
+#[cfg(target_arch = "wasm32")]
+const INVITE_PARAM: &str = "team_invite";
+
+/// The invite code of the link this page was opened from.
+#[cfg(target_arch = "wasm32")]
+pub fn invite_code_from_url() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(INVITE_PARAM)?.strip_prefix('='))
+        .filter(|code| !code.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn invite_code_from_url() -> Option<String> {
+    None
+}
+
+/// Drops the invite from the address bar once it was used or dismissed.
+#[cfg(target_arch = "wasm32")]
+pub fn clear_invite_from_url() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let Ok(path) = window.location().pathname() else {
+        return;
+    };
+    if let Ok(history) = window.history() {
+        let _ = history.replace_state_with_url(&wasm_bindgen::JsValue::NULL, "", Some(&path));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn clear_invite_from_url() {}
+
+/// Puts the team's invite link on the clipboard. Returns `false` when the
+/// browser offers no clipboard.
+#[cfg(target_arch = "wasm32")]
+pub fn copy_invite_link(invite_code: &str) -> bool {
+    let Some(window) = web_sys::window() else {
+        return false;
+    };
+    let Ok(origin) = window.location().origin() else {
+        return false;
+    };
+    let link = format!("{origin}/?{INVITE_PARAM}={invite_code}");
+    // The write finishes in the background; a refused write is not reported.
+    let _ = window.navigator().clipboard().write_text(&link);
+    true
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn copy_invite_link(_invite_code: &str) -> bool {
+    false
+}
@@ -3,11 +3,21 @@ mod auth;
 mod backend;
 mod components;
 mod gamification;
+mod invites;
 mod models;
+mod race_client;
+#[cfg(feature = "server")]
+mod races;
+#[cfg(feature = "server")]
+mod storage;
+#[cfg(feature = "server")]
+mod tickets;
 
 use async_std::task::sleep;
 use backend::{
-    get_leaderboard, get_private_profile, get_recent_leaderboard_days, get_story, save_typing_result,
+    create_team, follow_player, get_leaderboard, get_private_profile, get_recent_leaderboard_days,
+    get_run_ghost, get_snippet, get_story, join_team, save_typing_result, set_hide_ghosts,
+    set_keyboard_layout, start_run, unfollow_player,
 };
 use components::{
     avatar::{AvatarImageSize, ImageAvatar},
@@ -15,18 +25,27 @@ use components::{
 };
 use dioxus::prelude::*;
 use gamification::{
-    Badge, LocalStats, PaceStatus, award_badges, complete_daily_challenge, current_challenge_date,
-    load_local_stats, pace_vs_best, record_combo_word, save_local_stats, update_personal_bests,
+    Badge, LocalStats, PaceStatus, PersonalGhost, award_badges, complete_daily_challenge,
+    current_challenge_date, ghost_key, load_ghost, load_local_stats, pace_vs_best,
+    record_combo_word, save_ghost, save_local_stats, update_personal_bests,
 };
+use invites::{clear_invite_from_url, copy_invite_link, invite_code_from_url};
 use jiff::Timestamp;
 use models::{
-    Leaderboard, LeaderboardScope, PrivateProfile, Story, TypingSubmission, calculate_typing_metrics,
+    CharMark, DailyChallenge, ErrorTracker, FollowedPlayer, GhostTimeline, KeyStats,
+    KeyboardLayout, KeystrokeLog, Language, Leaderboard, LeaderboardEntry, LeaderboardScope,
+    MAX_CUSTOM_TEXT_CHARS, MAX_TEAM_NAME_CHARS, MIN_BIGRAM_ATTEMPTS, PrivateProfile,
+    RaceClientMessage, RaceServerMessage, RaceSpec, RacerProgress, RunGhost, RunTicket, Story,
+    Team, TestMode, TextKind, TextToken, TimelinePoint, TypingSubmission, VerifiedRun,
+    calculate_typing_metrics, chunk_tokens, drill_text, layout_tokens, mark_characters,
+    prepare_custom_text, prepare_github_login, prepare_team_name, run_seconds,
 };
+use race_client::{JoinedRace, RaceConnection, RaceEvent};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
 const DEFAULT_TITLE: &str = "";
-const TEST_DURATION_SECONDS: i64 = 60;
+const WORDS_PER_CHUNK: usize = 15;
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 const APP_CSS: Asset = asset!("/assets/main.css");
@@ -42,7 +61,8 @@ fn main() {
         Ok(dioxus::server::router(App)
             .route("/auth/github", get(auth::github_login))
             .route("/auth/github/callback", get(auth::github_callback))
-            .route("/auth/logout", post(auth::github_logout)))
+            .route("/auth/logout", post(auth::github_logout))
+            .route("/races/ws", get(races::race_socket)))
     });
 
     #[cfg(not(feature = "server"))]
@@ -84,12 +104,28 @@ pub fn TypingWords() -> Element {
     let mut current_word_in_chunk_index = use_signal(|| 0);
     let mut current_text = use_signal(String::new);
     let mut user_words = use_signal(Vec::<String>::new);
+    // Milliseconds since the epoch, from the first keystroke.
     let mut started_at = use_signal(|| None::<i64>);
+    let mut keystrokes = use_signal(KeystrokeLog::default);
+    let mut pending_key = use_signal(String::new);
     let mut run_id = use_signal(String::new);
+    let mut run_ticket = use_signal(|| None::<RunTicket>);
     let mut correct_words = use_signal(|| 0_i64);
     let mut wrong_words = use_signal(|| 0_i64);
+    let mut correct_chars = use_signal(|| 0_i64);
+    let mut typed_chars = use_signal(|| 0_i64);
     let mut duration_seconds = use_signal(|| 0_i64);
-    let mut timer_value = use_signal(|| TEST_DURATION_SECONDS);
+    let mut timeline = use_signal(Vec::<TimelinePoint>::new);
+    let mut error_tracker = use_signal(ErrorTracker::default);
+    let mut test_mode = use_signal(TestMode::default);
+    let mut text_kind = use_signal(TextKind::default);
+    let mut story_language = use_signal(Language::default);
+    // The passage in play for custom text and drills, already through `prepare_custom_text`.
+    let mut custom_text = use_signal(String::new);
+    let mut custom_draft = use_signal(String::new);
+    let mut custom_editor_open = use_signal(|| false);
+    let mut custom_error = use_signal(String::new);
+    let mut timer_value = use_signal(|| timer_start(TestMode::default()));
     let mut running = use_signal(|| false);
     let mut finished = use_signal(|| false);
     let mut submitted_run = use_signal(|| None::<String>);
@@ -102,6 +138,18 @@ pub fn TypingWords() -> Element {
     let mut processed_gamification_run = use_signal(|| None::<String>);
     let mut new_badge = use_signal(|| None::<Badge>);
     let mut new_personal_best = use_signal(|| false);
+    // The run raced as a ghost, and how far into the run it is replayed.
+    let mut ghost = use_signal(|| None::<GhostTimeline>);
+    let mut ghost_ms = use_signal(|| 0_u32);
+    // A leaderboard run picked to race instead of the player's own best.
+    let mut rival = use_signal(|| None::<RunGhost>);
+    // The live race room this player sits in, and when its countdown ends.
+    let mut race = use_signal(|| None::<JoinedRace>);
+    let mut race_socket = use_signal(|| None::<RaceConnection>);
+    let mut race_starts_at = use_signal(|| None::<i64>);
+    let mut race_countdown = use_signal(|| None::<i64>);
+    let mut race_code_draft = use_signal(String::new);
+    let mut race_message = use_signal(String::new);
     let mut text_input_ref: Signal<Option<Rc<MountedData>>> = use_signal(|| None);
     let today = use_signal(current_challenge_date);
     let mut selected_challenge_day = use_signal(current_challenge_date);
@@ -116,26 +164,134 @@ pub fn TypingWords() -> Element {
             .await
             .unwrap_or_else(|_| vec![current_challenge_date()])
     });
-    let re_story = use_resource(move || {
+    let re_challenge = use_resource(move || {
         let day = selected_challenge_day();
+        let language = story_language();
         async move {
-            get_story(Some(day)).await.unwrap_or(Story {
-                ..Default::default()
-            })
+            let story = get_story(Some(day.clone()), Some(language.id().to_string()))
+                .await
+                .unwrap_or_else(|_| Story::fallback(language));
+            let snippet = get_snippet(Some(day)).await.unwrap_or_default();
+            DailyChallenge { story, snippet }
         }
     });
     let profile_resource =
         use_resource(|| async move { get_private_profile().await.unwrap_or(None) });
     let mut leaderboard_scope = use_signal(|| LeaderboardScope::Day);
+    let mut leaderboard_layout = use_signal(|| None::<KeyboardLayout>);
+    // `None` ranks everyone; otherwise the ID of the team whose boards are shown.
+    let mut leaderboard_team = use_signal(|| None::<String>);
+    // Ranks only the signed-in player and the players they follow.
+    let mut leaderboard_friends = use_signal(|| false);
+    let mut follow_message = use_signal(String::new);
+    // The invite code of the link the page was opened from.
+    let mut team_invite = use_signal(|| None::<String>);
+    let mut team_message = use_signal(String::new);
+    use_effect(move || team_invite.set(invite_code_from_url()));
+    // This device's choice first, then the one saved on the profile.
+    let keyboard_layout = move || {
+        local_stats
+            .read()
+            .keyboard_layout
+            .or_else(|| {
+                profile_resource()
+                    .flatten()
+                    .and_then(|profile| profile.user.keyboard_layout)
+            })
+            .unwrap_or_default()
+    };
     let leaderboard_resource = use_resource(move || {
         let scope = leaderboard_scope();
+        let layout = leaderboard_layout();
+        let team = leaderboard_team();
+        let friends = leaderboard_friends();
         let day = selected_challenge_day();
+        let mode = test_mode();
+        let text = text_kind();
+        let language = story_language();
         async move {
-            get_leaderboard(scope.as_str().to_string(), Some(day))
-                .await
-                .ok()
+            if !text.is_ranked() {
+                return None;
+            }
+            get_leaderboard(
+                scope.as_str().to_string(),
+                Some(day),
+                Some(mode.id()),
+                Some(text.id().to_string()),
+                Some(language.id().to_string()),
+                // Team boards rank every layout together.
+                layout
+                    .filter(|_| team.is_none())
+                    .map(|layout| layout.id().to_string()),
+                team,
+                Some(friends),
+                None,
+                None,
+                None,
+            )
+            .await
+            .ok()
         }
     });
+    // The pages loaded with "Load more" after the resource's first page.
+    let mut leaderboard_more = use_signal(|| None::<Leaderboard>);
+    let mut loading_more = use_signal(|| false);
+    // Every reload of the first page drops the pages loaded after it.
+    use_effect(move || {
+        let _ = leaderboard_resource.read();
+        leaderboard_more.set(None);
+    });
+    let shown_leaderboard = move || {
+        let mut board = leaderboard_resource().flatten()?;
+        if let Some(more) = leaderboard_more().filter(|more| more.board_id == board.board_id) {
+            board.entries.extend(more.entries);
+            board.next_cursor = more.next_cursor;
+        }
+        Some(board)
+    };
+    let load_more_leaderboard = move |_| {
+        let Some(board) = shown_leaderboard() else {
+            return;
+        };
+        let Some(cursor) = board.next_cursor.clone() else {
+            return;
+        };
+        if loading_more() {
+            return;
+        }
+        loading_more.set(true);
+        spawn(async move {
+            let page = get_leaderboard(
+                board.scope.as_str().to_string(),
+                board.challenge_date,
+                Some(board.mode.id()),
+                Some(board.text.id().to_string()),
+                Some(board.language.id().to_string()),
+                board.keyboard_layout.map(|layout| layout.id().to_string()),
+                board.team_id,
+                Some(board.friends),
+                Some(cursor.sort_key),
+                Some(cursor.github_id.clone()),
+                None,
+            )
+            .await;
+            // The board may have reloaded or moved on while the page loaded;
+            // only the page following what is shown now gets appended.
+            let still_next = shown_leaderboard().is_some_and(|shown| {
+                shown.board_id == board.board_id && shown.next_cursor.as_ref() == Some(&cursor)
+            });
+            if let Some(page) = page.ok().filter(|_| still_next) {
+                leaderboard_more.with_mut(|more| match more {
+                    Some(more) if more.board_id == page.board_id => {
+                        more.entries.extend(page.entries);
+                        more.next_cursor = page.next_cursor;
+                    }
+                    _ => *more = Some(page),
+                });
+            }
+            loading_more.set(false);
+        });
+    };
 
     let mut reset_run = move || {
         current_chunk_index.set(0);
@@ -143,11 +299,18 @@ pub fn TypingWords() -> Element {
         current_text.set(String::new());
         user_words.set(Vec::new());
         started_at.set(None);
+        keystrokes.set(KeystrokeLog::default());
+        pending_key.set(String::new());
         run_id.set(String::new());
+        run_ticket.set(None);
         correct_words.set(0);
+        correct_chars.set(0);
+        typed_chars.set(0);
         wrong_words.set(0);
         duration_seconds.set(0);
-        timer_value.set(TEST_DURATION_SECONDS);
+        timeline.set(Vec::new());
+        error_tracker.set(ErrorTracker::default());
+        timer_value.set(timer_start(test_mode()));
         running.set(false);
         finished.set(false);
         submitted_run.set(None);
@@ -158,31 +321,230 @@ pub fn TypingWords() -> Element {
         processed_gamification_run.set(None);
         new_badge.set(None);
         new_personal_best.set(false);
+        ghost_ms.set(0);
+        // A rival only races the story and mode its run typed. Peeked so
+        // dropping it does not rerun the reset.
+        let rival_timeline = rival
+            .peek()
+            .as_ref()
+            .filter(|rival| {
+                rival.challenge_date == selected_challenge_day()
+                    && rival.mode == test_mode()
+                    && rival.text == text_kind()
+                    && rival.language == story_language()
+            })
+            .map(|rival| rival.timeline.clone());
+        if rival_timeline.is_none() {
+            rival.set(None);
+        }
+        ghost.set(if rival_timeline.is_some() {
+            rival_timeline
+        } else if text_kind().is_ranked() {
+            load_ghost(&ghost_key(
+                &selected_challenge_day(),
+                story_language(),
+                test_mode(),
+                text_kind(),
+            ))
+            .map(|personal| personal.timeline)
+        } else {
+            None
+        });
+    };
+
+    // Starts the clock at `now_ms` and registers the run with the server.
+    let mut begin_run = move |now_ms: i64| {
+        let local_run_id = format!("run-{now_ms}");
+        started_at.set(Some(now_ms));
+        run_id.set(local_run_id.clone());
+        running.set(true);
+        spawn(async move {
+            let ticket = start_run(selected_challenge_day(), story_language()).await;
+            if run_id() != local_run_id {
+                return;
+            }
+            match ticket {
+                Ok(ticket) => run_ticket.set(Some(ticket)),
+                Err(_) => save_message
+                    .set("This run could not be registered, so it will not be saved.".to_string()),
+            }
+        });
+    };
+
+    // The client's own tally of the run; the server recomputes it from the log.
+    let counted_run = move || VerifiedRun {
+        correct_words: correct_words(),
+        wrong_words: wrong_words(),
+        correct_chars: correct_chars(),
+        typed_chars: typed_chars(),
+        duration_seconds: duration_seconds().max(1),
+        chars_per_second: keystrokes.read().chars_per_second(duration_seconds()),
+        timeline: timeline(),
+        keystrokes: error_tracker.read().keystrokes,
+        corrected_errors: error_tracker.read().corrected_errors,
+        uncorrected_errors: error_tracker.read().uncorrected_errors,
+    };
+    // Records where the run stands at the end of `second`.
+    let mut sample_timeline = move |second: i64| {
+        let sampled_errors = timeline
+            .read()
+            .iter()
+            .map(|point| point.errors)
+            .sum::<u32>();
+        let chars_this_second = keystrokes
+            .read()
+            .chars_per_second(second)
+            .last()
+            .copied()
+            .unwrap_or(0);
+        timeline.write().push(TimelinePoint::new(
+            second as u32,
+            correct_chars(),
+            chars_this_second,
+            (wrong_words() as u32).saturating_sub(sampled_errors),
+        ));
+    };
+
+    let send_race = move |message: RaceClientMessage| {
+        if let Some(socket) = race_socket.read().as_ref() {
+            socket.send(&message);
+        }
+    };
+    // Tells the room how far this run got after each committed word.
+    let send_race_progress = move || {
+        if race.peek().is_none() || !running() {
+            return;
+        }
+        send_race(RaceClientMessage::Progress {
+            words_done: (correct_words() + wrong_words()) as u32,
+            wpm: calculate_typing_metrics(&counted_run()).map_or(0.0, |run| run.wpm),
+        });
+    };
+    let on_race_event = move |event: RaceEvent| match event {
+        RaceEvent::Message(RaceServerMessage::Joined {
+            code,
+            racer_id,
+            host,
+            spec,
+        }) => {
+            selected_challenge_day.set(spec.challenge_date);
+            test_mode.set(spec.mode);
+            text_kind.set(spec.text);
+            story_language.set(spec.language);
+            rival.set(None);
+            race_message.set(String::new());
+            race.set(Some(JoinedRace {
+                code,
+                racer_id,
+                host,
+                started: false,
+                racers: Vec::new(),
+            }));
+            reset_run();
+        }
+        RaceEvent::Message(RaceServerMessage::Racers { racers }) => {
+            if let Some(joined) = race.write().as_mut() {
+                joined.racers = racers;
+            }
+        }
+        RaceEvent::Message(RaceServerMessage::Countdown { starts_in_ms }) => {
+            if let Some(joined) = race.write().as_mut() {
+                joined.started = true;
+            }
+            reset_run();
+            race_starts_at.set(Some(
+                get_timestamp_milliseconds_now_wasm() + i64::from(starts_in_ms),
+            ));
+        }
+        RaceEvent::Message(RaceServerMessage::Error { message }) => race_message.set(message),
+        RaceEvent::Closed => {
+            race.set(None);
+            race_starts_at.set(None);
+            race_countdown.set(None);
+            race_message.set("The race connection closed.".to_string());
+            // The socket's own handler is running, so it is dropped afterwards.
+            spawn(async move {
+                race_socket.set(None);
+            });
+        }
+    };
+    // Creating or joining a room reuses the socket a failed attempt left open.
+    let mut enter_race = move |message: RaceClientMessage| {
+        if race_socket.read().is_some() {
+            send_race(message);
+        } else {
+            match RaceConnection::open(message, on_race_event) {
+                Some(socket) => race_socket.set(Some(socket)),
+                None => race_message.set("Races could not connect.".to_string()),
+            }
+        }
+    };
+    let mut leave_race = move || {
+        race_socket.set(None);
+        race.set(None);
+        race_starts_at.set(None);
+        race_countdown.set(None);
+        race_message.set(String::new());
+        reset_run();
     };
 
     use_effect(move || {
         let _day = selected_challenge_day();
+        let _mode = test_mode();
+        let _text = text_kind();
+        let _language = story_language();
+        let _custom = custom_text();
         reset_run();
     });
 
-    let story = re_story().unwrap_or(Story {
-        ..Default::default()
-    });
+    let challenge = re_challenge().unwrap_or_else(|| DailyChallenge::fallback(story_language()));
+    let story = &challenge.story;
     let profile = profile_resource().unwrap_or(None);
-    let last_title = story
-        .title
-        .clone()
-        .unwrap_or_else(|| DEFAULT_TITLE.to_string());
-    let sentence_to_write_words = story
-        .story
-        .split_whitespace()
-        .map(|w| w.to_string())
-        .collect::<Vec<String>>();
-
-    let sentence_to_write_chunks = sentence_to_write_words
-        .chunks(15)
-        .map(|chunk| chunk.to_vec())
-        .collect::<Vec<Vec<String>>>();
+    // Drills need key stats from at least one saved run.
+    let drill_stats = profile
+        .as_ref()
+        .map(|profile| profile.key_stats.clone())
+        .filter(|stats| !stats.keys.is_empty());
+    let code_layout = text_kind() == TextKind::Code;
+    let snippet = &challenge.snippet;
+    let (last_title, sources) = if text_kind().is_submitted() {
+        (text_kind().label().to_string(), Vec::new())
+    } else if code_layout {
+        (
+            format!(
+                "{} · {}",
+                snippet.title.as_deref().unwrap_or(DEFAULT_TITLE),
+                snippet.language
+            ),
+            snippet.sources.clone(),
+        )
+    } else {
+        (
+            story
+                .title
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TITLE.to_string()),
+            story.sources.clone(),
+        )
+    };
+    let mut sentence_to_write_words = if text_kind().is_submitted() {
+        layout_tokens(&custom_text())
+    } else {
+        layout_tokens(challenge.text(text_kind()))
+    };
+    sentence_to_write_words.truncate(test_mode().target_words(sentence_to_write_words.len()));
+
+    // Code keeps whole lines together so its layout survives chunking.
+    let sentence_to_write_chunks =
+        chunk_tokens(&sentence_to_write_words, WORDS_PER_CHUNK, code_layout);
+    let chunk_offsets = sentence_to_write_chunks
+        .iter()
+        .scan(0, |offset, chunk| {
+            let start = *offset;
+            *offset += chunk.len();
+            Some(start)
+        })
+        .collect::<Vec<usize>>();
 
     let nb_chunks_to_write = sentence_to_write_chunks.len();
 
@@ -190,14 +552,59 @@ pub fn TypingWords() -> Element {
         loop {
             sleep(std::time::Duration::from_secs(1)).await;
             if running() {
-                if timer_value() <= 1 {
-                    timer_value.set(0);
-                    duration_seconds.set(TEST_DURATION_SECONDS);
+                let mode = test_mode();
+                let limit = mode.time_limit_seconds();
+                let out_of_time = if mode.is_timed() {
+                    timer_value() <= 1
+                } else {
+                    timer_value() + 1 >= limit
+                };
+                if out_of_time {
+                    timer_value.set(if mode.is_timed() { 0 } else { limit });
+                    duration_seconds.set(limit);
                     running.set(false);
                     finished.set(true);
-                } else {
+                } else if mode.is_timed() {
                     timer_value.set(timer_value() - 1);
+                } else {
+                    timer_value.set(timer_value() + 1);
                 }
+                let sampled = timeline.read().len() as i64;
+                sample_timeline(sampled + 1);
+            }
+        }
+    });
+    // Moves the ghost more smoothly than the one-second timer would.
+    let _ = use_coroutine(move |_: UnboundedReceiver<i32>| async move {
+        loop {
+            sleep(std::time::Duration::from_millis(100)).await;
+            if !running() || ghost.read().is_none() {
+                continue;
+            }
+            if let Some(start) = started_at() {
+                let now_ms = get_timestamp_milliseconds_now_wasm();
+                ghost_ms.set((now_ms - start).clamp(0, u32::MAX as i64) as u32);
+            }
+        }
+    });
+
+    // Starts a race's run for every racer when its countdown runs out.
+    let _ = use_coroutine(move |_: UnboundedReceiver<i32>| async move {
+        loop {
+            sleep(std::time::Duration::from_millis(100)).await;
+            let Some(starts_at) = race_starts_at() else {
+                continue;
+            };
+            let now_ms = get_timestamp_milliseconds_now_wasm();
+            if now_ms < starts_at {
+                race_countdown.set(Some((starts_at - now_ms + 999) / 1_000));
+                continue;
+            }
+            race_starts_at.set(None);
+            race_countdown.set(None);
+            begin_run(starts_at);
+            if let Some(input) = text_input_ref() {
+                let _ = input.set_focus(true).await;
             }
         }
     });
@@ -210,16 +617,25 @@ pub fn TypingWords() -> Element {
             && !current_run_id.is_empty()
             && submitted_run().as_deref() != Some(current_run_id.as_str());
 
-        if should_save {
-            submitted_run.set(Some(current_run_id.clone()));
+        if let (true, Some(ticket)) = (should_save, run_ticket()) {
+            submitted_run.set(Some(current_run_id));
             save_message.set("Saving result…".to_string());
             let submission = TypingSubmission {
-                run_id: current_run_id,
+                run_id: ticket.run_id,
                 challenge_date: selected_challenge_day(),
-                story_when: story.when,
-                correct_words: correct_words(),
-                wrong_words: wrong_words(),
-                duration_seconds: duration_seconds(),
+                story_when: challenge.story.when,
+                mode: test_mode(),
+                text: text_kind(),
+                language: story_language(),
+                keyboard_layout: keyboard_layout(),
+                ticket: ticket.token,
+                custom_text: if text_kind().is_submitted() {
+                    Some(custom_text())
+                } else {
+                    None
+                },
+                keystrokes: keystrokes(),
+                race_code: race.peek().as_ref().map(|joined| joined.code.clone()),
             };
             let mut profile_resource = profile_resource;
             let mut leaderboard_resource = leaderboard_resource;
@@ -251,9 +667,17 @@ pub fn TypingWords() -> Element {
         }
 
         processed_gamification_run.set(Some(current_run_id));
-        let Ok(run_metrics) =
-            calculate_typing_metrics(correct_words(), wrong_words(), duration_seconds().max(1))
-        else {
+        if race.peek().is_some() {
+            send_race(RaceClientMessage::Finish {
+                words_done: (correct_words() + wrong_words()) as u32,
+                wpm: calculate_typing_metrics(&counted_run()).map_or(0.0, |run| run.wpm),
+            });
+        }
+        // Any passage can be pasted, so custom runs earn no streak, bests or badges.
+        if !text_kind().is_ranked() {
+            return;
+        }
+        let Ok(run_metrics) = calculate_typing_metrics(&counted_run()) else {
             return;
         };
         let mut updated_stats = local_stats();
@@ -273,6 +697,17 @@ pub fn TypingWords() -> Element {
         ));
         save_local_stats(&updated_stats);
         local_stats.set(updated_stats);
+        save_ghost(PersonalGhost {
+            key: ghost_key(
+                &selected_challenge_day(),
+                story_language(),
+                test_mode(),
+                text_kind(),
+            ),
+            score: run_metrics.score,
+            wpm: run_metrics.wpm,
+            timeline: keystrokes.read().ghost_timeline(),
+        });
     });
 
     let current_chunk = {
@@ -292,12 +727,14 @@ pub fn TypingWords() -> Element {
         }
     };
 
-    let metrics =
-        calculate_typing_metrics(correct_words(), wrong_words(), duration_seconds().max(1)).ok();
+    let metrics = calculate_typing_metrics(&counted_run()).ok();
     let accuracy_percent = metrics
         .map(|current| current.accuracy * 100.0)
         .unwrap_or(0.0);
     let wpm = metrics.map(|current| current.wpm).unwrap_or(0.0);
+    let raw_wpm = metrics.map(|current| current.raw_wpm).unwrap_or(0.0);
+    let cpm = metrics.map(|current| current.cpm).unwrap_or(0.0);
+    let consistency = metrics.map(|current| current.consistency).unwrap_or(0.0);
     let score = metrics.map(|current| current.score).unwrap_or(0);
     let stats = local_stats();
     let playing_today = selected_challenge_day() == today();
@@ -311,16 +748,25 @@ pub fn TypingWords() -> Element {
         }
     } else {
         "Past challenge"
-    };    let elapsed_seconds = if running() || finished() {
-        (TEST_DURATION_SECONDS - timer_value()).clamp(0, TEST_DURATION_SECONDS)
-    } else {
+    };
+    let elapsed_seconds = if !(running() || finished()) {
         0
+    } else if test_mode().is_timed() {
+        let limit = test_mode().time_limit_seconds();
+        (limit - timer_value()).clamp(0, limit)
+    } else {
+        timer_value()
     };
     let pace = if running() {
-        pace_vs_best(correct_words(), elapsed_seconds, stats.best_wpm)
+        pace_vs_best(correct_chars(), elapsed_seconds, stats.best_wpm)
     } else {
         None
     };
+    let ghost_position = ghost
+        .read()
+        .as_ref()
+        .filter(|_| running() || finished())
+        .map(|timeline| timeline.position_at(ghost_ms()));
     let combo_class = if combo_milestone().is_some() {
         "combo-display combo-milestone"
     } else if combo() > 0 {
@@ -328,7 +774,7 @@ pub fn TypingWords() -> Element {
     } else {
         "combo-display"
     };
-    let timer_class = if running() && timer_value() <= 5 {
+    let timer_class = if running() && test_mode().is_timed() && timer_value() <= 5 {
         "timer-urgent"
     } else {
         ""
@@ -339,9 +785,226 @@ pub fn TypingWords() -> Element {
         ""
     };
 
+    let current_chunk_offset = chunk_offsets
+        .get(current_chunk_index())
+        .copied()
+        .unwrap_or(0);
+    // Milliseconds since the first keystroke, as stored in the keystroke log.
+    let elapsed_ms = move |now_ms: i64| {
+        started_at()
+            .map(|start| (now_ms - start).clamp(0, u32::MAX as i64) as u32)
+            .unwrap_or(0)
+    };
+    // Applies a new value of the typing field; trailing whitespace commits the word.
+    let mut type_input = move |data: String, current_chunk: &[TextToken]| {
+        let now_ms = get_timestamp_milliseconds_now_wasm();
+        if started_at().is_none() {
+            begin_run(now_ms);
+        }
+        let story_word_index = current_chunk_offset + current_word_in_chunk_index();
+        let logged = keystrokes.read().events.len();
+        keystrokes.write().record(
+            elapsed_ms(now_ms),
+            pending_key.replace(String::new()),
+            story_word_index as u32,
+            &current_text(),
+            &data,
+        );
+        if let Some(event) = keystrokes.read().events.get(logged) {
+            let expected = current_chunk
+                .get(current_word_in_chunk_index())
+                .map_or("", |token| token.word.as_str());
+            error_tracker.write().apply(event, expected);
+        }
+        if data.ends_with(char::is_whitespace) {
+            let typed_word = data.trim().to_string();
+            let mut new_words = user_words().to_vec();
+            new_words.push(typed_word.clone());
+            user_words.set(new_words);
+
+            let word_index = current_word_in_chunk_index();
+            let is_correct = current_chunk
+                .get(word_index)
+                .is_some_and(|expected| expected.word == typed_word);
+            // Counted the way the server replays the log: the word plus its separator.
+            let committed_chars = typed_word.chars().count() as i64 + 1;
+            if is_correct {
+                correct_words.set(correct_words() + 1);
+                correct_chars.set(correct_chars() + committed_chars);
+            } else {
+                wrong_words.set(wrong_words() + 1);
+            }
+            typed_chars.set(typed_chars() + committed_chars);
+            let combo_progress = record_combo_word(combo(), max_combo(), is_correct);
+            combo.set(combo_progress.current);
+            max_combo.set(combo_progress.best);
+            combo_milestone.set(combo_progress.milestone);
+            send_race_progress();
+
+            let next_word_index = word_index + 1;
+            if next_word_index >= current_chunk.len() {
+                let next_chunk_index = current_chunk_index() + 1;
+                if next_chunk_index >= nb_chunks_to_write {
+                    current_word_in_chunk_index.set(next_word_index);
+                    let run_seconds = started_at()
+                        .map(|start| run_seconds(now_ms - start))
+                        .unwrap_or(1);
+                    duration_seconds.set(run_seconds);
+                    // The last, partial second only needs a point if no tick sampled it.
+                    if (timeline.read().len() as i64) < run_seconds {
+                        sample_timeline(run_seconds);
+                    }
+                    running.set(false);
+                    finished.set(true);
+                } else {
+                    current_word_in_chunk_index.set(0);
+                    current_chunk_index.set(next_chunk_index);
+                    user_words.set(vec![]);
+                }
+            } else {
+                current_word_in_chunk_index.set(next_word_index);
+            }
+            current_text.set(String::new());
+        } else {
+            current_text.set(data);
+        }
+    };
+    // Reopens the last word committed in this chunk so it can be corrected,
+    // undoing what committing it counted.
+    let mut go_back = move |current_chunk: &[TextToken]| {
+        let Some(word_index) = current_word_in_chunk_index().checked_sub(1) else {
+            return;
+        };
+        let Some(previous_word) = user_words().get(word_index).cloned() else {
+            return;
+        };
+        let expected = current_chunk
+            .get(word_index)
+            .map_or("", |token| token.word.as_str());
+        let logged = keystrokes.read().events.len();
+        keystrokes.write().record(
+            elapsed_ms(get_timestamp_milliseconds_now_wasm()),
+            pending_key.replace(String::new()),
+            (current_chunk_offset + word_index) as u32,
+            &format!("{previous_word} "),
+            &previous_word,
+        );
+        if let Some(event) = keystrokes.read().events.get(logged) {
+            error_tracker.write().apply(event, expected);
+        }
+
+        let committed_chars = previous_word.chars().count() as i64 + 1;
+        if previous_word == expected {
+            correct_words.set(correct_words() - 1);
+            correct_chars.set(correct_chars() - committed_chars);
+        } else {
+            wrong_words.set(wrong_words() - 1);
+        }
+        typed_chars.set(typed_chars() - committed_chars);
+        user_words.write().pop();
+        current_word_in_chunk_index.set(word_index);
+        current_text.set(previous_word);
+        send_race_progress();
+    };
+    let race_waiting = race.read().is_some() && started_at().is_none();
+    let race_place = race
+        .read()
+        .as_ref()
+        .and_then(|joined| joined.place().map(|place| (place, joined.racers.len())));
+    let key_chunk = current_chunk.clone();
+    let input_chunk = current_chunk.clone();
+    // The caret sits after the typed characters of the current word.
+    let caret_position = current_text().chars().count();
+    let current_marks_len = current_chunk
+        .get(current_word_in_chunk_index())
+        .map_or(0, |token| token.word.chars().count())
+        .max(caret_position);
+    // Words the player is ahead of their ghost, negative when behind.
+    let ghost_gap = ghost_position.map(|position| {
+        (current_chunk_offset + current_word_in_chunk_index()) as i64
+            - i64::from(position.word_index)
+    });
+    // The ghost's word within the shown chunks, and its caret within that word.
+    let ghost_word = |chunk_offset: usize, chunk_len: usize| {
+        ghost_position.and_then(|position| {
+            (position.word_index as usize)
+                .checked_sub(chunk_offset)
+                .filter(|&index| index < chunk_len)
+        })
+    };
+    let ghost_in_current = ghost_word(current_chunk_offset, current_chunk.len());
+    let ghost_in_next = ghost_word(current_chunk_offset + current_chunk.len(), next_chunk.len());
+    let ghost_chars = ghost_position.map_or(0, |position| position.typed_chars);
+    let ghost_name = rival
+        .read()
+        .as_ref()
+        .map_or("your best run".to_string(), |rival| {
+            format!("@{}", rival.login)
+        });
+    // Loads a leaderboard run and sets up its story and mode to race it.
+    let race_run = move |run_id: String| {
+        spawn(async move {
+            match get_run_ghost(run_id).await {
+                Ok(run_ghost) => {
+                    selected_challenge_day.set(run_ghost.challenge_date.clone());
+                    test_mode.set(run_ghost.mode);
+                    text_kind.set(run_ghost.text);
+                    story_language.set(run_ghost.language);
+                    rival.set(Some(run_ghost));
+                    reset_run();
+                }
+                Err(_) => save_message.set("This run cannot be raced.".to_string()),
+            }
+        });
+    };
+
     rsx! {
         div { id: "TypingWords",
             ProfileBar { profile: profile.clone() }
+            if let Some(invite_code) = team_invite() {
+                section { class: "team-invite", aria_label: "Team invite",
+                    span { "You were invited to join a team." }
+                    if profile.is_some() {
+                        Button {
+                            size: ButtonSize::Sm,
+                            onclick: move |_| {
+                                let invite_code = invite_code.clone();
+                                let mut profile_resource = profile_resource;
+                                spawn(async move {
+                                    match join_team(invite_code).await {
+                                        Ok(team) => {
+                                            clear_invite_from_url();
+                                            team_invite.set(None);
+                                            team_message.set(format!("You joined {}.", team.name));
+                                            if leaderboard_scope() == LeaderboardScope::Global {
+                                                leaderboard_scope.set(LeaderboardScope::Week);
+                                            }
+                                            leaderboard_team.set(Some(team.id));
+                                            profile_resource.restart();
+                                        }
+                                        Err(_) => team_message.set("This invite could not be used.".to_string()),
+                                    }
+                                });
+                            },
+                            "Join team"
+                        }
+                    } else {
+                        span { "Sign in with GitHub to join it." }
+                    }
+                    Button {
+                        size: ButtonSize::Sm,
+                        variant: ButtonVariant::Ghost,
+                        onclick: move |_| {
+                            clear_invite_from_url();
+                            team_invite.set(None);
+                        },
+                        "Dismiss"
+                    }
+                }
+            }
+            if !team_message().is_empty() {
+                p { class: "save-message", role: "status", "{team_message}" }
+            }
             section { class: "daily-status", aria_label: "Daily challenge status",
                 div {
                     span { class: "daily-label", "Challenge day" }
@@ -379,6 +1042,276 @@ pub fn TypingWords() -> Element {
                     }
                 }
             }
+            // A race fixes the story and mode for everyone in the room.
+            if !running() && race().is_none() {
+                div { class: "mode-picker", role: "group", aria_label: "Test mode",
+                    for mode in TestMode::all() {
+                        button {
+                            key: "{mode.id()}",
+                            class: if mode == test_mode() { "mode-option active" } else { "mode-option" },
+                            r#type: "button",
+                            aria_pressed: mode == test_mode(),
+                            onclick: move |_| {
+                                test_mode.set(mode);
+                                if let Some(input) = text_input_ref() {
+                                    spawn(async move {
+                                        let _ = input.set_focus(true).await;
+                                    });
+                                }
+                            },
+                            "{mode.label()}"
+                        }
+                    }
+                }
+                div { class: "mode-picker", role: "group", aria_label: "Text",
+                    for kind in TextKind::all() {
+                        button {
+                            key: "{kind.id()}",
+                            class: if kind == text_kind() { "mode-option active" } else { "mode-option" },
+                            r#type: "button",
+                            aria_pressed: kind == text_kind(),
+                            onclick: move |_| {
+                                text_kind.set(kind);
+                                if let Some(input) = text_input_ref() {
+                                    spawn(async move {
+                                        let _ = input.set_focus(true).await;
+                                    });
+                                }
+                            },
+                            "{kind.label()}"
+                        }
+                    }
+                    button {
+                        class: if text_kind() == TextKind::Custom { "mode-option active" } else { "mode-option" },
+                        r#type: "button",
+                        aria_pressed: text_kind() == TextKind::Custom,
+                        aria_expanded: custom_editor_open(),
+                        onclick: move |_| custom_editor_open.set(!custom_editor_open()),
+                        "{TextKind::Custom.label()}"
+                    }
+                    if let Some(stats) = drill_stats {
+                        button {
+                            class: if text_kind() == TextKind::Drill { "mode-option active" } else { "mode-option" },
+                            r#type: "button",
+                            aria_pressed: text_kind() == TextKind::Drill,
+                            title: "Words built around the keys you miss most. Click again for new words.",
+                            onclick: move |_| {
+                                let seed = get_timestamp_milliseconds_now_wasm() as u64;
+                                custom_text.set(drill_text(&stats, seed));
+                                text_kind.set(TextKind::Drill);
+                                custom_editor_open.set(false);
+                                if let Some(input) = text_input_ref() {
+                                    spawn(async move {
+                                        let _ = input.set_focus(true).await;
+                                    });
+                                }
+                            },
+                            "{TextKind::Drill.label()}"
+                        }
+                    }
+                }
+                div { class: "mode-picker", role: "group", aria_label: "Story language",
+                    for language in Language::all() {
+                        button {
+                            key: "{language.id()}",
+                            class: if language == story_language() { "mode-option active" } else { "mode-option" },
+                            r#type: "button",
+                            lang: "{language.id()}",
+                            aria_pressed: language == story_language(),
+                            onclick: move |_| {
+                                story_language.set(language);
+                                if let Some(input) = text_input_ref() {
+                                    spawn(async move {
+                                        let _ = input.set_focus(true).await;
+                                    });
+                                }
+                            },
+                            "{language.label()}"
+                        }
+                    }
+                }
+                if custom_editor_open() {
+                    section { class: "custom-text", aria_label: "Custom text",
+                        textarea {
+                            rows: "6",
+                            placeholder: "Paste the passage you want to practice",
+                            maxlength: "{MAX_CUSTOM_TEXT_CHARS}",
+                            value: "{custom_draft}",
+                            oninput: move |event| custom_draft.set(event.value()),
+                        }
+                        div { class: "custom-text-actions",
+                            label { class: "custom-text-upload",
+                                "Upload .txt or .md"
+                                input {
+                                    r#type: "file",
+                                    accept: ".txt,.md,text/plain,text/markdown",
+                                    onchange: move |event| async move {
+                                        let Some(file) = event.files().into_iter().next() else {
+                                            return;
+                                        };
+                                        // UTF-8 never needs more than four bytes per character.
+                                        if file.size() > (MAX_CUSTOM_TEXT_CHARS * 4) as u64 {
+                                            custom_error.set("This file is too large.".to_string());
+                                            return;
+                                        }
+                                        match file.read_string().await {
+                                            Ok(contents) => {
+                                                custom_draft.set(contents);
+                                                custom_error.set(String::new());
+                                            }
+                                            Err(_) => custom_error.set("This file could not be read.".to_string()),
+                                        }
+                                    },
+                                }
+                            }
+                            Button {
+                                size: ButtonSize::Sm,
+                                onclick: move |_| match prepare_custom_text(&custom_draft()) {
+                                    Ok(prepared) => {
+                                        custom_text.set(prepared);
+                                        text_kind.set(TextKind::Custom);
+                                        custom_editor_open.set(false);
+                                        custom_error.set(String::new());
+                                        if let Some(input) = text_input_ref() {
+                                            spawn(async move {
+                                                let _ = input.set_focus(true).await;
+                                            });
+                                        }
+                                    }
+                                    Err(message) => custom_error.set(message.to_string()),
+                                },
+                                "Practice this text"
+                            }
+                        }
+                        if !custom_error().is_empty() {
+                            p { class: "save-message", role: "alert", "{custom_error}" }
+                        }
+                        p { class: "save-message",
+                            "Custom text runs are saved to your private history only, never to the leaderboards."
+                        }
+                    }
+                }
+                label { class: "option-toggle",
+                    input {
+                        r#type: "checkbox",
+                        checked: stats.allow_previous_word,
+                        onchange: move |event| {
+                            let mut updated_stats = local_stats();
+                            updated_stats.allow_previous_word = event.checked();
+                            save_local_stats(&updated_stats);
+                            local_stats.set(updated_stats);
+                        },
+                    }
+                    "Allow going back to the previous word"
+                }
+                label { class: "challenge-day-picker",
+                    "Keyboard layout"
+                    select {
+                        value: "{keyboard_layout().id()}",
+                        onchange: move |event| {
+                            let Some(layout) = KeyboardLayout::parse(&event.value()) else {
+                                return;
+                            };
+                            let mut updated_stats = local_stats();
+                            updated_stats.keyboard_layout = Some(layout);
+                            save_local_stats(&updated_stats);
+                            local_stats.set(updated_stats);
+                            if profile_resource().flatten().is_some() {
+                                spawn(async move {
+                                    let _ = set_keyboard_layout(layout).await;
+                                });
+                            }
+                        },
+                        for layout in KeyboardLayout::all() {
+                            option {
+                                value: "{layout.id()}",
+                                selected: layout == keyboard_layout(),
+                                "{layout.label()}"
+                            }
+                        }
+                    }
+                }
+                if let Some(private_profile) = profile.as_ref() {
+                    label { class: "option-toggle",
+                        input {
+                            r#type: "checkbox",
+                            checked: !private_profile.user.hide_ghosts,
+                            onchange: move |event| {
+                                let hide_ghosts = !event.checked();
+                                let mut profile_resource = profile_resource;
+                                spawn(async move {
+                                    if set_hide_ghosts(hide_ghosts).await.is_ok() {
+                                        profile_resource.restart();
+                                    }
+                                });
+                            },
+                        }
+                        "Let other players race my runs"
+                    }
+                }
+            }
+            if !running() && (text_kind().is_ranked() || race().is_some()) {
+                section { class: "race-lobby", aria_label: "Live race",
+                    if let Some(joined) = race() {
+                        div { class: "race-code",
+                            span { "Race code" }
+                            strong { "{joined.code}" }
+                        }
+                        if joined.host && !joined.started {
+                            Button {
+                                size: ButtonSize::Sm,
+                                onclick: move |_| send_race(RaceClientMessage::Start),
+                                "Start race"
+                            }
+                        } else if !joined.started {
+                            span { "Waiting for the host to start" }
+                        }
+                        Button {
+                            size: ButtonSize::Sm,
+                            variant: ButtonVariant::Outline,
+                            onclick: move |_| leave_race(),
+                            "Leave race"
+                        }
+                    } else {
+                        Button {
+                            size: ButtonSize::Sm,
+                            onclick: move |_| {
+                                enter_race(RaceClientMessage::Create {
+                                    spec: RaceSpec {
+                                        challenge_date: selected_challenge_day(),
+                                        language: story_language(),
+                                        mode: test_mode(),
+                                        text: text_kind(),
+                                    },
+                                })
+                            },
+                            "Create race"
+                        }
+                        input {
+                            class: "race-code-input",
+                            value: "{race_code_draft}",
+                            maxlength: "6",
+                            placeholder: "Race code",
+                            aria_label: "Race code",
+                            oninput: move |event| race_code_draft.set(event.value()),
+                        }
+                        Button {
+                            size: ButtonSize::Sm,
+                            variant: ButtonVariant::Outline,
+                            disabled: race_code_draft().trim().is_empty(),
+                            onclick: move |_| {
+                                enter_race(RaceClientMessage::Join {
+                                    code: race_code_draft(),
+                                })
+                            },
+                            "Join race"
+                        }
+                    }
+                    if !race_message().is_empty() {
+                        p { class: "save-message", role: "alert", "{race_message}" }
+                    }
+                }
+            }
             if stats.best_score > 0 {
                 p { class: "personal-best",
                     "Best {stats.best_wpm:.0} WPM · {stats.best_accuracy * 100.0:.0}% · {stats.best_score} pts"
@@ -396,7 +1329,23 @@ pub fn TypingWords() -> Element {
                     em { role: "status", aria_live: "polite", "{milestone} word blaze!" }
                 }
             }
-            if let Some(pace_status) = pace {
+            if let Some(gap) = ghost_gap {
+                p {
+                    class: match gap.signum() {
+                        1 => "pace-status pace-ahead",
+                        -1 => "pace-status pace-behind",
+                        _ => "pace-status pace-even",
+                    },
+                    role: "status",
+                    match gap {
+                        0 => format!("Level with {ghost_name}"),
+                        1 => format!("1 word ahead of {ghost_name}"),
+                        -1 => format!("1 word behind {ghost_name}"),
+                        gap if gap > 0 => format!("{gap} words ahead of {ghost_name}"),
+                        gap => format!("{} words behind {ghost_name}", -gap),
+                    }
+                }
+            } else if let Some(pace_status) = pace {
                 p {
                     class: match pace_status {
                         PaceStatus::Ahead => "pace-status pace-ahead",
@@ -411,30 +1360,90 @@ pub fn TypingWords() -> Element {
                     }
                 }
             }
-            div { id: "words",
-                for (i , word) in current_chunk.iter().enumerate() {
+            if let Some(joined) = race() {
+                RaceTrack {
+                    racers: joined.racers,
+                    racer_id: joined.racer_id,
+                    total_words: sentence_to_write_words.len() as u32,
+                }
+            }
+            if let Some(seconds) = race_countdown() {
+                p { class: "race-countdown", role: "status", "Race starts in {seconds}" }
+            }
+            div { id: "words", class: if code_layout { "code-words" } else { "" },
+                for (i , token) in current_chunk.iter().enumerate() {
+                    if code_layout && token.line_start && i > 0 {
+                        div { class: "break" }
+                    }
                     if i < current_word_in_chunk_index() {
                         if user_words().len() > i {
-                            if user_words()[i] == *word {
-                                div { class: "previous_correct", "{word}" }
+                            if user_words()[i] == token.word {
+                                div {
+                                    class: "previous_correct",
+                                    style: indent_style(code_layout, token),
+                                    GhostWord {
+                                        word: token.word.clone(),
+                                        ghost: (ghost_in_current == Some(i)).then_some(ghost_chars),
+                                    }
+                                }
                             } else {
-                                div { class: "previous_wrong", "{word}" }
+                                div {
+                                    class: "previous_wrong",
+                                    style: indent_style(code_layout, token),
+                                    GhostWord {
+                                        word: token.word.clone(),
+                                        ghost: (ghost_in_current == Some(i)).then_some(ghost_chars),
+                                    }
+                                }
                             }
                         }
                     } else if i == current_word_in_chunk_index() {
-                        div { id: "current", "{word}" }
+                        div { id: "current", style: indent_style(code_layout, token),
+                            for (position , (character , mark)) in mark_characters(&token.word, &current_text()).into_iter().enumerate() {
+                                if ghost_in_current == Some(i) && position == ghost_chars {
+                                    span { class: "ghost-caret", aria_hidden: "true" }
+                                }
+                                if position == caret_position {
+                                    span { class: "caret", aria_hidden: "true" }
+                                }
+                                span { class: char_mark_class(mark), "{character}" }
+                            }
+                            if ghost_in_current == Some(i) && ghost_chars >= current_marks_len {
+                                span { class: "ghost-caret", aria_hidden: "true" }
+                            }
+                            if caret_position >= current_marks_len {
+                                span { class: "caret", aria_hidden: "true" }
+                            }
+                        }
                     } else {
-                        div { "{word}" }
+                        div { style: indent_style(code_layout, token),
+                            GhostWord {
+                                word: token.word.clone(),
+                                ghost: (ghost_in_current == Some(i)).then_some(ghost_chars),
+                            }
+                        }
                     }
                 }
                 div { class: "break" }
-                for word in next_chunk.iter() {
-                    div { "{word}" }
+                for (i , token) in next_chunk.iter().enumerate() {
+                    if code_layout && token.line_start && i > 0 {
+                        div { class: "break" }
+                    }
+                    div { style: indent_style(code_layout, token),
+                        GhostWord {
+                            word: token.word.clone(),
+                            ghost: (ghost_in_next == Some(i)).then_some(ghost_chars),
+                        }
+                    }
                 }
             }
             if !running() && !finished() {
                 div { id: "tips",
-                    "Write as quickly as you can, pressing the space bar after each word."
+                    if code_layout {
+                        "Type each token and press space, Tab or Enter. Indentation is skipped for you."
+                    } else {
+                        "Write as quickly as you can, pressing the space bar after each word."
+                    }
                 }
             }
             if current_chunk_index() < nb_chunks_to_write && !finished() {
@@ -447,69 +1456,40 @@ pub fn TypingWords() -> Element {
                             let _ = input.set_focus(true).await;
                         });
                     },
-                    oninput: move |event| {
-                        let current_chunk_clone = current_chunk.clone();
-                        async move {
-                            if started_at().is_none() {
-                                started_at.set(Some(get_timestamp_seconds_now_wasm()));
-                                run_id.set(format!(
-                                    "run-{}",
-                                    get_timestamp_milliseconds_now_wasm()
-                                ));
-                                running.set(true);
-                            }
-                            let data = event.value();
-                            if data.ends_with(" ") {
-                                let typed_word = data.trim().to_string();
-                                let mut new_words = user_words().to_vec();
-                                new_words.push(typed_word.clone());
-                                user_words.set(new_words);
-
-                                let word_index = current_word_in_chunk_index();
-                                let is_correct = current_chunk_clone
-                                    .get(word_index)
-                                    .is_some_and(|expected| expected == &typed_word);
-                                if is_correct {
-                                    correct_words.set(correct_words() + 1);
-                                } else {
-                                    wrong_words.set(wrong_words() + 1);
-                                }
-                                let combo_progress =
-                                    record_combo_word(combo(), max_combo(), is_correct);
-                                combo.set(combo_progress.current);
-                                max_combo.set(combo_progress.best);
-                                combo_milestone.set(combo_progress.milestone);
-
-                                let next_word_index = word_index + 1;
-                                if next_word_index >= current_chunk_clone.len() {
-                                    let next_chunk_index = current_chunk_index() + 1;
-                                    if next_chunk_index >= nb_chunks_to_write {
-                                        current_word_in_chunk_index.set(next_word_index);
-                                        duration_seconds.set(
-                                            started_at()
-                                                .map(|start| {
-                                                    (get_timestamp_seconds_now_wasm() - start)
-                                                        .clamp(1, 600)
-                                                })
-                                                .unwrap_or(1),
-                                        );
-                                        running.set(false);
-                                        finished.set(true);
-                                    } else {
-                                        current_word_in_chunk_index.set(0);
-                                        current_chunk_index.set(next_chunk_index);
-                                        user_words.set(vec![]);
-                                    }
-                                } else {
-                                    current_word_in_chunk_index.set(next_word_index);
-                                }
-                                current_text.set(String::new());
-                            } else {
-                                current_text.set(data.clone());
-                            }
+                    onkeydown: move |event| {
+                        let key = event.key();
+                        pending_key.set(key.to_string());
+                        if key == Key::Backspace
+                            && current_text().is_empty()
+                            && running()
+                            && local_stats.read().allow_previous_word
+                        {
+                            event.prevent_default();
+                            go_back(&key_chunk);
+                            return;
+                        }
+                        if !code_layout {
+                            return;
+                        }
+                        let separator = match key {
+                            Key::Enter => '\n',
+                            Key::Tab => '\t',
+                            Key::Character(ref typed) if typed == " " => ' ',
+                            _ => return,
+                        };
+                        // Indentation is laid out for the player, so whitespace
+                        // before a token is swallowed instead of typed.
+                        if current_text().is_empty() {
+                            event.prevent_default();
+                        } else if separator != ' ' {
+                            event.prevent_default();
+                            type_input(format!("{}{separator}", current_text()), &key_chunk);
                         }
                     },
+                    oninput: move |event| type_input(event.value(), &input_chunk),
                     value: "{current_text}",
+                    // Racers wait for the countdown before their first key.
+                    readonly: race_waiting,
                     autocomplete: "off",
                     autocapitalize: "off",
                     spellcheck: "false",
@@ -522,6 +1502,9 @@ pub fn TypingWords() -> Element {
                         p { class: "record-banner", role: "status", "New personal best!" }
                     }
                     div { class: "result-grid",
+                        if let Some((place, racers)) = race_place {
+                            ResultStat { label: "Place", value: format!("{place} of {racers}") }
+                        }
                         ResultStat { label: "WPM", value: format!("{wpm:.0}") }
                         ResultStat {
                             label: "Accuracy",
@@ -532,7 +1515,22 @@ pub fn TypingWords() -> Element {
                             label: "Time",
                             value: format!("{}s", duration_seconds()),
                         }
+                        ResultStat { label: "Raw WPM", value: format!("{raw_wpm:.0}") }
+                        ResultStat { label: "CPM", value: format!("{cpm:.0}") }
+                        ResultStat {
+                            label: "Consistency",
+                            value: format!("{consistency:.0}%"),
+                        }
+                        ResultStat {
+                            label: "Corrected errors",
+                            value: error_tracker.read().corrected_errors.to_string(),
+                        }
+                        ResultStat {
+                            label: "Uncorrected errors",
+                            value: error_tracker.read().uncorrected_errors.to_string(),
+                        }
                     }
+                    SpeedChart { points: timeline() }
                     p { class: "combo-summary", "Best combo: 🔥 {max_combo}" }
                     if let Some(badge) = new_badge() {
                         section { class: "new-badge", aria_live: "polite",
@@ -563,18 +1561,89 @@ pub fn TypingWords() -> Element {
                 }
 
                 if let Some(private_profile) = profile.clone() {
-                    HistoryPanel { profile: private_profile }
+                    HistoryPanel { profile: private_profile.clone(), layout: keyboard_layout() }
+                    TeamsPanel {
+                        teams: private_profile.teams,
+                        on_create: move |name: String| {
+                            let mut profile_resource = profile_resource;
+                            spawn(async move {
+                                match create_team(name).await {
+                                    Ok(team) => {
+                                        team_message.set(format!("{} is ready. Copy its invite link to add teammates.", team.name));
+                                        profile_resource.restart();
+                                    }
+                                    Err(_) => team_message.set("This team could not be created.".to_string()),
+                                }
+                            });
+                        },
+                    }
+                    FriendsPanel {
+                        following: private_profile.following,
+                        message: follow_message(),
+                        on_follow: move |login: String| {
+                            let mut profile_resource = profile_resource;
+                            let mut leaderboard_resource = leaderboard_resource;
+                            spawn(async move {
+                                match follow_player(login).await {
+                                    Ok(player) => {
+                                        follow_message.set(format!("You follow @{}.", player.login));
+                                        profile_resource.restart();
+                                        leaderboard_resource.restart();
+                                    }
+                                    Err(_) => follow_message.set("This player could not be followed. They need to have signed in once.".to_string()),
+                                }
+                            });
+                        },
+                        on_unfollow: move |github_id: String| {
+                            let mut profile_resource = profile_resource;
+                            let mut leaderboard_resource = leaderboard_resource;
+                            spawn(async move {
+                                if unfollow_player(github_id).await.is_ok() {
+                                    follow_message.set(String::new());
+                                    profile_resource.restart();
+                                    leaderboard_resource.restart();
+                                }
+                            });
+                        },
+                    }
                 }
 
-                LeaderboardPanel {
-                    board: leaderboard_resource().flatten(),
-                    scope: leaderboard_scope(),
-                    on_scope: move |scope| leaderboard_scope.set(scope),
+                if text_kind().is_ranked() {
+                    LeaderboardPanel {
+                        board: shown_leaderboard(),
+                        loading_more: loading_more(),
+                        on_more: load_more_leaderboard,
+                        scope: leaderboard_scope(),
+                        on_scope: move |scope| leaderboard_scope.set(scope),
+                        layout: leaderboard_layout(),
+                        on_layout: move |layout| leaderboard_layout.set(layout),
+                        teams: profile.as_ref().map(|profile| profile.teams.clone()).unwrap_or_default(),
+                        team: leaderboard_team(),
+                        on_team: move |team: Option<String>| {
+                            // Team boards only exist per day and per week.
+                            if team.is_some() && leaderboard_scope() == LeaderboardScope::Global {
+                                leaderboard_scope.set(LeaderboardScope::Week);
+                            }
+                            if team.is_some() {
+                                leaderboard_friends.set(false);
+                            }
+                            leaderboard_team.set(team);
+                        },
+                        signed_in: profile.is_some(),
+                        friends: leaderboard_friends(),
+                        on_friends: move |friends: bool| {
+                            if friends {
+                                leaderboard_team.set(None);
+                            }
+                            leaderboard_friends.set(friends);
+                        },
+                        on_race: race_run,
+                    }
                 }
 
                 div { class: "sources",
                     div { "Text sources" }
-                    for source in story.sources {
+                    for source in sources {
                         a { href: source, target: "_blank", rel: "noreferrer", "{last_title}" }
                     }
                 }
@@ -583,6 +1652,33 @@ pub fn TypingWords() -> Element {
     }
 }
 
+fn char_mark_class(mark: CharMark) -> &'static str {
+    match mark {
+        CharMark::Correct => "char-correct",
+        CharMark::Incorrect => "char-wrong",
+        CharMark::Pending => "char-pending",
+        CharMark::Extra => "char-extra",
+    }
+}
+
+/// Code tokens that open a line are pushed right by the line's indentation.
+fn indent_style(code_layout: bool, token: &TextToken) -> String {
+    if code_layout && token.line_start && token.indent > 0 {
+        format!("margin-left: {}ch", token.indent)
+    } else {
+        String::new()
+    }
+}
+
+/// Timed runs count down from their limit; the others count up from zero.
+fn timer_start(mode: TestMode) -> i64 {
+    if mode.is_timed() {
+        mode.time_limit_seconds()
+    } else {
+        0
+    }
+}
+
 #[component]
 fn ProfileBar(profile: Option<PrivateProfile>) -> Element {
     rsx! {
@@ -629,6 +1725,62 @@ fn ResultStat(label: &'static str, value: String) -> Element {
     }
 }
 
+/// Net WPM over the run, raw WPM of each second, and a marker on every
+/// second in which a word went wrong.
+#[component]
+fn SpeedChart(points: Vec<TimelinePoint>) -> Element {
+    const WIDTH: f64 = 600.0;
+    const HEIGHT: f64 = 160.0;
+    let Some(last) = points.last().filter(|_| points.len() > 1) else {
+        return rsx! {};
+    };
+    let last_second = f64::from(last.second);
+    let top_wpm = points
+        .iter()
+        .map(|point| point.wpm.max(point.raw_wpm))
+        .fold(10.0, f64::max);
+    let x = |point: &TimelinePoint| f64::from(point.second) / last_second * WIDTH;
+    let y = |wpm: f64| HEIGHT - wpm / top_wpm * HEIGHT;
+    let line = |speed: fn(&TimelinePoint) -> f64| {
+        points
+            .iter()
+            .map(|point| format!("{:.1},{:.1}", x(point), y(speed(point))))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let net_line = line(|point| point.wpm);
+    let raw_line = line(|point| point.raw_wpm);
+    let error_marks = points
+        .iter()
+        .filter(|point| point.errors > 0)
+        .map(|point| (x(point), y(point.wpm), point.second, point.errors))
+        .collect::<Vec<_>>();
+
+    rsx! {
+        figure { class: "speed-chart",
+            svg {
+                view_box: "0 0 {WIDTH} {HEIGHT}",
+                role: "img",
+                "aria-label": "Speed over the run",
+                text { class: "speed-scale", x: "4", y: "14", "{top_wpm:.0} WPM" }
+                polyline { class: "speed-raw", points: "{raw_line}" }
+                polyline { class: "speed-net", points: "{net_line}" }
+                for (cx , cy , second , errors) in error_marks {
+                    circle { class: "speed-error", cx: "{cx:.1}", cy: "{cy:.1}", r: "4",
+                        title { "{errors} wrong at {second}s" }
+                    }
+                }
+            }
+            figcaption {
+                span { class: "legend-net", "WPM" }
+                span { class: "legend-raw", "Raw" }
+                span { class: "legend-error", "Errors" }
+                span { "{last.second}s" }
+            }
+        }
+    }
+}
+
 #[component]
 fn AchievementBadge(badge: Badge) -> Element {
     rsx! {
@@ -640,7 +1792,7 @@ fn AchievementBadge(badge: Badge) -> Element {
 }
 
 #[component]
-fn HistoryPanel(profile: PrivateProfile) -> Element {
+fn HistoryPanel(profile: PrivateProfile, layout: KeyboardLayout) -> Element {
     rsx! {
         section { class: "history-panel",
             h2 { "Private profile" }
@@ -660,7 +1812,17 @@ fn HistoryPanel(profile: PrivateProfile) -> Element {
                                 span { "{result.story_title}" }
                             }
                             div { class: "history-metrics",
+                                span { "{result.mode.label()}" }
+                                span { "{result.keyboard_layout.label()}" }
+                                if let Some(race) = result.race.as_ref() {
+                                    span { "Race #{race.place} of {race.racers}" }
+                                }
                                 span { "{result.wpm:.0} WPM" }
+                                if result.cpm > 0.0 {
+                                    span { "{result.raw_wpm:.0} raw" }
+                                    span { "{result.cpm:.0} CPM" }
+                                    span { "{result.consistency:.0}% consistent" }
+                                }
                                 span { "{result.accuracy * 100.0:.0}%" }
                                 span { "{result.created_at.format(\"%Y-%m-%d\")}" }
                             }
@@ -668,6 +1830,294 @@ fn HistoryPanel(profile: PrivateProfile) -> Element {
                     }
                 }
             }
+            if !profile.key_stats.keys.is_empty() {
+                KeyHeatmap { stats: profile.key_stats.clone(), layout }
+            }
+        }
+    }
+}
+
+/// The player's teams and a form to start one. Members share a team's
+/// invite link to bring others in.
+#[component]
+fn TeamsPanel(teams: Vec<Team>, on_create: EventHandler<String>) -> Element {
+    let mut team_name = use_signal(String::new);
+    let mut name_error = use_signal(String::new);
+    let mut copied_team = use_signal(|| None::<String>);
+    rsx! {
+        section { class: "teams-panel", aria_label: "Teams",
+            h2 { "Teams" }
+            if teams.is_empty() {
+                p { "Start a team to compete with colleagues on private day and week boards." }
+            }
+            for team in teams {
+                div { class: "team-row", key: "{team.id}",
+                    div {
+                        strong { "{team.name}" }
+                        span {
+                            if team.member_ids.len() == 1 {
+                                "1 member"
+                            } else {
+                                "{team.member_ids.len()} members"
+                            }
+                        }
+                    }
+                    Button {
+                        size: ButtonSize::Sm,
+                        variant: ButtonVariant::Outline,
+                        onclick: {
+                            let team_id = team.id.clone();
+                            let invite_code = team.invite_code.clone();
+                            move |_| {
+                                if copy_invite_link(&invite_code) {
+                                    copied_team.set(Some(team_id.clone()));
+                                }
+                            }
+                        },
+                        if copied_team().as_deref() == Some(team.id.as_str()) {
+                            "Invite link copied"
+                        } else {
+                            "Copy invite link"
+                        }
+                    }
+                }
+            }
+            div { class: "team-create",
+                input {
+                    value: "{team_name}",
+                    maxlength: "{MAX_TEAM_NAME_CHARS}",
+                    placeholder: "Team name",
+                    aria_label: "Team name",
+                    oninput: move |event| team_name.set(event.value()),
+                }
+                Button {
+                    size: ButtonSize::Sm,
+                    onclick: move |_| match prepare_team_name(&team_name()) {
+                        Ok(name) => {
+                            on_create.call(name);
+                            team_name.set(String::new());
+                            name_error.set(String::new());
+                        }
+                        Err(message) => name_error.set(message.to_string()),
+                    },
+                    "Create team"
+                }
+            }
+            if !name_error().is_empty() {
+                p { class: "save-message", role: "alert", "{name_error}" }
+            }
+        }
+    }
+}
+
+/// The players this one follows, who share the Following leaderboard with them.
+#[component]
+fn FriendsPanel(
+    following: Vec<FollowedPlayer>,
+    message: String,
+    on_follow: EventHandler<String>,
+    on_unfollow: EventHandler<String>,
+) -> Element {
+    let mut login = use_signal(String::new);
+    let mut login_error = use_signal(String::new);
+    rsx! {
+        section { class: "teams-panel friends-panel", aria_label: "Following",
+            h2 { "Following" }
+            if following.is_empty() {
+                p { "Follow colleagues by their GitHub login to rank against them on the Following board." }
+            }
+            for player in following {
+                div { class: "team-row", key: "{player.github_id}",
+                    strong { "@{player.login}" }
+                    Button {
+                        size: ButtonSize::Sm,
+                        variant: ButtonVariant::Ghost,
+                        onclick: {
+                            let github_id = player.github_id.clone();
+                            move |_| on_unfollow.call(github_id.clone())
+                        },
+                        "Unfollow"
+                    }
+                }
+            }
+            div { class: "team-create",
+                input {
+                    value: "{login}",
+                    placeholder: "GitHub login",
+                    aria_label: "GitHub login to follow",
+                    oninput: move |event| login.set(event.value()),
+                }
+                Button {
+                    size: ButtonSize::Sm,
+                    onclick: move |_| match prepare_github_login(&login()) {
+                        Ok(valid_login) => {
+                            on_follow.call(valid_login);
+                            login.set(String::new());
+                            login_error.set(String::new());
+                        }
+                        Err(error) => login_error.set(error.to_string()),
+                    },
+                    "Follow"
+                }
+            }
+            // Signing in again with the flag set imports the list after login.
+            form { action: "/auth/github", method: "get",
+                input { r#type: "hidden", name: "import_following", value: "true" }
+                Button {
+                    r#type: "submit",
+                    size: ButtonSize::Sm,
+                    variant: ButtonVariant::Outline,
+                    "Import who I follow on GitHub"
+                }
+            }
+            if !login_error().is_empty() {
+                p { class: "save-message", role: "alert", "{login_error}" }
+            } else if !message.is_empty() {
+                p { class: "save-message", role: "status", "{message}" }
+            }
+        }
+    }
+}
+
+/// One bar per racer, filled by the share of the story they have typed.
+#[component]
+fn RaceTrack(racers: Vec<RacerProgress>, racer_id: u32, total_words: u32) -> Element {
+    rsx! {
+        section { class: "race-track", aria_label: "Race progress",
+            for racer in racers {
+                div {
+                    key: "{racer.racer_id}",
+                    class: if racer.racer_id == racer_id { "race-lane race-lane-self" } else { "race-lane" },
+                    span { class: "race-name", "{racer.name}" }
+                    div { class: "race-bar",
+                        div {
+                            class: "race-bar-fill",
+                            style: "width: {racer.words_done.min(total_words) * 100 / total_words.max(1)}%",
+                        }
+                    }
+                    span { class: "race-stat",
+                        if let Some(place) = racer.place {
+                            "#{place} · {racer.wpm:.0} WPM"
+                        } else {
+                            "{racer.wpm:.0} WPM"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A word outside the input, with the ghost's caret after its first `ghost`
+/// characters when the ghost is typing it.
+#[component]
+fn GhostWord(word: String, ghost: Option<usize>) -> Element {
+    let Some(typed_chars) = ghost else {
+        return rsx! { "{word}" };
+    };
+    let split = word
+        .char_indices()
+        .nth(typed_chars)
+        .map_or(word.len(), |(index, _)| index);
+    let (typed, pending) = word.split_at(split);
+    rsx! {
+        "{typed}"
+        span { class: "ghost-caret", aria_hidden: "true" }
+        "{pending}"
+    }
+}
+
+/// Shows a space as a visible glyph in key labels.
+fn key_label(key: &str) -> String {
+    key.replace(' ', "␣")
+}
+
+/// The keyboard tinted by how often each key was missed across every saved
+/// run, plus the letter pairs missed most. Keys sit where `layout` puts them.
+#[component]
+fn KeyHeatmap(stats: KeyStats, layout: KeyboardLayout) -> Element {
+    let heat = |key: char| {
+        let stat = stats.for_key(key);
+        let title = match stat.mean_latency_ms() {
+            Some(latency) => format!(
+                "{}: {:.0}% missed over {} presses, {latency:.0} ms",
+                key_label(&stat.key),
+                stat.miss_rate() * 100.0,
+                stat.attempts()
+            ),
+            None => format!("{}: not typed yet", key_label(&stat.key)),
+        };
+        let style = if stat.attempts() > 0 {
+            format!(
+                "background-color: rgba(230, 57, 70, {:.2})",
+                (stat.miss_rate() * 4.0).clamp(0.08, 0.9)
+            )
+        } else {
+            String::new()
+        };
+        (title, style)
+    };
+    let rows = layout
+        .rows()
+        .iter()
+        .map(|row| {
+            row.chars()
+                .map(|key| {
+                    let (title, style) = heat(key);
+                    (key, title, style)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let (space_title, space_style) = heat(' ');
+    let weakest = stats
+        .weakest_bigrams(8)
+        .into_iter()
+        .map(|stat| {
+            (
+                key_label(&stat.key),
+                stat.miss_rate() * 100.0,
+                stat.mean_latency_ms().unwrap_or(0.0),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    rsx! {
+        div { class: "key-heatmap",
+            h3 { "Missed keys" }
+            div {
+                class: "heat-keyboard",
+                aria_label: "Miss rate per key on {layout.label()}",
+                for (index , row) in rows.into_iter().enumerate() {
+                    div { class: "heat-row", key: "{index}",
+                        for (key , title , style) in row {
+                            span { class: "heat-key", title: "{title}", style: "{style}", "{key}" }
+                        }
+                    }
+                }
+                div { class: "heat-row",
+                    span {
+                        class: "heat-key heat-space",
+                        title: "{space_title}",
+                        style: "{space_style}",
+                        "space"
+                    }
+                }
+            }
+            h3 { "Weakest bigrams" }
+            if weakest.is_empty() {
+                p { "Keep typing; pairs show up here after {MIN_BIGRAM_ATTEMPTS} tries." }
+            } else {
+                ol { class: "weak-bigrams",
+                    for (pair , miss_percent , latency) in weakest {
+                        li { key: "{pair}",
+                            strong { "{pair}" }
+                            span { "{miss_percent:.0}% missed" }
+                            span { "{latency:.0} ms" }
+                        }
+                    }
+                }
+            }
         }
     }
 }
@@ -675,9 +2125,33 @@ fn HistoryPanel(profile: PrivateProfile) -> Element {
 #[component]
 fn LeaderboardPanel(
     board: Option<Leaderboard>,
+    loading_more: bool,
+    on_more: EventHandler<()>,
     scope: LeaderboardScope,
     on_scope: EventHandler<LeaderboardScope>,
+    layout: Option<KeyboardLayout>,
+    on_layout: EventHandler<Option<KeyboardLayout>>,
+    teams: Vec<Team>,
+    team: Option<String>,
+    on_team: EventHandler<Option<String>>,
+    /// Whether the board shows the players list; only signed-in players have one.
+    signed_in: bool,
+    friends: bool,
+    on_friends: EventHandler<bool>,
+    on_race: EventHandler<String>,
 ) -> Element {
+    let players_label = if friends {
+        "Following · ".to_string()
+    } else {
+        team.as_ref()
+            .and_then(|team_id| teams.iter().find(|team| &team.id == team_id))
+            .map(|team| format!("{} · ", team.name))
+            .unwrap_or_default()
+    };
+    let own_rank = board
+        .as_ref()
+        .and_then(|board| board.around_me.as_ref())
+        .map(|standing| standing.rank);
     rsx! {
         section { class: "leaderboard-panel",
             h2 { "Leaderboard" }
@@ -698,37 +2172,117 @@ fn LeaderboardPanel(
                     onclick: move |_| on_scope.call(LeaderboardScope::Week),
                     "Week"
                 }
-                button {
-                    class: if scope == LeaderboardScope::Global { "leaderboard-tab active" } else { "leaderboard-tab" },
-                    r#type: "button",
-                    role: "tab",
-                    aria_selected: scope == LeaderboardScope::Global,
-                    onclick: move |_| on_scope.call(LeaderboardScope::Global),
-                    "Global"
+                if team.is_none() {
+                    button {
+                        class: if scope == LeaderboardScope::Global { "leaderboard-tab active" } else { "leaderboard-tab" },
+                        r#type: "button",
+                        role: "tab",
+                        aria_selected: scope == LeaderboardScope::Global,
+                        onclick: move |_| on_scope.call(LeaderboardScope::Global),
+                        "Global"
+                    }
+                }
+            }
+            if signed_in {
+                div { class: "leaderboard-tabs", role: "tablist", aria_label: "Leaderboard players",
+                    button {
+                        class: if team.is_none() && !friends { "leaderboard-tab active" } else { "leaderboard-tab" },
+                        r#type: "button",
+                        role: "tab",
+                        aria_selected: team.is_none() && !friends,
+                        onclick: move |_| {
+                            on_team.call(None);
+                            on_friends.call(false);
+                        },
+                        "Everyone"
+                    }
+                    button {
+                        class: if friends { "leaderboard-tab active" } else { "leaderboard-tab" },
+                        r#type: "button",
+                        role: "tab",
+                        aria_selected: friends,
+                        onclick: move |_| on_friends.call(true),
+                        "Following"
+                    }
+                    for option_team in teams.iter() {
+                        button {
+                            key: "{option_team.id}",
+                            class: if team.as_ref() == Some(&option_team.id) { "leaderboard-tab active" } else { "leaderboard-tab" },
+                            r#type: "button",
+                            role: "tab",
+                            aria_selected: team.as_ref() == Some(&option_team.id),
+                            onclick: {
+                                let team_id = option_team.id.clone();
+                                move |_| on_team.call(Some(team_id.clone()))
+                            },
+                            "{option_team.name}"
+                        }
+                    }
+                }
+            }
+            if team.is_none() {
+                label { class: "leaderboard-layout-picker",
+                    "Layout"
+                    select {
+                        value: layout.map_or("all", KeyboardLayout::id),
+                        onchange: move |event| on_layout.call(KeyboardLayout::parse(&event.value())),
+                        option { value: "all", selected: layout.is_none(), "All layouts" }
+                        for option_layout in KeyboardLayout::all() {
+                            option {
+                                value: "{option_layout.id()}",
+                                selected: layout == Some(option_layout),
+                                "{option_layout.label()}"
+                            }
+                        }
+                    }
                 }
             }
             if let Some(board) = board {
-                p { class: "leaderboard-label", "{board.label}" }
-                if board.entries.is_empty() {
+                p { class: "leaderboard-label",
+                    "{players_label}{board.label} · {board.mode.label()} · {board.text.label()} · {board.language.label()}"
+                }
+                if board.entries.is_empty() && board.friends {
+                    p { class: "leaderboard-empty", "Neither you nor the players you follow have a ranked run here yet." }
+                } else if board.entries.is_empty() {
                     p { class: "leaderboard-empty", "No ranked runs yet. Be the first." }
                 } else {
                     div { class: "leaderboard-list",
                         for entry in board.entries.iter() {
-                            div { class: "leaderboard-row", key: "{entry.github_id}-{entry.run_id}",
-                                span { class: "leaderboard-rank", "#{entry.rank}" }
-                                ImageAvatar {
-                                    size: AvatarImageSize::Small,
-                                    src: entry.avatar_url.clone(),
-                                    alt: format!("{}'s GitHub avatar", entry.login),
-                                    "{entry.login.chars().next().unwrap_or('?')}"
-                                }
-                                div { class: "leaderboard-identity",
-                                    strong { "@{entry.login}" }
-                                    span { "{entry.score} pts" }
-                                }
-                                div { class: "leaderboard-metrics",
-                                    span { "{entry.wpm:.0} WPM" }
-                                    span { "{entry.accuracy * 100.0:.0}%" }
+                            LeaderboardRow {
+                                key: "{entry.github_id}-{entry.run_id}",
+                                entry: entry.clone(),
+                                own: own_rank == Some(entry.rank),
+                                raceable: board.scope == LeaderboardScope::Day,
+                                on_race,
+                            }
+                        }
+                    }
+                    if board.next_cursor.is_some() {
+                        div { class: "leaderboard-more",
+                            Button {
+                                size: ButtonSize::Sm,
+                                variant: ButtonVariant::Outline,
+                                disabled: loading_more,
+                                onclick: move |_| on_more.call(()),
+                                if loading_more { "Loading…" } else { "Load more" }
+                            }
+                        }
+                    }
+                }
+                if let Some(standing) = board.around_me.as_ref() {
+                    p { class: "leaderboard-standing",
+                        "Your rank: #{standing.rank} of {standing.players} · ahead of {standing.percentile:.0}% of players"
+                    }
+                    // Neighbors already in the top list are not repeated.
+                    if standing.rank as usize > board.entries.len() {
+                        div { class: "leaderboard-list leaderboard-around",
+                            for entry in standing.entries.iter().filter(|entry| entry.rank as usize > board.entries.len()) {
+                                LeaderboardRow {
+                                    key: "{entry.github_id}-{entry.run_id}",
+                                    entry: entry.clone(),
+                                    own: entry.rank == standing.rank,
+                                    raceable: board.scope == LeaderboardScope::Day,
+                                    on_race,
                                 }
                             }
                         }
@@ -740,3 +2294,50 @@ fn LeaderboardPanel(
         }
     }
 }
+
+#[component]
+fn LeaderboardRow(
+    entry: LeaderboardEntry,
+    /// Marks the signed-in player's own entry.
+    own: bool,
+    raceable: bool,
+    on_race: EventHandler<String>,
+) -> Element {
+    rsx! {
+        div { class: if own { "leaderboard-row own" } else { "leaderboard-row" },
+            span { class: "leaderboard-rank", "#{entry.rank}" }
+            ImageAvatar {
+                size: AvatarImageSize::Small,
+                src: entry.avatar_url.clone(),
+                alt: format!("{}'s GitHub avatar", entry.login),
+                "{entry.login.chars().next().unwrap_or('?')}"
+            }
+            div { class: "leaderboard-identity",
+                strong { "@{entry.login}" }
+                span { "{entry.score} pts" }
+            }
+            div { class: "leaderboard-metrics",
+                span { "{entry.wpm:.0} WPM" }
+                span { "{entry.keyboard_layout.label()}" }
+                if entry.cpm > 0.0 {
+                    span { "{entry.raw_wpm:.0} raw" }
+                    span { "{entry.cpm:.0} CPM" }
+                    span { "{entry.consistency:.0}% consistent" }
+                }
+                span { "{entry.accuracy * 100.0:.0}%" }
+            }
+            // Only a day board's runs share the story being typed.
+            if raceable {
+                Button {
+                    size: ButtonSize::Sm,
+                    variant: ButtonVariant::Ghost,
+                    onclick: {
+                        let run_id = entry.run_id.clone();
+                        move |_| on_race.call(run_id.clone())
+                    },
+                    "Race"
+                }
+            }
+        }
+    }
+}
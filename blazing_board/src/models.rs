@@ -2,43 +2,350 @@ use chrono::{DateTime, Utc};
 #[cfg(any(feature = "server", test))]
 use chrono::{Datelike, Duration, NaiveDate};
 use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "server", test))]
+use std::collections::HashMap;
+
+/// Longest run the server scores; untimed modes end here at the latest.
+pub const MAX_RUN_SECONDS: i64 = 600;
+pub const TIMED_MODE_SECONDS: [i64; 4] = [15, 30, 60, 120];
+pub const WORD_MODE_COUNTS: [usize; 4] = [10, 25, 50, 100];
+/// Longest passage accepted for custom text practice.
+pub const MAX_CUSTOM_TEXT_CHARS: usize = 20_000;
+pub const MAX_TEAM_NAME_CHARS: usize = 40;
+/// Each team a player is in adds a day and a week board to their saved runs.
+#[cfg(any(feature = "server", test))]
+pub const MAX_TEAMS_PER_PLAYER: usize = 10;
+#[cfg(any(feature = "server", test))]
+pub const MAX_TEAM_MEMBERS: usize = 100;
+/// A friends board loads every followed player's entry, so the list is capped.
+#[cfg(any(feature = "server", test))]
+pub const MAX_FOLLOWS: usize = 500;
 
 /// How many past UTC challenge days are exposed on the day leaderboard.
 #[cfg(any(feature = "server", test))]
 pub const RECENT_LEADERBOARD_DAYS: i64 = 10;
-/// Top entries returned for each leaderboard board.
+/// Entries on each leaderboard page unless a smaller page is asked for.
 #[cfg(any(feature = "server", test))]
 pub const LEADERBOARD_TOP_N: u32 = 50;
+#[cfg(any(feature = "server", test))]
+pub const MAX_LEADERBOARD_PAGE_SIZE: u32 = 100;
+/// Entries shown on each side of the signed-in player's own entry.
+#[cfg(any(feature = "server", test))]
+pub const AROUND_ME_NEIGHBORS: u32 = 3;
 
 #[cfg(any(feature = "server", test))]
 pub const GLOBAL_BOARD_ID: &str = "global";
+/// Bumped whenever score, WPM or accuracy are measured differently. Board ids
+/// carry it, so runs measured one way never rank against runs measured another.
+///
+/// - 2: WPM counts five characters per word
+/// - 3: corrected errors count against accuracy
+#[cfg(any(feature = "server", test))]
+pub const BOARD_METRICS_VERSION: u32 = 3;
+/// Upper bound on logged input changes; ten minutes at 20 keys per second.
+#[cfg(any(feature = "server", test))]
+pub const MAX_KEYSTROKE_EVENTS: usize = 12_000;
+/// Characters a run may insert per second on average before it is treated as
+/// scripted. 25 is 300 WPM sustained.
+#[cfg(any(feature = "server", test))]
+pub const MAX_AVERAGE_CHARS_PER_SECOND: u32 = 25;
+/// Characters any single second of a run may insert; 480 WPM, a burst nobody
+/// keeps up.
+#[cfg(any(feature = "server", test))]
+pub const MAX_BURST_CHARS_PER_SECOND: u32 = 40;
+/// Characters one change may add without replacing any: a dead key followed by
+/// a letter it does not combine with inserts both at once.
+#[cfg(any(feature = "server", test))]
+pub const MAX_UNREPLACED_INSERT_CHARS: usize = 2;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Story {
     pub sources: Vec<String>,
+    /// Lowercase text without punctuation.
     pub story: String,
+    /// The story as written, with punctuation and casing. Older stories have none.
+    #[serde(default)]
+    pub raw_story: Option<String>,
     pub title: Option<String>,
     pub when: DateTime<Utc>,
+    /// Stories generated before other languages were added are all English.
+    #[serde(default)]
+    pub language: Language,
 }
 
-impl Default for Story {
-    fn default() -> Story {
+impl Story {
+    /// The bundled story served in `language` until one has been generated.
+    pub fn fallback(language: Language) -> Story {
+        let (story, title) = match language {
+            Language::English => (
+                include_str!("../assets/texts/01.txt"),
+                "The Rust Programming Language",
+            ),
+            Language::French => (
+                include_str!("../assets/texts/01.fr.txt"),
+                "Le langage de programmation Rust",
+            ),
+            Language::German => (
+                include_str!("../assets/texts/01.de.txt"),
+                "Die Programmiersprache Rust",
+            ),
+        };
         Story {
             sources: vec!["https://doc.rust-lang.org/book/".to_string()],
-            story: include_str!("../assets/texts/01.txt").to_string(),
-            title: Some("The Rust Programming Language".to_string()),
+            story: story.to_string(),
+            raw_story: None,
+            title: Some(title.to_string()),
             // Stable fallback so SSR/client and save_typing_result story_when checks agree.
             when: DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
                 .expect("fallback story timestamp")
                 .with_timezone(&Utc),
+            language,
+        }
+    }
+}
+
+impl Default for Story {
+    fn default() -> Story {
+        Story::fallback(Language::English)
+    }
+}
+
+/// Everything a challenge day serves: its story in one language and the code
+/// snippet shared by every language.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct DailyChallenge {
+    pub story: Story,
+    pub snippet: CodeSnippet,
+}
+
+impl DailyChallenge {
+    /// The bundled story in `language` with the bundled snippet.
+    pub fn fallback(language: Language) -> DailyChallenge {
+        DailyChallenge {
+            story: Story::fallback(language),
+            snippet: CodeSnippet::default(),
         }
     }
+
+    /// The text a run of `kind` types. Stories without a raw version fall back
+    /// to the normalized one. Custom text and drills come with the run, so the
+    /// challenge has none.
+    pub fn text(&self, kind: TextKind) -> &str {
+        match kind {
+            TextKind::Custom | TextKind::Drill => "",
+            TextKind::Plain => &self.story.story,
+            TextKind::Punctuated => self.story.raw_story.as_deref().unwrap_or(&self.story.story),
+            TextKind::Code => &self.snippet.code,
+        }
+    }
+}
+
+/// The language a daily story is written in. Each language has its own story
+/// and its own prose leaderboards; code snippets are shared.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "fr")]
+    French,
+    #[serde(rename = "de")]
+    German,
+}
+
+impl Language {
+    pub fn all() -> [Self; 3] {
+        [Self::English, Self::French, Self::German]
+    }
+
+    /// ISO 639-1 code, as stored on generated stories.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::French => "fr",
+            Self::German => "de",
+        }
+    }
+
+    #[cfg(any(feature = "server", test))]
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|language| language.id() == value.trim().to_ascii_lowercase())
+    }
+
+    /// The language's name in itself.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::English => "English",
+            Self::French => "Français",
+            Self::German => "Deutsch",
+        }
+    }
+}
+
+/// The keyboard layout a player types on. Key stats are counted per character,
+/// so they carry over between layouts; the layout says which physical key
+/// produces each character.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+    Colemak,
+}
+
+impl KeyboardLayout {
+    pub fn all() -> [Self; 5] {
+        [
+            Self::Qwerty,
+            Self::Azerty,
+            Self::Qwertz,
+            Self::Dvorak,
+            Self::Colemak,
+        ]
+    }
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Qwerty => "qwerty",
+            Self::Azerty => "azerty",
+            Self::Qwertz => "qwertz",
+            Self::Dvorak => "dvorak",
+            Self::Colemak => "colemak",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|layout| layout.id() == value.trim().to_ascii_lowercase())
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Qwerty => "QWERTY",
+            Self::Azerty => "AZERTY",
+            Self::Qwertz => "QWERTZ",
+            Self::Dvorak => "Dvorak",
+            Self::Colemak => "Colemak",
+        }
+    }
+
+    /// Unshifted characters of the number, top, home and bottom rows, left to
+    /// right as the physical keys sit on an ISO or ANSI keyboard.
+    pub fn rows(self) -> [&'static str; 4] {
+        match self {
+            Self::Qwerty => ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"],
+            Self::Azerty => ["&é\"'(-è_çà)=", "azertyuiop^$", "qsdfghjklmù", "wxcvbn,;:!"],
+            Self::Qwertz => ["1234567890ß´", "qwertzuiopü+", "asdfghjklöä", "yxcvbnm,.-"],
+            Self::Dvorak => ["1234567890[]", "',.pyfgcrl/=", "aoeuidhtns-", ";qjkxbmwvz"],
+            Self::Colemak => ["1234567890-=", "qwfpgjluy;[]", "arstdhneio'", "zxcvbkm,./"],
+        }
+    }
+
+    /// Whether a key of [`Self::rows`] or the space bar types `character`,
+    /// counting capitals with their letter.
+    #[cfg(any(feature = "server", test))]
+    pub fn has_key_for(self, character: char) -> bool {
+        character == ' '
+            || character
+                .to_lowercase()
+                .all(|lowercase| self.rows().iter().any(|row| row.contains(lowercase)))
+    }
+}
+
+const FALLBACK_SNIPPET_CODE: &str = include_str!("../assets/snippets/01.rs");
+
+/// Source code typed in the code text mode, kept with its newlines and indentation.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct CodeSnippet {
+    /// Lowercase language name such as `rust`, `python` or `typescript`.
+    pub language: String,
+    pub title: Option<String>,
+    pub code: String,
+    pub sources: Vec<String>,
+    pub when: DateTime<Utc>,
+}
+
+impl Default for CodeSnippet {
+    fn default() -> CodeSnippet {
+        CodeSnippet {
+            language: "rust".to_string(),
+            title: Some("Counting words".to_string()),
+            code: FALLBACK_SNIPPET_CODE.to_string(),
+            sources: vec![
+                "https://doc.rust-lang.org/std/collections/struct.HashMap.html".to_string(),
+            ],
+            when: DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+                .expect("fallback snippet timestamp")
+                .with_timezone(&Utc),
+        }
+    }
+}
+
+/// A word of the text and where it sits in the layout shown to the player.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextToken {
+    pub word: String,
+    /// First word on its line.
+    pub line_start: bool,
+    /// Leading indentation of the line in columns, tabs counting as four.
+    pub indent: usize,
+}
+
+/// Splits `text` into the same words as `split_whitespace`, remembering which
+/// ones open a line and how far that line is indented.
+pub fn layout_tokens(text: &str) -> Vec<TextToken> {
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        let indent = line
+            .chars()
+            .take_while(|character| character.is_whitespace())
+            .map(|character| if character == '\t' { 4 } else { 1 })
+            .sum();
+        for (position, word) in line.split_whitespace().enumerate() {
+            tokens.push(TextToken {
+                word: word.to_string(),
+                line_start: position == 0,
+                indent: if position == 0 { indent } else { 0 },
+            });
+        }
+    }
+    tokens
+}
+
+/// Groups tokens into chunks of about `size` words. With `whole_lines` a chunk
+/// only ends at a line break, so a long line becomes a chunk of its own.
+pub fn chunk_tokens(tokens: &[TextToken], size: usize, whole_lines: bool) -> Vec<Vec<TextToken>> {
+    if !whole_lines {
+        return tokens.chunks(size).map(<[TextToken]>::to_vec).collect();
+    }
+    let mut chunks: Vec<Vec<TextToken>> = Vec::new();
+    let mut current = Vec::new();
+    for token in tokens {
+        if token.line_start && current.len() >= size {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push(token.clone());
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct UserProfile {
     pub github_id: String,
     pub login: String,
+    /// `login` in lowercase, which players are looked up by. GitHub logins
+    /// ignore case.
+    #[serde(default)]
+    pub login_lowercase: String,
     pub display_name: Option<String>,
     pub avatar_url: String,
     pub created_at: DateTime<Utc>,
@@ -47,6 +354,219 @@ pub struct UserProfile {
     pub best_wpm: f64,
     pub best_accuracy: f64,
     pub best_score: i64,
+    /// The layout the player declared; `None` until they pick one.
+    #[serde(default)]
+    pub keyboard_layout: Option<KeyboardLayout>,
+    /// Keeps other players from racing this player's runs as ghosts.
+    #[serde(default)]
+    pub hide_ghosts: bool,
+}
+
+/// Which version of the text a run types.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TextKind {
+    /// Lowercase words without punctuation.
+    #[default]
+    Plain,
+    /// The story as written; words must match exactly, punctuation and case included.
+    Punctuated,
+    /// The day's code snippet. Indentation is skipped, Enter and Tab end a token.
+    Code,
+    /// A passage the player pasted or uploaded. Saved to private history only.
+    Custom,
+    /// Words picked for the player's weakest keys. Saved to private history only.
+    Drill,
+}
+
+impl TextKind {
+    /// The daily texts, each with its own leaderboards.
+    pub fn all() -> [Self; 3] {
+        [Self::Plain, Self::Punctuated, Self::Code]
+    }
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Punctuated => "punctuated",
+            Self::Code => "code",
+            Self::Custom => "custom",
+            Self::Drill => "drill",
+        }
+    }
+
+    /// Whether the typed text comes with the submission instead of the daily story.
+    pub fn is_submitted(self) -> bool {
+        matches!(self, Self::Custom | Self::Drill)
+    }
+
+    /// Custom text and drills never reach the public leaderboards.
+    pub fn is_ranked(self) -> bool {
+        !self.is_submitted()
+    }
+
+    #[cfg(any(feature = "server", test))]
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|kind| kind.id() == value.trim().to_ascii_lowercase())
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Plain => "Plain",
+            Self::Punctuated => "Punctuation & case",
+            Self::Code => "Code",
+            Self::Custom => "Custom text",
+            Self::Drill => "Weak-key drill",
+        }
+    }
+}
+
+/// Replaces typographic characters a keyboard cannot type with their ASCII
+/// counterparts, keeping everything else as written.
+pub fn typable_text(text: &str) -> String {
+    let mut typable = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '‘' | '’' | '‚' | '′' => typable.push('\''),
+            '“' | '”' | '„' | '″' => typable.push('"'),
+            '–' | '—' | '‐' | '‑' => typable.push('-'),
+            '…' => typable.push_str("..."),
+            '\u{a0}' | '\u{202f}' => typable.push(' '),
+            other => typable.push(other),
+        }
+    }
+    typable
+}
+
+/// Checks a pasted or uploaded passage and returns it the way it is typed.
+/// The client and the server both run it, so they split the same words.
+pub fn prepare_custom_text(text: &str) -> Result<String, &'static str> {
+    let typable = typable_text(&text.replace("\r\n", "\n"));
+    if typable.split_whitespace().next().is_none() {
+        return Err("Custom text is empty");
+    }
+    if typable.chars().count() > MAX_CUSTOM_TEXT_CHARS {
+        return Err("Custom text is longer than 20000 characters");
+    }
+    Ok(typable)
+}
+
+/// Trims a team name and checks its length. The client and the server both run it.
+pub fn prepare_team_name(name: &str) -> Result<String, &'static str> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err("Team name is empty");
+    }
+    if name.chars().count() > MAX_TEAM_NAME_CHARS {
+        return Err("Team name is longer than 40 characters");
+    }
+    Ok(name)
+}
+
+/// Trims a GitHub login and a leading `@`, and checks it could be one: 1 to 39
+/// letters, digits or single hyphens, not at either end.
+pub fn prepare_github_login(login: &str) -> Result<String, &'static str> {
+    let login = login.trim().trim_start_matches('@');
+    let valid = (1..=39).contains(&login.len())
+        && login
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '-')
+        && !login.starts_with('-')
+        && !login.ends_with('-')
+        && !login.contains("--");
+    if !valid {
+        return Err("That is not a GitHub login");
+    }
+    Ok(login.to_string())
+}
+
+/// How a run ends. Stored as its [`TestMode::id`].
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum TestMode {
+    /// Ends after this many seconds, or earlier if the story runs out.
+    Timed(i64),
+    /// Ends after this many words of the story.
+    Words(usize),
+    /// Ends with the last word of the story.
+    Story,
+}
+
+impl TestMode {
+    pub fn all() -> Vec<Self> {
+        TIMED_MODE_SECONDS
+            .into_iter()
+            .map(Self::Timed)
+            .chain(WORD_MODE_COUNTS.into_iter().map(Self::Words))
+            .chain([Self::Story])
+            .collect()
+    }
+
+    /// Compact stable name: `t60`, `w25` or `story`.
+    pub fn id(self) -> String {
+        match self {
+            Self::Timed(seconds) => format!("t{seconds}"),
+            Self::Words(count) => format!("w{count}"),
+            Self::Story => "story".to_string(),
+        }
+    }
+
+    /// Accepts only the modes offered in [`TestMode::all`].
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|mode| mode.id() == value.trim().to_ascii_lowercase())
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Self::Timed(seconds) => format!("{seconds}s"),
+            Self::Words(count) => format!("{count} words"),
+            Self::Story => "Story".to_string(),
+        }
+    }
+
+    /// Seconds before the run ends on its own.
+    pub fn time_limit_seconds(self) -> i64 {
+        match self {
+            Self::Timed(seconds) => seconds,
+            Self::Words(_) | Self::Story => MAX_RUN_SECONDS,
+        }
+    }
+
+    pub fn is_timed(self) -> bool {
+        matches!(self, Self::Timed(_))
+    }
+
+    /// Words the run asks for out of a story of `story_len` words.
+    pub fn target_words(self, story_len: usize) -> usize {
+        match self {
+            Self::Words(count) => count.min(story_len),
+            Self::Timed(_) | Self::Story => story_len,
+        }
+    }
+}
+
+impl Default for TestMode {
+    fn default() -> Self {
+        Self::Timed(60)
+    }
+}
+
+impl From<TestMode> for String {
+    fn from(mode: TestMode) -> Self {
+        mode.id()
+    }
+}
+
+impl TryFrom<String> for TestMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value).ok_or_else(|| format!("Unknown test mode {value:?}"))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -57,11 +577,67 @@ pub struct TypingResult {
     pub correct_words: i64,
     pub wrong_words: i64,
     pub duration_seconds: i64,
+    /// Share of keystrokes that were not mistakes. Runs saved before errors
+    /// were counted per character stored the share of correct words.
     pub accuracy: f64,
+    /// Net WPM. Runs saved before character counting stored correct words per minute.
     pub wpm: f64,
+    /// Zero on runs saved before raw WPM, CPM and consistency were measured.
+    #[serde(default)]
+    pub raw_wpm: f64,
+    #[serde(default)]
+    pub cpm: f64,
+    #[serde(default)]
+    pub consistency: f64,
+    /// Zero on runs saved before errors were counted per character.
+    #[serde(default)]
+    pub corrected_errors: i64,
+    #[serde(default)]
+    pub uncorrected_errors: i64,
     pub score: i64,
     pub created_at: DateTime<Utc>,
     pub created_at_epoch_seconds: i64,
+    /// Runs saved before modes existed were all 60 second runs.
+    #[serde(default)]
+    pub mode: TestMode,
+    #[serde(default)]
+    pub text: TextKind,
+    #[serde(default)]
+    pub language: Language,
+    /// Runs saved before layouts were recorded count as QWERTY.
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
+    /// One point per second of the run; empty on runs saved before timelines.
+    #[serde(default)]
+    pub timeline: Vec<TimelinePoint>,
+    /// Where the run finished when it was typed in a live race.
+    #[serde(default)]
+    pub race: Option<RacePlacement>,
+}
+
+/// Speed at the end of one second of a run.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub struct TimelinePoint {
+    /// Seconds since the run started, from 1.
+    pub second: u32,
+    /// Net WPM over the run so far.
+    pub wpm: f64,
+    /// Gross WPM of the characters entered during this second alone.
+    pub raw_wpm: f64,
+    /// Wrong words committed during this second.
+    pub errors: u32,
+}
+
+impl TimelinePoint {
+    pub fn new(second: u32, correct_chars: i64, chars_this_second: u32, errors: u32) -> Self {
+        let minutes = f64::from(second.max(1)) / 60.0;
+        Self {
+            second,
+            wpm: correct_chars as f64 / minutes / CHARS_PER_WORD,
+            raw_wpm: f64::from(chars_this_second) * 60.0 / CHARS_PER_WORD,
+            errors,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -70,15 +646,741 @@ pub struct TypingSubmission {
     /// UTC challenge day (`YYYY-MM-DD`) this run belongs to.
     pub challenge_date: String,
     pub story_when: DateTime<Utc>,
-    pub correct_words: i64,
-    pub wrong_words: i64,
-    pub duration_seconds: i64,
+    pub mode: TestMode,
+    pub text: TextKind,
+    /// Language of the daily story the run typed.
+    #[serde(default)]
+    pub language: Language,
+    /// The layout the run was typed on.
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
+    /// `RunTicket::token` from `start_run`; `run_id` must be the ticket's.
+    pub ticket: String,
+    /// The passage typed when `text` is [`TextKind::Custom`] or [`TextKind::Drill`].
+    #[serde(default)]
+    pub custom_text: Option<String>,
+    /// The server recomputes word counts and duration from this log.
+    pub keystrokes: KeystrokeLog,
+    /// Join code of the live race the run was typed in.
+    #[serde(default)]
+    pub race_code: Option<String>,
+}
+
+/// Racers allowed in one race room.
+pub const MAX_RACERS: usize = 8;
+/// Time between the host starting a race and the first key being accepted.
+pub const RACE_COUNTDOWN_MS: u32 = 3_000;
+
+/// What everyone in a race room types: one challenge story, text and mode.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct RaceSpec {
+    /// UTC challenge day (`YYYY-MM-DD`).
+    pub challenge_date: String,
+    pub language: Language,
+    pub mode: TestMode,
+    pub text: TextKind,
+}
+
+/// One racer as the whole room sees them.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct RacerProgress {
+    pub racer_id: u32,
+    /// GitHub login, or `Guest` for players who are not signed in.
+    pub name: String,
+    /// Words committed, counted from the start of the story.
+    pub words_done: u32,
+    pub wpm: f64,
+    /// Position from 1 among the runs saved so far; a later run can still move it.
+    pub place: Option<u32>,
+}
+
+/// Sent by a racer over the race socket, as JSON.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaceClientMessage {
+    /// Opens a room on `spec` with the sender as its host.
+    Create {
+        spec: RaceSpec,
+    },
+    Join {
+        code: String,
+    },
+    /// Starts the countdown. Only the host can send it.
+    Start,
+    Progress {
+        words_done: u32,
+        wpm: f64,
+    },
+    /// The racer's run ended. Places come from saved runs, not from this.
+    Finish {
+        words_done: u32,
+        wpm: f64,
+    },
+}
+
+/// Sent by the server over the race socket, as JSON.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaceServerMessage {
+    Joined {
+        code: String,
+        racer_id: u32,
+        host: bool,
+        spec: RaceSpec,
+    },
+    /// Every racer in the room, after anyone joins, leaves or moves.
+    Racers {
+        racers: Vec<RacerProgress>,
+    },
+    /// The race starts for everyone `starts_in_ms` after this arrives.
+    Countdown {
+        starts_in_ms: u32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A saved run's finishing position in its race.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RacePlacement {
+    pub code: String,
+    pub place: u32,
+    /// Racers in the room when the run was saved.
+    pub racers: u32,
+}
+
+/// Issued by `start_run` when the first key of a run is pressed.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct RunTicket {
+    pub run_id: String,
+    /// Signed challenge day, story hash and issue time.
+    pub token: String,
+}
+
+/// One change to the typing input, stored as a diff against the previous value.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KeystrokeEvent {
+    /// Milliseconds since the run started.
+    pub at_ms: u32,
+    /// Key reported by the last `keydown`, empty when the change had none (paste, IME).
+    pub key: String,
+    /// Position of the word being typed, counted from the start of the story.
+    pub word_index: u32,
+    /// Characters removed from the end of the previous input.
+    pub removed: u32,
+    /// Text appended once `removed` characters are gone.
+    pub inserted: String,
+}
+
+/// A word as it stood when the player pressed space.
+#[cfg(any(feature = "server", test))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayedWord {
+    pub word_index: u32,
+    pub typed: String,
+    pub at_ms: u32,
+}
+
+/// Where a replayed run stood at one moment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GhostPosition {
+    /// The word being typed, counted from the start of the story.
+    pub word_index: u32,
+    /// Characters of that word entered so far.
+    pub typed_chars: usize,
+}
+
+/// A run's progress through its text over time, small enough to keep with
+/// every ranked run and race as a ghost.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GhostTimeline {
+    /// `(at_ms, word_index, typed_chars)` after each change that moved the run.
+    pub steps: Vec<(u32, u32, u32)>,
+}
+
+impl GhostTimeline {
+    /// Where the run stood `at_ms` milliseconds after it started.
+    pub fn position_at(&self, at_ms: u32) -> GhostPosition {
+        let reached = self
+            .steps
+            .partition_point(|&(step_ms, _, _)| step_ms <= at_ms);
+        match reached.checked_sub(1).map(|last| self.steps[last]) {
+            Some((_, word_index, typed_chars)) => GhostPosition {
+                word_index,
+                typed_chars: typed_chars as usize,
+            },
+            None => GhostPosition::default(),
+        }
+    }
+
+    /// Keeps only where the run stood at the end of each `step_ms` window,
+    /// which bounds the size of long runs kept on the device.
+    pub fn compact(&self, step_ms: u32) -> GhostTimeline {
+        let step_ms = step_ms.max(1);
+        let steps = self
+            .steps
+            .iter()
+            .enumerate()
+            .filter(|&(index, &(at_ms, _, _))| {
+                self.steps
+                    .get(index + 1)
+                    .is_none_or(|&(next_ms, _, _)| next_ms / step_ms != at_ms / step_ms)
+            })
+            .map(|(_, &step)| step)
+            .collect();
+        GhostTimeline { steps }
+    }
+}
+
+/// A ranked run's timeline, served so other players can race it. Stored
+/// under `run_ghosts/{run_id}`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct RunGhost {
+    pub run_id: String,
+    pub github_id: String,
+    pub login: String,
+    /// UTC challenge day (`YYYY-MM-DD`) whose story the run typed.
+    pub challenge_date: String,
+    pub mode: TestMode,
+    pub text: TextKind,
+    pub language: Language,
+    pub score: i64,
+    pub wpm: f64,
+    pub timeline: GhostTimeline,
+}
+
+/// Every input change of a run, in order.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KeystrokeLog {
+    pub events: Vec<KeystrokeEvent>,
+}
+
+impl KeystrokeLog {
+    /// Appends the change from `previous` to `current`. Unchanged input is ignored.
+    pub fn record(
+        &mut self,
+        at_ms: u32,
+        key: impl Into<String>,
+        word_index: u32,
+        previous: &str,
+        current: &str,
+    ) {
+        let shared = previous
+            .char_indices()
+            .zip(current.chars())
+            .take_while(|((_, before), after)| before == after)
+            .last()
+            .map(|((index, character), _)| index + character.len_utf8())
+            .unwrap_or(0);
+        let removed = previous[shared..].chars().count() as u32;
+        let inserted = &current[shared..];
+        if removed == 0 && inserted.is_empty() {
+            return;
+        }
+
+        self.events.push(KeystrokeEvent {
+            at_ms,
+            key: key.into(),
+            word_index,
+            removed,
+            inserted: inserted.to_string(),
+        });
+    }
+
+    /// Characters inserted during each of the first `seconds` seconds.
+    pub fn chars_per_second(&self, seconds: i64) -> Vec<u32> {
+        let mut buckets = vec![0; seconds.max(0) as usize];
+        for event in &self.events {
+            if let Some(bucket) = buckets.get_mut((event.at_ms / 1_000) as usize) {
+                *bucket += event.inserted.chars().count() as u32;
+            }
+        }
+        buckets
+    }
+
+    /// Rebuilds the typing field after every change and keeps where the run
+    /// stood, so it can be raced as a ghost.
+    pub fn ghost_timeline(&self) -> GhostTimeline {
+        let mut steps = Vec::<(u32, u32, u32)>::new();
+        let mut input = InputReplay::default();
+        for event in &self.events {
+            input.reopen_for(event);
+            input.remove(event.removed);
+            input.text.push_str(&event.inserted);
+            let word_index = match input.commit(event.word_index) {
+                Some(_) => event.word_index + 1,
+                None => event.word_index,
+            };
+            let typed_chars = input.text.chars().count() as u32;
+            if steps.last().is_none_or(|&(_, last_word, last_chars)| {
+                (last_word, last_chars) != (word_index, typed_chars)
+            }) {
+                steps.push((event.at_ms, word_index, typed_chars));
+            }
+        }
+        GhostTimeline { steps }
+    }
+
+    /// Milliseconds between the start of the run and the last logged change.
+    #[cfg(any(feature = "server", test))]
+    pub fn duration_ms(&self) -> i64 {
+        self.events
+            .last()
+            .map(|event| i64::from(event.at_ms))
+            .unwrap_or(0)
+    }
+
+    /// Rebuilds the input from the diffs and returns each word committed with
+    /// trailing whitespace, the same way the typing field does. A word the
+    /// player went back to is replaced by its new commit.
+    #[cfg(any(feature = "server", test))]
+    pub fn replay(&self) -> Vec<ReplayedWord> {
+        let mut words = Vec::new();
+        let mut input = InputReplay::default();
+        for event in &self.events {
+            if input.reopen_for(event) {
+                words.pop();
+            }
+            input.remove(event.removed);
+            input.text.push_str(&event.inserted);
+            if let Some(committed) = input.commit(event.word_index) {
+                words.push(ReplayedWord {
+                    word_index: event.word_index,
+                    typed: committed.trim().to_string(),
+                    at_ms: event.at_ms,
+                });
+            }
+        }
+        words
+    }
+
+    /// Hits, misses and latency for every character and pair of characters
+    /// the story asked for, separators included. Only characters `layout` has
+    /// a key for are counted, which keeps the totals to a few thousand pairs.
+    #[cfg(any(feature = "server", test))]
+    pub fn key_stats(&self, story_words: &[&str], layout: KeyboardLayout) -> KeyStats {
+        let mut keys = HashMap::new();
+        let mut bigrams = HashMap::new();
+        let mut input = InputReplay::default();
+        let mut last_at_ms = None::<u32>;
+        for event in &self.events {
+            input.reopen_for(event);
+            input.remove(event.removed);
+            let expected = story_words
+                .get(event.word_index as usize)
+                .map(|word| word.chars().chain([' ']).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let latency_ms = last_at_ms
+                .map(|last| event.at_ms.saturating_sub(last))
+                .filter(|&latency| latency <= MAX_KEY_LATENCY_MS);
+            for typed in event.inserted.chars() {
+                let position = input.text.chars().count();
+                input.text.push(typed);
+                let Some(&wanted) = expected
+                    .get(position)
+                    .filter(|&&wanted| layout.has_key_for(wanted))
+                else {
+                    continue;
+                };
+                let hit = typed == wanted || (wanted == ' ' && typed.is_whitespace());
+                record_key(&mut keys, wanted.to_string(), hit, latency_ms);
+                if let Some(previous) = position
+                    .checked_sub(1)
+                    .map(|index| expected[index])
+                    .filter(|&previous| layout.has_key_for(previous))
+                {
+                    record_key(&mut bigrams, format!("{previous}{wanted}"), hit, latency_ms);
+                }
+            }
+            last_at_ms = Some(event.at_ms);
+            input.commit(event.word_index);
+        }
+        let sorted = |stats: HashMap<String, KeyStat>| {
+            let mut stats = stats.into_values().collect::<Vec<_>>();
+            stats.sort_by(|left, right| left.key.cmp(&right.key));
+            stats
+        };
+        KeyStats {
+            keys: sorted(keys),
+            bigrams: sorted(bigrams),
+        }
+    }
+}
+
+/// The typing field as rebuilt from the log. Going back to the previous word
+/// shows up as a change to that word while the input is empty; it reopens
+/// the word with its separator, which the same change then deletes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct InputReplay {
+    text: String,
+    /// Inputs of committed words with their separator, newest last.
+    committed: Vec<(u32, String)>,
+}
+
+impl InputReplay {
+    /// Returns whether `event` reopened the last committed word.
+    fn reopen_for(&mut self, event: &KeystrokeEvent) -> bool {
+        if !self.text.is_empty()
+            || self
+                .committed
+                .last()
+                .is_none_or(|(word_index, _)| *word_index != event.word_index)
+        {
+            return false;
+        }
+        if let Some((_, text)) = self.committed.pop() {
+            self.text = text;
+        }
+        true
+    }
+
+    /// Removes up to `count` characters from the end and returns them, last first.
+    fn remove(&mut self, count: u32) -> Vec<(usize, char)> {
+        (0..count)
+            .map_while(|_| {
+                let removed = self.text.pop()?;
+                Some((self.text.chars().count(), removed))
+            })
+            .collect()
+    }
+
+    /// Commits the input once it ends with whitespace and returns it.
+    fn commit(&mut self, word_index: u32) -> Option<String> {
+        if !self.text.ends_with(char::is_whitespace) {
+            return None;
+        }
+        let committed = std::mem::take(&mut self.text);
+        self.committed.push((word_index, committed.clone()));
+        Some(committed)
+    }
+}
+
+/// How a character of the word being typed compares with the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharMark {
+    Correct,
+    /// Typed as something else; the expected character is shown.
+    Incorrect,
+    /// Not typed yet.
+    Pending,
+    /// Typed past the end of the word; the typed character is shown.
+    Extra,
+}
+
+/// Each character of `expected` marked against `typed`, followed by any
+/// characters typed beyond it.
+pub fn mark_characters(expected: &str, typed: &str) -> Vec<(char, CharMark)> {
+    let mut typed = typed.chars();
+    let mut marks = expected
+        .chars()
+        .map(|character| {
+            let mark = match typed.next() {
+                None => CharMark::Pending,
+                Some(entered) if entered == character => CharMark::Correct,
+                Some(_) => CharMark::Incorrect,
+            };
+            (character, mark)
+        })
+        .collect::<Vec<_>>();
+    marks.extend(typed.map(|extra| (extra, CharMark::Extra)));
+    marks
+}
+
+/// Whether `typed` at `position` differs from the expected word, whose
+/// separator accepts any whitespace.
+fn is_wrong_character(expected_word: &str, position: usize, typed: char) -> bool {
+    match expected_word.chars().nth(position) {
+        Some(wanted) => typed != wanted,
+        None => position != expected_word.chars().count() || !typed.is_whitespace(),
+    }
+}
+
+/// Wrong, missing and extra characters of a committed word.
+fn uncorrected_errors(expected_word: &str, committed: &str) -> i64 {
+    let typed = committed.trim_end().chars().collect::<Vec<_>>();
+    let expected = expected_word.chars().collect::<Vec<_>>();
+    (0..typed.len().max(expected.len()))
+        .filter(|&position| typed.get(position) != expected.get(position))
+        .count() as i64
+}
+
+/// Counts mistakes as the typing field changes. A wrong character deleted
+/// before its word is final is a corrected error; wrong, missing and extra
+/// characters left in committed words are uncorrected errors. The client
+/// feeds it each change as it is logged and the server replays the whole log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorTracker {
+    /// Characters typed, deleted ones included.
+    pub keystrokes: i64,
+    pub corrected_errors: i64,
+    pub uncorrected_errors: i64,
+    input: InputReplay,
+}
+
+impl ErrorTracker {
+    /// Applies one logged change to the word `expected_word`, the story word
+    /// at `event.word_index`.
+    pub fn apply(&mut self, event: &KeystrokeEvent, expected_word: &str) {
+        if self.input.reopen_for(event) {
+            self.uncorrected_errors -= uncorrected_errors(expected_word, &self.input.text);
+        }
+        for (position, removed) in self.input.remove(event.removed) {
+            if is_wrong_character(expected_word, position, removed) {
+                self.corrected_errors += 1;
+            }
+        }
+        self.keystrokes += event.inserted.chars().count() as i64;
+        self.input.text.push_str(&event.inserted);
+        if let Some(committed) = self.input.commit(event.word_index) {
+            self.uncorrected_errors += uncorrected_errors(expected_word, &committed);
+        }
+    }
+}
+
+/// Pauses longer than this between keystrokes are not counted as latency.
+#[cfg(any(feature = "server", test))]
+const MAX_KEY_LATENCY_MS: u32 = 2_000;
+
+#[cfg(any(feature = "server", test))]
+fn record_key(
+    stats: &mut HashMap<String, KeyStat>,
+    key: String,
+    hit: bool,
+    latency_ms: Option<u32>,
+) {
+    let stat = stats.entry(key.clone()).or_insert_with(|| KeyStat {
+        key,
+        ..KeyStat::default()
+    });
+    if hit {
+        stat.hits += 1;
+    } else {
+        stat.misses += 1;
+    }
+    if let Some(latency_ms) = latency_ms {
+        stat.latency_ms_total += i64::from(latency_ms);
+        stat.latency_samples += 1;
+    }
+}
+
+/// How often one character (or pair of characters) was typed right.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KeyStat {
+    pub key: String,
+    pub hits: i64,
+    pub misses: i64,
+    pub latency_ms_total: i64,
+    pub latency_samples: i64,
+}
+
+impl KeyStat {
+    pub fn attempts(&self) -> i64 {
+        self.hits + self.misses
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        if self.attempts() == 0 {
+            0.0
+        } else {
+            self.misses as f64 / self.attempts() as f64
+        }
+    }
+
+    pub fn mean_latency_ms(&self) -> Option<f64> {
+        (self.latency_samples > 0)
+            .then(|| self.latency_ms_total as f64 / self.latency_samples as f64)
+    }
+
+    fn add(&mut self, other: &KeyStat) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.latency_ms_total += other.latency_ms_total;
+        self.latency_samples += other.latency_samples;
+    }
+}
+
+/// Bigrams typed fewer times than this are too noisy to call weak.
+pub const MIN_BIGRAM_ATTEMPTS: i64 = 5;
+/// Pairs kept in a player's totals, the most typed ones first. Keeps the
+/// stored totals far below Firestore's 1 MiB document limit.
+#[cfg(any(feature = "server", test))]
+pub const MAX_BIGRAM_STATS: usize = 2_000;
+
+/// Per-user totals over every saved run, keyed by the expected character.
+/// Stored as lists because keys like `.` cannot be Firestore map keys.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KeyStats {
+    #[serde(default)]
+    pub keys: Vec<KeyStat>,
+    #[serde(default)]
+    pub bigrams: Vec<KeyStat>,
+}
+
+impl KeyStats {
+    /// Adds a run's counts to these totals, then drops the least typed pairs
+    /// past [`MAX_BIGRAM_STATS`].
+    #[cfg(any(feature = "server", test))]
+    pub fn merge(&mut self, run: &KeyStats) {
+        for (totals, additions) in [
+            (&mut self.keys, &run.keys),
+            (&mut self.bigrams, &run.bigrams),
+        ] {
+            let mut positions = totals
+                .iter()
+                .enumerate()
+                .map(|(index, stat)| (stat.key.clone(), index))
+                .collect::<HashMap<_, _>>();
+            for addition in additions {
+                match positions.get(&addition.key) {
+                    Some(&index) => totals[index].add(addition),
+                    None => {
+                        positions.insert(addition.key.clone(), totals.len());
+                        totals.push(addition.clone());
+                    }
+                }
+            }
+        }
+        if self.bigrams.len() > MAX_BIGRAM_STATS {
+            self.bigrams
+                .sort_by_key(|stat| std::cmp::Reverse(stat.attempts()));
+            self.bigrams.truncate(MAX_BIGRAM_STATS);
+        }
+    }
+
+    /// Counts for a key cap, both cases of a letter together.
+    pub fn for_key(&self, key: char) -> KeyStat {
+        let mut total = KeyStat {
+            key: key.to_string(),
+            ..KeyStat::default()
+        };
+        let lowercase = key.to_lowercase().to_string();
+        for stat in self
+            .keys
+            .iter()
+            .filter(|stat| stat.key.to_lowercase() == lowercase)
+        {
+            total.add(stat);
+        }
+        total
+    }
+
+    /// Bigrams with the highest miss rate, slowest first on ties.
+    pub fn weakest_bigrams(&self, limit: usize) -> Vec<&KeyStat> {
+        let mut bigrams = self
+            .bigrams
+            .iter()
+            .filter(|stat| stat.attempts() >= MIN_BIGRAM_ATTEMPTS && stat.misses > 0)
+            .collect::<Vec<_>>();
+        bigrams.sort_by(|left, right| {
+            right
+                .miss_rate()
+                .total_cmp(&left.miss_rate())
+                .then_with(|| {
+                    let latency = |stat: &KeyStat| stat.mean_latency_ms().unwrap_or(0.0);
+                    latency(right).total_cmp(&latency(left))
+                })
+        });
+        bigrams.truncate(limit);
+        bigrams
+    }
+}
+
+const DRILL_WORD_LIST: &str = include_str!("../assets/words/english.txt");
+/// Words in a drill; enough for the longest timed mode at a brisk pace.
+pub const DRILL_WORD_COUNT: usize = 120;
+/// Drills sample from this many of the words that best target weak keys.
+const DRILL_POOL_SIZE: usize = 120;
+
+/// How much a key or bigram slows the player down: its miss rate plus its
+/// mean latency in seconds. Untyped keys are neutral.
+fn weakness(stat: &KeyStat) -> f64 {
+    if stat.attempts() == 0 {
+        return 0.0;
+    }
+    stat.miss_rate() + stat.mean_latency_ms().unwrap_or(0.0) / 1_000.0
+}
+
+/// The bundled word list, the words that exercise the weakest keys and
+/// bigrams first.
+fn rank_drill_words(stats: &KeyStats) -> Vec<&'static str> {
+    let bigram_weakness = |pair: &str| {
+        stats
+            .bigrams
+            .iter()
+            .find(|stat| stat.key == pair)
+            .map_or(0.0, weakness)
+    };
+    let mut scored = DRILL_WORD_LIST
+        .split_whitespace()
+        .map(|word| {
+            let characters = word.chars().collect::<Vec<_>>();
+            let keys = characters
+                .iter()
+                .map(|&key| weakness(&stats.for_key(key)))
+                .sum::<f64>();
+            let bigrams = characters
+                .windows(2)
+                .map(|pair| bigram_weakness(&pair.iter().collect::<String>()))
+                .sum::<f64>();
+            (word, (keys + bigrams) / characters.len() as f64)
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by(|left, right| right.1.total_cmp(&left.1));
+    scored.into_iter().map(|(word, _)| word).collect()
+}
+
+/// Practice text weighted toward the keys and bigrams in `stats` that are
+/// missed most or typed slowest. `seed` varies the words between drills.
+pub fn drill_text(stats: &KeyStats, seed: u64) -> String {
+    let ranked = rank_drill_words(stats);
+    let pool = &ranked[..ranked.len().min(DRILL_POOL_SIZE)];
+    // xorshift64: enough to shuffle a word list, no dependency needed. The
+    // state must never be zero, and nearby seeds should not start alike.
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..DRILL_WORD_COUNT)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            pool[(state % pool.len() as u64) as usize]
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct PrivateProfile {
     pub user: UserProfile,
     pub history: Vec<TypingResult>,
+    #[serde(default)]
+    pub key_stats: KeyStats,
+    /// Teams the player belongs to, oldest first.
+    #[serde(default)]
+    pub teams: Vec<Team>,
+    /// Players this one follows, by login.
+    #[serde(default)]
+    pub following: Vec<FollowedPlayer>,
+}
+
+/// A GitHub account a player follows. Stored under
+/// `users/{id}/following/{github_id}`; the account may not have played yet.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FollowedPlayer {
+    pub github_id: String,
+    pub login: String,
+}
+
+/// A private group with its own day and week boards. Stored under `teams/{id}`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Team {
+    pub id: String,
+    pub name: String,
+    /// Anyone holding it can join, so it is only shown to members.
+    pub invite_code: String,
+    pub owner_id: String,
+    pub member_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
@@ -117,17 +1419,63 @@ pub struct LeaderboardEntry {
     pub avatar_url: String,
     pub score: i64,
     pub wpm: f64,
+    pub raw_wpm: f64,
+    pub cpm: f64,
+    pub consistency: f64,
     pub accuracy: f64,
     pub run_id: String,
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Leaderboard {
     pub scope: LeaderboardScope,
+    pub mode: TestMode,
+    pub text: TextKind,
+    #[serde(default)]
+    pub language: Language,
+    /// Set when the board only ranks runs typed on one layout.
+    #[serde(default)]
+    pub keyboard_layout: Option<KeyboardLayout>,
+    /// Set when the board only ranks one team's members.
+    #[serde(default)]
+    pub team_id: Option<String>,
+    /// Set when the board only ranks the viewer and the players they follow.
+    #[serde(default)]
+    pub friends: bool,
     pub board_id: String,
     pub label: String,
     pub challenge_date: Option<String>,
     pub entries: Vec<LeaderboardEntry>,
+    /// The signed-in player's place, when they have an entry on the board.
+    #[serde(default)]
+    pub around_me: Option<PlayerStanding>,
+    /// Where the next page starts, when more entries follow this page.
+    #[serde(default)]
+    pub next_cursor: Option<LeaderboardCursor>,
+}
+
+/// Where the signed-in player ranks on a board, with the entries just above
+/// and below their own.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PlayerStanding {
+    pub rank: i32,
+    /// Everyone ranked on the board.
+    pub players: i32,
+    /// Share of the other players ranked below this one, from 0 to 100.
+    pub percentile: f64,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[cfg(any(feature = "server", test))]
+impl PlayerStanding {
+    pub fn percentile_for(rank: i32, players: i32) -> f64 {
+        if players <= 1 {
+            return 100.0;
+        }
+        f64::from(players - rank) / f64::from(players - 1) * 100.0
+    }
 }
 
 /// Denormalized per-user best for a board. Stored under
@@ -140,14 +1488,45 @@ pub struct LeaderboardStoredEntry {
     pub avatar_url: String,
     pub score: i64,
     pub wpm: f64,
+    #[serde(default)]
+    pub raw_wpm: f64,
+    #[serde(default)]
+    pub cpm: f64,
+    #[serde(default)]
+    pub consistency: f64,
     pub accuracy: f64,
     pub run_id: String,
     pub challenge_date: String,
+    #[serde(default)]
+    pub mode: TestMode,
+    #[serde(default)]
+    pub text: TextKind,
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
     /// `score * 100_000 + round(wpm * 100)` so a single-field order breaks ties.
     pub sort_key: i64,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A place in a board's order: highest `sort_key` first, then the higher
+/// `github_id`, so every entry has exactly one rank. Leaderboard pages start
+/// after one.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LeaderboardCursor {
+    pub sort_key: i64,
+    pub github_id: String,
+}
+
+#[cfg(any(feature = "server", test))]
+impl LeaderboardCursor {
+    pub fn of(entry: &LeaderboardStoredEntry) -> Self {
+        Self {
+            sort_key: entry.sort_key,
+            github_id: entry.github_id.clone(),
+        }
+    }
+}
+
 #[cfg(any(feature = "server", test))]
 pub fn challenge_date_string(date: NaiveDate) -> String {
     date.format("%Y-%m-%d").to_string()
@@ -179,18 +1558,58 @@ pub fn leaderboard_sort_key(score: i64, wpm: f64) -> i64 {
 #[cfg(any(feature = "server", test))]
 pub fn recent_challenge_dates(today: NaiveDate, n: i64) -> Vec<NaiveDate> {
     let n = n.max(1);
-    (0..n).map(|offset| today - Duration::days(offset)).collect()
+    (0..n)
+        .map(|offset| today - Duration::days(offset))
+        .collect()
 }
 
+/// Each test mode and text kind competes on its own boards, e.g.
+/// `day-2026-07-20-v3-t15` or `day-2026-07-20-v3-t60-punctuated`. Stories in
+/// other languages than English add the language, as in
+/// `day-2026-07-20-v3-t60-punctuated-fr`; the code snippet is the same in every
+/// language and shares its boards. Default 60-second runs of the plain English
+/// story keep the bare period and [`BOARD_METRICS_VERSION`], as in
+/// `day-2026-07-20-v3`.
 #[cfg(any(feature = "server", test))]
-pub fn board_id_for_scope(scope: LeaderboardScope, date: NaiveDate) -> String {
-    match scope {
+pub fn board_id_for_scope(
+    scope: LeaderboardScope,
+    date: NaiveDate,
+    mode: TestMode,
+    text: TextKind,
+    language: Language,
+) -> String {
+    let period = match scope {
         LeaderboardScope::Day => day_board_id(date),
         LeaderboardScope::Week => week_board_id(date),
         LeaderboardScope::Global => GLOBAL_BOARD_ID.to_string(),
+    };
+    let period = format!("{period}-v{BOARD_METRICS_VERSION}");
+    let board = match text {
+        TextKind::Plain if mode == TestMode::default() => period,
+        TextKind::Plain => format!("{period}-{}", mode.id()),
+        other => format!("{period}-{}-{}", mode.id(), other.id()),
+    };
+    if language == Language::English || text == TextKind::Code {
+        board
+    } else {
+        format!("{board}-{}", language.id())
     }
 }
 
+/// A team's copy of a day or week board, e.g. `team-k3v9x2-day-2026-07-20-v3-t15`,
+/// ranking only the team's members.
+#[cfg(any(feature = "server", test))]
+pub fn team_board_id(team_id: &str, board_id: &str) -> String {
+    format!("team-{team_id}-{board_id}")
+}
+
+/// Every board also exists per keyboard layout, e.g. `day-2026-07-20-v3-azerty`,
+/// ranking only the runs typed on it.
+#[cfg(any(feature = "server", test))]
+pub fn layout_board_id(board_id: &str, layout: KeyboardLayout) -> String {
+    format!("{board_id}-{}", layout.id())
+}
+
 #[cfg(any(feature = "server", test))]
 pub fn leaderboard_label(scope: LeaderboardScope, date: NaiveDate) -> String {
     match scope {
@@ -208,40 +1627,302 @@ pub fn is_allowed_recent_day(day: NaiveDate, today: NaiveDate) -> bool {
     recent_challenge_dates(today, RECENT_LEADERBOARD_DAYS).contains(&day)
 }
 
+/// Characters that make up one word in WPM figures.
+pub const CHARS_PER_WORD: f64 = 5.0;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TypingMetrics {
+    /// Keystrokes minus corrected and uncorrected errors, over keystrokes.
     pub accuracy: f64,
+    /// Net WPM: correct characters per minute over [`CHARS_PER_WORD`].
     pub wpm: f64,
+    /// Gross WPM: every committed character, mistakes included.
+    pub raw_wpm: f64,
+    /// Correct characters per minute.
+    pub cpm: f64,
+    /// Percentage; 100 when every second of the run was typed at the same speed.
+    pub consistency: f64,
     pub score: i64,
 }
 
-pub fn calculate_typing_metrics(
-    correct_words: i64,
-    wrong_words: i64,
-    duration_seconds: i64,
-) -> Result<TypingMetrics, &'static str> {
-    let total_words = correct_words
-        .checked_add(wrong_words)
+/// Seconds a run that ended `elapsed_ms` after its first key is scored over.
+/// A started second counts in full, so a cut-off remainder never raises WPM.
+pub fn run_seconds(elapsed_ms: i64) -> i64 {
+    ((elapsed_ms + 999) / 1_000).clamp(1, MAX_RUN_SECONDS)
+}
+
+pub fn calculate_typing_metrics(run: &VerifiedRun) -> Result<TypingMetrics, &'static str> {
+    let total_words = run
+        .correct_words
+        .checked_add(run.wrong_words)
         .ok_or("Word count is too large")?;
 
-    if !(1..=600).contains(&duration_seconds) {
+    if !(1..=MAX_RUN_SECONDS).contains(&run.duration_seconds) {
         return Err("Duration must be between 1 and 600 seconds");
     }
-    if correct_words < 0 || wrong_words < 0 || !(1..=2_000).contains(&total_words) {
+    if run.correct_words < 0 || run.wrong_words < 0 || !(1..=2_000).contains(&total_words) {
         return Err("Word counts are invalid");
     }
+    if run.correct_chars < 0
+        || run.typed_chars < run.correct_chars
+        || run.keystrokes < 1
+        || run.corrected_errors < 0
+        || run.uncorrected_errors < 0
+    {
+        return Err("Character counts are invalid");
+    }
 
-    let accuracy = correct_words as f64 / total_words as f64;
-    let wpm = correct_words as f64 / (duration_seconds as f64 / 60.0);
+    let minutes = run.duration_seconds as f64 / 60.0;
+    // Every mistake costs accuracy, whether or not it was fixed.
+    let errors = run.corrected_errors + run.uncorrected_errors;
+    let accuracy = (run.keystrokes - errors).max(0) as f64 / run.keystrokes as f64;
+    let cpm = run.correct_chars as f64 / minutes;
+    let wpm = cpm / CHARS_PER_WORD;
+    let raw_wpm = run.typed_chars as f64 / minutes / CHARS_PER_WORD;
     let score = (wpm * accuracy).round() as i64;
 
     Ok(TypingMetrics {
         accuracy,
         wpm,
+        raw_wpm,
+        cpm,
+        consistency: consistency(&run.chars_per_second),
         score,
     })
 }
 
+/// One minus the coefficient of variation of the per-second raw WPM, as a
+/// percentage. Runs shorter than two seconds are perfectly consistent.
+fn consistency(chars_per_second: &[u32]) -> f64 {
+    if chars_per_second.len() < 2 {
+        return 100.0;
+    }
+    let speeds = chars_per_second
+        .iter()
+        .map(|&chars| f64::from(chars) * 60.0 / CHARS_PER_WORD)
+        .collect::<Vec<_>>();
+    let mean = speeds.iter().sum::<f64>() / speeds.len() as f64;
+    if mean <= 0.0 {
+        return 0.0;
+    }
+    let variance = speeds
+        .iter()
+        .map(|speed| (speed - mean).powi(2))
+        .sum::<f64>()
+        / speeds.len() as f64;
+    ((1.0 - variance.sqrt() / mean) * 100.0).clamp(0.0, 100.0)
+}
+
+/// What a run typed. The server recomputes it from the keystroke log; the
+/// client tallies the same counts as words are committed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedRun {
+    pub correct_words: i64,
+    pub wrong_words: i64,
+    /// Characters of correct words plus the space that commits each one.
+    pub correct_chars: i64,
+    /// Characters of every committed word plus its space.
+    pub typed_chars: i64,
+    pub duration_seconds: i64,
+    /// Characters entered during each second of the run.
+    pub chars_per_second: Vec<u32>,
+    pub timeline: Vec<TimelinePoint>,
+    /// Characters typed, deleted ones included.
+    pub keystrokes: i64,
+    pub corrected_errors: i64,
+    pub uncorrected_errors: i64,
+}
+
+/// Autocorrect and IME composition replace a typed word with up to the whole
+/// word and its separator. A change adding several characters is only
+/// accepted as such a replacement: it removes typed characters, the input it
+/// replaces covers the word but for one character, and what it keeps is where
+/// the word starts. Anything else was pasted or scripted.
+#[cfg(any(feature = "server", test))]
+fn check_replacements(log: &KeystrokeLog, story_words: &[&str]) -> Result<(), &'static str> {
+    let mut input = InputReplay::default();
+    for event in &log.events {
+        input.reopen_for(event);
+        let typed_chars = input.text.chars().count();
+        input.remove(event.removed);
+        let inserted = event.inserted.chars().count();
+        if inserted > MAX_UNREPLACED_INSERT_CHARS {
+            let word = story_words
+                .get(event.word_index as usize)
+                .copied()
+                .unwrap_or_default();
+            let word_chars = word.chars().count();
+            let replaces_typed_word = event.removed > 0
+                && typed_chars + 1 >= word_chars
+                && inserted <= word_chars + 1
+                && word.starts_with(input.text.as_str());
+            if !replaces_typed_word {
+                return Err("Pasted text is not accepted");
+            }
+        }
+        input.text.push_str(&event.inserted);
+        input.commit(event.word_index);
+    }
+    Ok(())
+}
+
+/// Rejects logs that insert characters faster than a person types, over any
+/// one second or over the whole run. Characters are counted rather than
+/// changes, so a change that replaces a word counts everything it adds.
+#[cfg(any(feature = "server", test))]
+fn check_typing_rate(log: &KeystrokeLog) -> Result<(), &'static str> {
+    const TOO_FAST: &str = "Keystroke timing is too fast to be human";
+    let inserted = log
+        .events
+        .iter()
+        .map(|event| event.inserted.chars().count() as u64)
+        .collect::<Vec<_>>();
+    let mut window_start = 0;
+    let mut window_chars = 0;
+    for (index, event) in log.events.iter().enumerate() {
+        window_chars += inserted[index];
+        while event.at_ms - log.events[window_start].at_ms >= 1_000 {
+            window_chars -= inserted[window_start];
+            window_start += 1;
+        }
+        if window_chars > u64::from(MAX_BURST_CHARS_PER_SECOND) {
+            return Err(TOO_FAST);
+        }
+    }
+
+    // The first change starts the clock, so its own characters are untimed.
+    let (Some(first), Some(last)) = (log.events.first(), log.events.last()) else {
+        return Ok(());
+    };
+    let span_ms = u64::from(last.at_ms - first.at_ms);
+    let timed_chars = inserted.iter().skip(1).sum::<u64>();
+    if span_ms > 0 && timed_chars * 1_000 > u64::from(MAX_AVERAGE_CHARS_PER_SECOND) * span_ms {
+        return Err(TOO_FAST);
+    }
+    Ok(())
+}
+
+/// Replays `log` against the story words and scores it the way the typing
+/// field does. Words committed after the mode's time limit are not counted.
+/// A run that types every word the mode asks for lasts until its last word;
+/// any other run lasts the whole time limit.
+#[cfg(any(feature = "server", test))]
+pub fn verify_keystroke_log(
+    log: &KeystrokeLog,
+    story_words: &[&str],
+    mode: TestMode,
+) -> Result<VerifiedRun, &'static str> {
+    if log.events.is_empty() {
+        return Err("Keystroke log is empty");
+    }
+    if log.events.len() > MAX_KEYSTROKE_EVENTS {
+        return Err("Keystroke log is too long");
+    }
+    if log
+        .events
+        .windows(2)
+        .any(|pair| pair[1].at_ms < pair[0].at_ms)
+    {
+        return Err("Keystroke timestamps go backwards");
+    }
+    check_replacements(log, story_words)?;
+    check_typing_rate(log)?;
+
+    let time_limit_seconds = mode.time_limit_seconds();
+    let time_limit_ms = (time_limit_seconds * 1_000) as u32;
+    let target_words = &story_words[..mode.target_words(story_words.len())];
+    let mut correct_words = 0;
+    let mut wrong_words = 0;
+    let mut correct_chars = 0;
+    let mut typed_chars = 0;
+    let mut last_commit_ms = 0;
+    // (commit time, correct characters it adds, whether it was wrong)
+    let mut commits = Vec::new();
+    for (position, word) in log.replay().iter().enumerate() {
+        if word.word_index as usize != position {
+            return Err("Keystroke log skips words of the story");
+        }
+        let Some(expected) = target_words.get(position) else {
+            return Err("Keystroke log is longer than the run");
+        };
+        if word.at_ms > time_limit_ms {
+            break;
+        }
+        let committed_chars = word.typed.chars().count() as i64 + 1;
+        let is_correct = word.typed == *expected;
+        if is_correct {
+            correct_words += 1;
+            correct_chars += committed_chars;
+        } else {
+            wrong_words += 1;
+        }
+        typed_chars += committed_chars;
+        last_commit_ms = word.at_ms;
+        commits.push((
+            word.at_ms,
+            if is_correct { committed_chars } else { 0 },
+            !is_correct,
+        ));
+    }
+
+    let finished = (correct_words + wrong_words) as usize == target_words.len();
+    let duration_seconds = if finished {
+        run_seconds(i64::from(last_commit_ms)).min(time_limit_seconds)
+    } else {
+        time_limit_seconds
+    };
+
+    let mut errors = ErrorTracker::default();
+    for event in log
+        .events
+        .iter()
+        .take_while(|event| event.at_ms <= time_limit_ms)
+    {
+        errors.apply(
+            event,
+            target_words
+                .get(event.word_index as usize)
+                .copied()
+                .unwrap_or_default(),
+        );
+    }
+
+    let chars_per_second = log.chars_per_second(duration_seconds);
+    // Words committed during the last, partial second count towards the last point.
+    let mut commits = commits.into_iter().peekable();
+    let mut correct_so_far = 0;
+    let timeline = (1..=duration_seconds)
+        .map(|second| {
+            let mut errors = 0;
+            while let Some((_, chars, wrong)) = commits.next_if(|(at_ms, _, _)| {
+                i64::from(*at_ms) < second * 1_000 || second == duration_seconds
+            }) {
+                correct_so_far += chars;
+                errors += u32::from(wrong);
+            }
+            TimelinePoint::new(
+                second as u32,
+                correct_so_far,
+                chars_per_second[second as usize - 1],
+                errors,
+            )
+        })
+        .collect();
+
+    Ok(VerifiedRun {
+        correct_words,
+        wrong_words,
+        correct_chars,
+        typed_chars,
+        duration_seconds,
+        chars_per_second,
+        timeline,
+        keystrokes: errors.keystrokes,
+        corrected_errors: errors.corrected_errors,
+        uncorrected_errors: errors.uncorrected_errors,
+    })
+}
+
 #[cfg(any(feature = "server", test))]
 pub fn validate_run_id(run_id: &str) -> Result<(), &'static str> {
     if !(8..=80).contains(&run_id.len())
@@ -257,27 +1938,84 @@ pub fn validate_run_id(run_id: &str) -> Result<(), &'static str> {
 #[cfg(test)]
 mod tests {
     use super::{
-        LeaderboardScope, board_id_for_scope, calculate_typing_metrics, challenge_date_string,
-        day_board_id, is_allowed_recent_day, leaderboard_sort_key, parse_challenge_date,
-        recent_challenge_dates, validate_run_id, week_board_id,
+        CharMark, DRILL_WORD_COUNT, DailyChallenge, KeyStat, KeyStats, KeyboardLayout,
+        KeystrokeEvent, KeystrokeLog, Language, LeaderboardScope, MAX_BIGRAM_STATS,
+        MAX_CUSTOM_TEXT_CHARS, PlayerStanding, Story, TestMode, TextKind, TimelinePoint,
+        VerifiedRun, board_id_for_scope, calculate_typing_metrics, challenge_date_string,
+        chunk_tokens, day_board_id, drill_text, is_allowed_recent_day, layout_board_id,
+        layout_tokens, leaderboard_sort_key, mark_characters, parse_challenge_date,
+        prepare_custom_text, prepare_github_login, prepare_team_name, rank_drill_words,
+        recent_challenge_dates, run_seconds, typable_text, validate_run_id, verify_keystroke_log,
+        week_board_id,
     };
     use chrono::NaiveDate;
 
+    const MINUTE: TestMode = TestMode::Timed(60);
+
+    fn counted_run(correct_words: i64, wrong_words: i64, duration_seconds: i64) -> VerifiedRun {
+        VerifiedRun {
+            correct_words,
+            wrong_words,
+            correct_chars: correct_words * 5,
+            typed_chars: (correct_words + wrong_words) * 5,
+            duration_seconds,
+            chars_per_second: vec![4; duration_seconds.max(0) as usize],
+            timeline: Vec::new(),
+            keystrokes: (correct_words + wrong_words) * 5,
+            corrected_errors: 0,
+            uncorrected_errors: wrong_words * 5,
+        }
+    }
+
     #[test]
     fn calculates_server_authoritative_metrics() {
-        let metrics = calculate_typing_metrics(45, 5, 60).unwrap();
+        let metrics = calculate_typing_metrics(&counted_run(45, 5, 60)).unwrap();
 
         assert!((metrics.accuracy - 0.9).abs() < f64::EPSILON);
         assert!((metrics.wpm - 45.0).abs() < f64::EPSILON);
+        assert!((metrics.raw_wpm - 50.0).abs() < f64::EPSILON);
+        assert!((metrics.cpm - 225.0).abs() < f64::EPSILON);
+        assert!((metrics.consistency - 100.0).abs() < f64::EPSILON);
         assert_eq!(metrics.score, 41);
     }
 
+    #[test]
+    fn wpm_counts_characters_not_words() {
+        let mut short_words = counted_run(10, 0, 60);
+        short_words.correct_chars = 40;
+        short_words.typed_chars = 40;
+        let mut long_words = short_words.clone();
+        long_words.correct_chars = 130;
+        long_words.typed_chars = 130;
+
+        let short = calculate_typing_metrics(&short_words).unwrap();
+        let long = calculate_typing_metrics(&long_words).unwrap();
+        assert!((short.wpm - 8.0).abs() < f64::EPSILON);
+        assert!((long.wpm - 26.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn consistency_falls_as_per_second_speed_varies() {
+        let mut steady = counted_run(10, 0, 4);
+        steady.chars_per_second = vec![5, 5, 5, 5];
+        let mut uneven = steady.clone();
+        uneven.chars_per_second = vec![1, 9, 1, 9];
+
+        let steady = calculate_typing_metrics(&steady).unwrap();
+        let uneven = calculate_typing_metrics(&uneven).unwrap();
+        assert!((steady.consistency - 100.0).abs() < f64::EPSILON);
+        assert!((uneven.consistency - 20.0).abs() < 1e-9);
+    }
+
     #[test]
     fn rejects_invalid_result_bounds() {
-        assert!(calculate_typing_metrics(0, 0, 60).is_err());
-        assert!(calculate_typing_metrics(10, 0, 0).is_err());
-        assert!(calculate_typing_metrics(-1, 2, 60).is_err());
-        assert!(calculate_typing_metrics(2_001, 0, 60).is_err());
+        assert!(calculate_typing_metrics(&counted_run(0, 0, 60)).is_err());
+        assert!(calculate_typing_metrics(&counted_run(10, 0, 0)).is_err());
+        assert!(calculate_typing_metrics(&counted_run(-1, 2, 60)).is_err());
+        assert!(calculate_typing_metrics(&counted_run(2_001, 0, 60)).is_err());
+        let mut overcounted = counted_run(10, 0, 60);
+        overcounted.correct_chars = overcounted.typed_chars + 1;
+        assert!(calculate_typing_metrics(&overcounted).is_err());
     }
 
     #[test]
@@ -290,19 +2028,225 @@ mod tests {
     #[test]
     fn builds_stable_board_ids() {
         let date = NaiveDate::from_ymd_opt(2026, 7, 20).unwrap();
+        let english = Language::English;
         assert_eq!(challenge_date_string(date), "2026-07-20");
         assert_eq!(day_board_id(date), "day-2026-07-20");
         assert_eq!(week_board_id(date), "week-2026-W30");
         assert_eq!(
-            board_id_for_scope(LeaderboardScope::Global, date),
-            "global"
+            board_id_for_scope(
+                LeaderboardScope::Day,
+                date,
+                MINUTE,
+                TextKind::Plain,
+                english
+            ),
+            "day-2026-07-20-v3"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Global,
+                date,
+                MINUTE,
+                TextKind::Plain,
+                english
+            ),
+            "global-v3"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Day,
+                date,
+                TestMode::Timed(15),
+                TextKind::Plain,
+                Language::French
+            ),
+            "day-2026-07-20-v3-t15-fr"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Week,
+                date,
+                TestMode::Words(25),
+                TextKind::Punctuated,
+                english
+            ),
+            "week-2026-W30-v3-w25-punctuated"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Global,
+                date,
+                TestMode::Story,
+                TextKind::Plain,
+                english
+            ),
+            "global-v3-story"
+        );
+        assert_eq!(
+            board_id_for_scope(LeaderboardScope::Day, date, MINUTE, TextKind::Code, english),
+            "day-2026-07-20-v3-t60-code"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Day,
+                date,
+                MINUTE,
+                TextKind::Punctuated,
+                Language::French
+            ),
+            "day-2026-07-20-v3-t60-punctuated-fr"
+        );
+        assert_eq!(
+            board_id_for_scope(
+                LeaderboardScope::Day,
+                date,
+                MINUTE,
+                TextKind::Code,
+                Language::German
+            ),
+            "day-2026-07-20-v3-t60-code"
+        );
+        assert_eq!(leaderboard_sort_key(41, 45.0), 41 * 100_000 + 4_500);
+    }
+
+    #[test]
+    fn every_layout_places_each_letter_on_one_key() {
+        for layout in KeyboardLayout::all() {
+            let keys = layout.rows().concat();
+            for letter in 'a'..='z' {
+                assert_eq!(
+                    keys.chars().filter(|&key| key == letter).count(),
+                    1,
+                    "{letter} on {}",
+                    layout.label()
+                );
+            }
+            assert_eq!(KeyboardLayout::parse(layout.id()), Some(layout));
+        }
+        assert_eq!(
+            layout_board_id("day-2026-07-20-t60-fr", KeyboardLayout::Azerty),
+            "day-2026-07-20-t60-fr-azerty"
+        );
+    }
+
+    #[test]
+    fn stories_without_a_language_are_english() {
+        let mut stored = serde_json::to_value(Story::fallback(Language::German)).unwrap();
+        assert_eq!(stored["language"], "de");
+        stored.as_object_mut().unwrap().remove("language");
+        let story: Story = serde_json::from_value(stored).unwrap();
+        assert_eq!(story.language, Language::English);
+        assert_eq!(Language::parse(" FR"), Some(Language::French));
+        assert_eq!(Language::parse("es"), None);
+    }
+
+    #[test]
+    fn punctuated_runs_type_the_raw_story_with_ascii_punctuation() {
+        let mut challenge = DailyChallenge::default();
+        assert_eq!(challenge.text(TextKind::Punctuated), challenge.story.story);
+
+        challenge.story.raw_story = Some("It’s “fast” — and safe…".to_string());
+        assert_eq!(
+            typable_text(challenge.text(TextKind::Punctuated)),
+            "It's \"fast\" - and safe..."
+        );
+        assert_eq!(challenge.text(TextKind::Plain), challenge.story.story);
+        assert_eq!(TextKind::parse("Punctuated"), Some(TextKind::Punctuated));
+    }
+
+    #[test]
+    fn custom_text_is_checked_and_never_ranked() {
+        assert_eq!(
+            prepare_custom_text("Hello,\r\n“world”").unwrap(),
+            "Hello,\n\"world\""
+        );
+        assert!(prepare_custom_text(" \n\t").is_err());
+        assert!(prepare_custom_text(&"a".repeat(MAX_CUSTOM_TEXT_CHARS + 1)).is_err());
+        assert_eq!(TextKind::parse("custom"), None);
+        assert!(!TextKind::Custom.is_ranked());
+        assert!(TextKind::all().into_iter().all(TextKind::is_ranked));
+    }
+
+    #[test]
+    fn team_names_are_trimmed_and_bounded() {
+        assert_eq!(
+            prepare_team_name("  Platform \n team ").unwrap(),
+            "Platform team"
         );
+        assert!(prepare_team_name(" \t").is_err());
+        assert!(prepare_team_name(&"é".repeat(40)).is_ok());
+        assert!(prepare_team_name(&"é".repeat(41)).is_err());
+    }
+
+    #[test]
+    fn github_logins_are_trimmed_and_checked() {
+        assert_eq!(prepare_github_login(" @wdroz ").unwrap(), "wdroz");
+        assert_eq!(prepare_github_login("Octo-Cat42").unwrap(), "Octo-Cat42");
+        assert!(prepare_github_login("").is_err());
+        assert!(prepare_github_login("-octocat").is_err());
+        assert!(prepare_github_login("octo--cat").is_err());
+        assert!(prepare_github_login("octo cat").is_err());
+        assert!(prepare_github_login(&"a".repeat(40)).is_err());
+    }
+
+    #[test]
+    fn percentiles_count_the_players_behind() {
+        assert_eq!(PlayerStanding::percentile_for(1, 1), 100.0);
+        assert_eq!(PlayerStanding::percentile_for(1, 6), 100.0);
+        assert_eq!(PlayerStanding::percentile_for(5, 6), 20.0);
+        assert_eq!(PlayerStanding::percentile_for(6, 6), 0.0);
+    }
+
+    #[test]
+    fn code_tokens_keep_their_line_layout() {
+        let code = "fn main() {\n    let x = 1;\n\tprintln!(\"{x}\");\n}\n";
+        let tokens = layout_tokens(code);
         assert_eq!(
-            leaderboard_sort_key(41, 45.0),
-            41 * 100_000 + 4_500
+            tokens
+                .iter()
+                .map(|token| token.word.as_str())
+                .collect::<Vec<_>>(),
+            code.split_whitespace().collect::<Vec<_>>()
+        );
+        assert!(tokens[0].line_start && !tokens[1].line_start);
+        assert!(tokens[3].line_start);
+        assert_eq!(tokens[3].indent, 4);
+        assert_eq!(tokens[7].indent, 4);
+
+        let by_line = chunk_tokens(&tokens, 4, true);
+        assert_eq!(by_line.iter().map(Vec::len).collect::<Vec<_>>(), [7, 2]);
+        assert!(by_line.iter().all(|chunk| chunk[0].line_start));
+        assert_eq!(chunk_tokens(&tokens, 4, false).len(), 3);
+
+        assert!(
+            DailyChallenge::default()
+                .text(TextKind::Code)
+                .contains("\n    ")
         );
     }
 
+    #[test]
+    fn enter_and_tab_commit_code_tokens() {
+        let story = ["let", "x", "=", "1;", "}"];
+        let mut log = KeystrokeLog::default();
+        let mut at_ms = 0;
+        for (word_index, (word, separator)) in
+            story.iter().zip([" ", "\t", " ", "\n", "\n"]).enumerate()
+        {
+            let mut input = String::new();
+            for character in format!("{word}{separator}").chars() {
+                let previous = input.clone();
+                input.push(character);
+                log.record(at_ms, "", word_index as u32, &previous, &input);
+                at_ms += 150;
+            }
+        }
+
+        let run = verify_keystroke_log(&log, &story, TestMode::Story).unwrap();
+        assert_eq!(run.correct_words, 5);
+        assert_eq!(run.wrong_words, 0);
+    }
+
     #[test]
     fn limits_recent_day_access_to_configured_window() {
         let today = NaiveDate::from_ymd_opt(2026, 7, 20).unwrap();
@@ -331,4 +2275,476 @@ mod tests {
         );
         assert_eq!(LeaderboardScope::parse("nope"), None);
     }
+
+    #[test]
+    fn records_input_changes_as_diffs() {
+        let mut log = KeystrokeLog::default();
+        log.record(0, "h", 0, "", "h");
+        log.record(90, "x", 0, "h", "hx");
+        log.record(180, "Backspace", 0, "hx", "h");
+        log.record(200, "Shift", 0, "h", "h");
+        log.record(260, "", 0, "h", "héllo");
+
+        assert_eq!(log.events.len(), 4);
+        assert_eq!(
+            log.events[2],
+            KeystrokeEvent {
+                at_ms: 180,
+                key: "Backspace".to_string(),
+                word_index: 0,
+                removed: 1,
+                inserted: String::new(),
+            }
+        );
+        assert_eq!(log.events[3].inserted, "éllo");
+    }
+
+    #[test]
+    fn replays_committed_words_with_corrections() {
+        let mut log = KeystrokeLog::default();
+        log.record(0, "t", 0, "", "t");
+        log.record(100, "e", 0, "t", "te");
+        log.record(200, "Backspace", 0, "te", "t");
+        log.record(300, "o", 0, "t", "to");
+        log.record(400, " ", 0, "to", "to ");
+        log.record(500, "b", 1, "", "b");
+        log.record(600, " ", 1, "b", "b ");
+        log.record(700, "x", 2, "", "x");
+
+        let words = log.replay();
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].typed, "to");
+        assert_eq!(words[0].at_ms, 400);
+        assert_eq!(words[1].word_index, 1);
+        assert_eq!(words[1].typed, "b");
+    }
+
+    fn typed_log(words: &[&str], interval_ms: u32) -> KeystrokeLog {
+        let mut log = KeystrokeLog::default();
+        let mut at_ms = 0;
+        for (word_index, word) in words.iter().enumerate() {
+            let mut input = String::new();
+            for character in format!("{word} ").chars() {
+                let previous = input.clone();
+                input.push(character);
+                log.record(
+                    at_ms,
+                    character.to_string(),
+                    word_index as u32,
+                    &previous,
+                    &input,
+                );
+                at_ms += interval_ms;
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn recomputes_counts_and_duration_from_the_log() {
+        let story = ["the", "quick", "brown", "fox"];
+
+        let partial =
+            verify_keystroke_log(&typed_log(&["the", "quikc"], 150), &story, MINUTE).unwrap();
+        assert_eq!(partial.correct_words, 1);
+        assert_eq!(partial.wrong_words, 1);
+        assert_eq!(partial.duration_seconds, 60);
+
+        assert_eq!(partial.correct_chars, 4);
+        assert_eq!(partial.typed_chars, 10);
+
+        let finished = verify_keystroke_log(&typed_log(&story, 150), &story, MINUTE).unwrap();
+        assert_eq!(finished.correct_words, 4);
+        assert_eq!(finished.correct_chars, 20);
+        assert_eq!(finished.duration_seconds, 3);
+        assert_eq!(finished.chars_per_second, [7, 7, 6]);
+    }
+
+    #[test]
+    fn started_seconds_count_in_full() {
+        assert_eq!(run_seconds(0), 1);
+        assert_eq!(run_seconds(1_000), 1);
+        assert_eq!(run_seconds(2_001), 3);
+        assert_eq!(run_seconds(2_990), 3);
+        assert_eq!(run_seconds(MAX_RUN_SECONDS * 1_000 + 500), MAX_RUN_SECONDS);
+
+        // Finishing 2.99 s in scores over 3 s, not 2.
+        let story = ["aaaa"; 5];
+        let mut log = typed_log(&story, 100);
+        log.events.last_mut().unwrap().at_ms = 2_990;
+        let run = verify_keystroke_log(&log, &story, TestMode::Story).unwrap();
+        assert_eq!(run.duration_seconds, 3);
+        assert_eq!(run.correct_chars, 25);
+        assert_eq!(calculate_typing_metrics(&run).unwrap().wpm, 100.0);
+    }
+
+    #[test]
+    fn timeline_samples_speed_and_errors_each_second() {
+        let story = ["the", "quick", "brown", "fox"];
+
+        let finished = verify_keystroke_log(&typed_log(&story, 150), &story, MINUTE).unwrap();
+        assert_eq!(
+            finished.timeline,
+            [
+                TimelinePoint::new(1, 4, 7, 0),
+                TimelinePoint::new(2, 20, 7, 0)
+            ]
+        );
+        assert!((finished.timeline[0].wpm - 48.0).abs() < f64::EPSILON);
+        assert!((finished.timeline[1].raw_wpm - 84.0).abs() < f64::EPSILON);
+
+        let partial =
+            verify_keystroke_log(&typed_log(&["the", "quikc"], 150), &story, MINUTE).unwrap();
+        assert_eq!(partial.timeline.len(), 60);
+        assert_eq!(partial.timeline[1].errors, 1);
+        assert_eq!(partial.timeline[59].raw_wpm, 0.0);
+        assert_eq!(
+            partial
+                .timeline
+                .iter()
+                .map(|point| point.errors)
+                .sum::<u32>(),
+            1
+        );
+    }
+
+    #[test]
+    fn key_stats_count_hits_misses_and_latency_per_expected_key() {
+        let mut log = KeystrokeLog::default();
+        log.record(0, "t", 0, "", "t");
+        log.record(100, "h", 0, "t", "th");
+        log.record(200, "w", 0, "th", "thw");
+        log.record(300, "Backspace", 0, "thw", "th");
+        log.record(400, "e", 0, "th", "the");
+        log.record(500, " ", 0, "the", "the ");
+        log.record(3_000, "f", 1, "", "f");
+        log.record(3_100, "o", 1, "f", "fo");
+        log.record(3_200, "x", 1, "fo", "fox");
+        log.record(3_300, " ", 1, "fox", "fox ");
+
+        let run = log.key_stats(&["the", "fox"], KeyboardLayout::Qwerty);
+        let e = run.for_key('e');
+        assert_eq!((e.hits, e.misses), (1, 1));
+        assert_eq!(e.mean_latency_ms(), Some(100.0));
+        assert_eq!(run.for_key(' ').hits, 2);
+        assert_eq!(run.for_key('f').mean_latency_ms(), None);
+        let he = run.bigrams.iter().find(|stat| stat.key == "he").unwrap();
+        assert_eq!((he.hits, he.misses), (1, 1));
+        assert!(run.bigrams.iter().all(|stat| stat.key != "ef"));
+
+        let mut totals = KeyStats::default();
+        assert!(totals.weakest_bigrams(3).is_empty());
+        for _ in 0..3 {
+            totals.merge(&run);
+        }
+        assert_eq!(totals.for_key('E').attempts(), 6);
+        let weakest = totals.weakest_bigrams(3);
+        assert_eq!(weakest.len(), 1);
+        assert_eq!(weakest[0].key, "he");
+    }
+
+    #[test]
+    fn key_stats_stay_bounded_whatever_is_typed() {
+        // Characters without a key on the layout, such as CJK, are not counted.
+        let mut log = KeystrokeLog::default();
+        log.record(0, "", 0, "", "漢");
+        log.record(150, "", 0, "漢", "漢字");
+        log.record(300, " ", 0, "漢字", "漢字 ");
+        assert_eq!(
+            log.key_stats(&["漢字"], KeyboardLayout::Qwerty),
+            KeyStats::default()
+        );
+
+        let layout = KeyboardLayout::Qwerty;
+        assert!(layout.has_key_for('Q') && layout.has_key_for(' '));
+        assert!(!layout.has_key_for('ß') && KeyboardLayout::Qwertz.has_key_for('ß'));
+
+        // Pairs of every character any layout types overflow the cap and get trimmed.
+        let characters = KeyboardLayout::all()
+            .into_iter()
+            .flat_map(|layout| layout.rows().concat().chars().collect::<Vec<_>>())
+            .flat_map(|character| [character, character.to_ascii_uppercase()])
+            .collect::<Vec<_>>();
+        let mut totals = KeyStats::default();
+        for first in &characters {
+            let run = KeyStats {
+                keys: Vec::new(),
+                bigrams: characters
+                    .iter()
+                    .map(|second| KeyStat {
+                        key: format!("{first}{second}"),
+                        hits: 1,
+                        ..KeyStat::default()
+                    })
+                    .collect(),
+            };
+            totals.merge(&run);
+        }
+        assert_eq!(totals.bigrams.len(), MAX_BIGRAM_STATS);
+        assert!(serde_json::to_string(&totals).unwrap().len() < 200_000);
+    }
+
+    #[test]
+    fn drills_favor_words_with_the_weakest_keys() {
+        let mut stats = KeyStats::default();
+        for (key, misses, latency_ms_total) in [("z", 6, 900), ("e", 0, 1_200), ("t", 0, 1_000)] {
+            stats.keys.push(KeyStat {
+                key: key.to_string(),
+                hits: 10 - misses,
+                misses,
+                latency_ms_total,
+                latency_samples: 10,
+            });
+        }
+
+        let ranked = rank_drill_words(&stats);
+        assert!(ranked[..10].iter().all(|word| word.contains('z')));
+
+        let drill = drill_text(&stats, 42);
+        assert_eq!(drill.split_whitespace().count(), DRILL_WORD_COUNT);
+        assert_eq!(drill, drill_text(&stats, 42));
+        assert_ne!(drill, drill_text(&stats, 43));
+        assert_eq!(prepare_custom_text(&drill), Ok(drill.clone()));
+        assert!(!TextKind::Drill.is_ranked());
+        assert!(TextKind::Drill.is_submitted());
+    }
+
+    #[test]
+    fn going_back_reopens_the_previous_word_and_counts_corrections() {
+        let story = ["the", "quick"];
+        let mut log = KeystrokeLog::default();
+        let mut at_ms = 0;
+        let mut change = |word_index: u32, previous: &str, current: &str| {
+            log.record(at_ms, "", word_index, previous, current);
+            at_ms += 150;
+        };
+        for (previous, current) in [("", "t"), ("t", "th"), ("th", "thw"), ("thw", "thw ")] {
+            change(0, previous, current);
+        }
+        // Backspace on the empty input of "quick" goes back to "thw ".
+        for (previous, current) in [
+            ("thw ", "thw"),
+            ("thw", "th"),
+            ("th", "the"),
+            ("the", "the "),
+        ] {
+            change(0, previous, current);
+        }
+        let mut input = String::new();
+        for character in "quick ".chars() {
+            let previous = input.clone();
+            input.push(character);
+            change(1, &previous, &input);
+        }
+
+        let run = verify_keystroke_log(&log, &story, MINUTE).unwrap();
+        assert_eq!((run.correct_words, run.wrong_words), (2, 0));
+        assert_eq!(run.keystrokes, 12);
+        assert_eq!((run.corrected_errors, run.uncorrected_errors), (1, 0));
+        assert!((metrics.accuracy - 11.0 / 12.0).abs() < f64::EPSILON);
+
+        let mut short = KeystrokeLog::default();
+        short.record(0, "", 0, "", "t");
+        short.record(150, "", 0, "t", "th");
+        short.record(300, "", 0, "th", "th ");
+        let run = verify_keystroke_log(&short, &story, MINUTE).unwrap();
+        assert_eq!((run.corrected_errors, run.uncorrected_errors), (0, 1));
+    }
+
+    #[test]
+    fn replays_a_run_up_to_a_moment_for_the_ghost() {
+        let mut log = KeystrokeLog::default();
+        for (at_ms, word_index, previous, current) in [
+            (0, 0, "", "t"),
+            (150, 0, "t", "th"),
+            (300, 0, "th", "thw"),
+            (450, 0, "thw", "thw "),
+            (600, 0, "thw ", "thw"),
+            (750, 0, "thw", "th"),
+            (900, 0, "th", "the"),
+            (1_050, 0, "the", "the "),
+            (1_200, 1, "", "q"),
+        ] {
+            log.record(at_ms, "", word_index, previous, current);
+        }
+
+        let timeline = log.ghost_timeline();
+        let at = |at_ms| {
+            let position = timeline.position_at(at_ms);
+            (position.word_index, position.typed_chars)
+        };
+        assert_eq!(at(0), (0, 1));
+        assert_eq!(at(449), (0, 3));
+        assert_eq!(at(450), (1, 0));
+        // Going back reopens the word the ghost already left.
+        assert_eq!(at(750), (0, 2));
+        assert_eq!(at(1_100), (1, 0));
+        assert_eq!(at(60_000), (1, 1));
+
+        let compact = timeline.compact(500);
+        assert_eq!(compact.steps, [(450, 1, 0), (900, 0, 3), (1_200, 1, 1)]);
+        assert_eq!(compact.position_at(60_000), timeline.position_at(60_000));
+    }
+
+    #[test]
+    fn marks_the_current_word_character_by_character() {
+        assert_eq!(
+            mark_characters("fox", "fa"),
+            [
+                ('f', CharMark::Correct),
+                ('o', CharMark::Incorrect),
+                ('x', CharMark::Pending)
+            ]
+        );
+        assert_eq!(
+            mark_characters("ox", "oxen"),
+            [
+                ('o', CharMark::Correct),
+                ('x', CharMark::Correct),
+                ('e', CharMark::Extra),
+                ('n', CharMark::Extra)
+            ]
+        );
+        assert!(
+            mark_characters("fox", "")
+                .iter()
+                .all(|(_, mark)| *mark == CharMark::Pending)
+        );
+    }
+
+    #[test]
+    fn word_and_story_modes_end_with_their_last_word() {
+        let story = ["the", "quick", "brown", "fox"];
+
+        let words = verify_keystroke_log(
+            &typed_log(&["the", "quick"], 150),
+            &story,
+            TestMode::Words(2),
+        )
+        .unwrap();
+        assert_eq!(words.correct_words, 2);
+        assert_eq!(words.duration_seconds, 2);
+        assert!(
+            verify_keystroke_log(&typed_log(&story[..3], 150), &story, TestMode::Words(2)).is_err()
+        );
+
+        let unfinished =
+            verify_keystroke_log(&typed_log(&story[..3], 150), &story, TestMode::Story).unwrap();
+        assert_eq!(unfinished.duration_seconds, 600);
+    }
+
+    #[test]
+    fn test_modes_round_trip_through_their_ids() {
+        assert_eq!(TestMode::all().len(), 9);
+        for mode in TestMode::all() {
+            assert_eq!(TestMode::parse(&mode.id()), Some(mode));
+            let json = serde_json::to_string(&mode).unwrap();
+            assert_eq!(serde_json::from_str::<TestMode>(&json).unwrap(), mode);
+        }
+        assert_eq!(TestMode::default().id(), "t60");
+        assert_eq!(TestMode::parse("w25"), Some(TestMode::Words(25)));
+        assert_eq!(TestMode::parse("t45"), None);
+        assert!(serde_json::from_str::<TestMode>("\"t45\"").is_err());
+    }
+
+    #[test]
+    fn ignores_words_committed_after_the_timer() {
+        let story = ["a"; 200];
+        let run = verify_keystroke_log(&typed_log(&story[..150], 250), &story, MINUTE).unwrap();
+
+        assert_eq!(run.correct_words, 120);
+        assert_eq!(run.duration_seconds, 60);
+    }
+
+    #[test]
+    fn accepts_dead_keys_and_autocorrect() {
+        let story = ["café", "the", "don't"];
+        let mut log = typed_log(&["caf"], 150);
+        log.events.pop();
+        // A composing dead key shows its accent, then the letter replaces it.
+        log.record(450, "Dead", 0, "caf", "caf´");
+        log.record(600, "e", 0, "caf´", "café");
+        log.record(750, " ", 0, "café", "café ");
+        log.record(900, "t", 1, "", "t");
+        log.record(1_050, "e", 1, "t", "te");
+        log.record(1_200, "h", 1, "te", "teh");
+        // Autocorrect fixes the word when space is pressed.
+        log.record(1_350, " ", 1, "teh", "the ");
+        log.record(1_500, "d", 2, "", "d");
+        log.record(1_650, "o", 2, "d", "do");
+        log.record(1_800, "n", 2, "do", "don");
+        // A dead apostrophe before a letter it does not accent inserts both.
+        log.record(1_950, "t", 2, "don", "don't");
+        log.record(2_100, " ", 2, "don't", "don't ");
+
+        let run = verify_keystroke_log(&log, &story, MINUTE).unwrap();
+        assert_eq!(run.correct_words, 3);
+        assert_eq!(run.wrong_words, 0);
+    }
+
+    #[test]
+    fn rejects_scripted_or_mismatched_logs() {
+        let story = ["the", "quick", "brown", "fox"];
+        assert!(verify_keystroke_log(&KeystrokeLog::default(), &story, MINUTE).is_err());
+        assert!(verify_keystroke_log(&typed_log(&story, 10), &story, MINUTE).is_err());
+        assert!(verify_keystroke_log(&typed_log(&["a"; 5], 150), &story, MINUTE).is_err());
+
+        let mut pasted = KeystrokeLog::default();
+        pasted.record(0, "", 0, "", "the ");
+        assert!(verify_keystroke_log(&pasted, &story, MINUTE).is_err());
+        let mut pasted_over = KeystrokeLog::default();
+        pasted_over.record(0, "t", 0, "", "t");
+        pasted_over.record(150, "", 0, "t", "the quick brown ");
+        assert!(verify_keystroke_log(&pasted_over, &story, MINUTE).is_err());
+
+        let mut skipped = typed_log(&["the"], 150);
+        skipped.record(900, "f", 2, "", "f");
+        skipped.record(1_050, " ", 2, "f", "f ");
+        assert!(verify_keystroke_log(&skipped, &story, MINUTE).is_err());
+
+        let mut rewound = typed_log(&["the"], 150);
+        rewound.events[2].at_ms = 100;
+        assert!(verify_keystroke_log(&rewound, &story, MINUTE).is_err());
+    }
+
+    #[test]
+    fn rejects_scripts_that_replace_one_letter_with_the_word() {
+        let story = ["the", "quick", "brown", "fox"];
+        // Types each word's first letter, then swaps it for the whole word.
+        let script = |gap_ms: u32| {
+            let mut log = KeystrokeLog::default();
+            for (word_index, word) in story.iter().enumerate() {
+                let at_ms = word_index as u32 * gap_ms * 2;
+                log.record(at_ms, &word[..1], word_index as u32, "", &word[..1]);
+                log.events.push(KeystrokeEvent {
+                    at_ms: at_ms + gap_ms,
+                    key: String::new(),
+                    word_index: word_index as u32,
+                    removed: 1,
+                    inserted: format!("{word} "),
+                });
+            }
+            log
+        };
+        assert_eq!(
+            verify_keystroke_log(&script(30), &story, MINUTE),
+            Err("Pasted text is not accepted")
+        );
+        assert!(verify_keystroke_log(&script(400), &story, MINUTE).is_err());
+
+        // Even with only single characters, the rate gives a script away.
+        let steady = typed_log(&["the", "quick", "brown", "fox"], 35);
+        assert_eq!(
+            verify_keystroke_log(&steady, &story, MINUTE),
+            Err("Keystroke timing is too fast to be human")
+        );
+        // A steady run with 44 characters crammed into half a second.
+        let long_story = ["the"; 40];
+        let mut burst = typed_log(&long_story, 150);
+        assert!(verify_keystroke_log(&burst, &long_story, MINUTE).is_ok());
+        for event in burst.events.iter_mut().skip(40).take(44) {
+            event.at_ms = 6_000 + (event.at_ms - 6_000) / 15;
+        }
+        assert!(verify_keystroke_log(&burst, &long_story, MINUTE).is_err());
+    }
 }
@@ -0,0 +1,126 @@
+//! The browser end of the race socket served at `/races/ws`.
+
+use crate::models::{RaceClientMessage, RaceServerMessage, RacerProgress};
+
+#[cfg(target_arch = "wasm32")]
+const RACE_SOCKET_PATH: &str = "/races/ws";
+
+/// What the race socket reports to the page.
+pub enum RaceEvent {
+    Message(RaceServerMessage),
+    /// The server closed the socket or the network dropped it.
+    Closed,
+}
+
+/// The room this player sits in, as the page shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinedRace {
+    pub code: String,
+    pub racer_id: u32,
+    pub host: bool,
+    /// Set once the host started the countdown.
+    pub started: bool,
+    pub racers: Vec<RacerProgress>,
+}
+
+impl JoinedRace {
+    pub fn place(&self) -> Option<u32> {
+        self.racers
+            .iter()
+            .find(|racer| racer.racer_id == self.racer_id)
+            .and_then(|racer| racer.place)
+    }
+}
+
+/// An open race socket. Dropping it closes the socket without reporting
+/// [`RaceEvent::Closed`].
+pub struct RaceConnection {
+    #[cfg(target_arch = "wasm32")]
+    socket: web_sys::WebSocket,
+    /// Open, message and close handlers, kept alive as long as the socket.
+    #[cfg(target_arch = "wasm32")]
+    _callbacks: (
+        wasm_bindgen::closure::Closure<dyn FnMut()>,
+        wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+        wasm_bindgen::closure::Closure<dyn FnMut()>,
+    ),
+}
+
+impl RaceConnection {
+    /// Connects to the server and sends `first` once the socket is open.
+    #[cfg(target_arch = "wasm32")]
+    pub fn open(
+        first: RaceClientMessage,
+        on_event: impl FnMut(RaceEvent) + Clone + 'static,
+    ) -> Option<Self> {
+        use wasm_bindgen::{JsCast, closure::Closure};
+
+        let location = web_sys::window()?.location();
+        let scheme = if location.protocol().ok()? == "https:" {
+            "wss"
+        } else {
+            "ws"
+        };
+        let host = location.host().ok()?;
+        let socket =
+            web_sys::WebSocket::new(&format!("{scheme}://{host}{RACE_SOCKET_PATH}")).ok()?;
+
+        let opened = socket.clone();
+        let on_open = Closure::<dyn FnMut()>::new(move || send_json(&opened, &first));
+        let mut on_message_event = on_event.clone();
+        let on_message = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| {
+                let Some(text) = event.data().as_string() else {
+                    return;
+                };
+                if let Ok(message) = serde_json::from_str(&text) {
+                    on_message_event(RaceEvent::Message(message));
+                }
+            },
+        );
+        let mut on_close_event = on_event;
+        let on_close = Closure::<dyn FnMut()>::new(move || on_close_event(RaceEvent::Closed));
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        Some(Self {
+            socket,
+            _callbacks: (on_open, on_message, on_close),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(
+        _first: RaceClientMessage,
+        _on_event: impl FnMut(RaceEvent) + Clone + 'static,
+    ) -> Option<Self> {
+        None
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn send(&self, message: &RaceClientMessage) {
+        send_json(&self.socket, message);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn send(&self, _message: &RaceClientMessage) {}
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for RaceConnection {
+    fn drop(&mut self) {
+        // The callbacks are freed with the connection, so the socket must not
+        // call them afterwards.
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn send_json(socket: &web_sys::WebSocket, message: &RaceClientMessage) {
+    if let Ok(json) = serde_json::to_string(message) {
+        let _ = socket.send_with_str(&json);
+    }
+}
@@ -0,0 +1,721 @@
+#![cfg(feature = "server")]
+
+//! Live race rooms. A player opens a room on a challenge story and shares its
+//! join code; the host starts a countdown that releases everyone at once, and
+//! each racer's progress is broadcast to the room while they type. Places come
+//! from the runs racers save, never from what their sockets report. Rooms live
+//! in process memory and disappear with their last connection.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{LazyLock, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::HeaderMap,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use oauth2::CsrfToken;
+use tokio::sync::mpsc::{Sender, channel};
+
+use crate::{
+    auth::authenticated_user_id,
+    models::{
+        MAX_RACERS, RACE_COUNTDOWN_MS, RaceClientMessage, RacePlacement, RaceServerMessage,
+        RaceSpec, RacerProgress, is_allowed_recent_day, parse_challenge_date,
+    },
+    storage::{Storage, StorageResult, storage},
+};
+
+const ROOM_CODE_LEN: usize = 6;
+const GUEST_NAME: &str = "Guest";
+/// Messages queued for one racer's socket. A racer who falls this far behind
+/// misses snapshots until they catch up; the next one supersedes them anyway.
+const OUTBOX_CAPACITY: usize = 32;
+/// Progress reports closer together than this are dropped.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// `code -> room`
+static ROOMS: LazyLock<Mutex<HashMap<String, RaceRoom>>> = LazyLock::new(Default::default);
+
+/// Room state is only touched in short synchronous sections, so a panic in
+/// one of them cannot leave a room half-updated in a way worth refusing.
+fn rooms() -> MutexGuard<'static, HashMap<String, RaceRoom>> {
+    ROOMS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Held while a saved run is ranked and the moved places are written, so two
+/// saves finishing together cannot store their places out of order.
+static PLACEMENT_WRITES: LazyLock<tokio::sync::Mutex<()>> = LazyLock::new(Default::default);
+
+/// A saved race run, as the server verified it.
+pub(crate) struct RaceRun {
+    pub(crate) run_id: String,
+    pub(crate) issued_at: DateTime<Utc>,
+    pub(crate) correct_words: i64,
+    /// Milliseconds from the first to the last logged keystroke change.
+    pub(crate) duration_ms: i64,
+}
+
+/// A racer's saved run and how long after the start it finished.
+struct FinishedRun {
+    run_id: String,
+    correct_words: i64,
+    finish_ms: i64,
+}
+
+struct Racer {
+    id: u32,
+    user_id: Option<String>,
+    name: String,
+    words_done: u32,
+    wpm: f64,
+    /// Set once the racer's socket reported the end of their run.
+    finished: bool,
+    last_progress: Option<Instant>,
+    place: Option<u32>,
+    /// The first run the racer saved in this race.
+    run: Option<FinishedRun>,
+    /// `None` once the racer's socket closed.
+    outbox: Option<Sender<RaceServerMessage>>,
+}
+
+struct RaceRoom {
+    spec: RaceSpec,
+    host: u32,
+    racers: Vec<Racer>,
+    /// When the countdown runs out, once the host started it.
+    starts_at: Option<DateTime<Utc>>,
+    next_racer_id: u32,
+}
+
+impl RaceRoom {
+    fn new(spec: RaceSpec) -> Self {
+        Self {
+            spec,
+            host: 1,
+            racers: Vec::new(),
+            starts_at: None,
+            next_racer_id: 1,
+        }
+    }
+
+    /// Seats a new racer and returns their ID. The first one hosts the room.
+    fn join(
+        &mut self,
+        user_id: Option<String>,
+        name: String,
+        outbox: Sender<RaceServerMessage>,
+    ) -> Result<u32, &'static str> {
+        if self.started() {
+            return Err("This race has already started");
+        }
+        if self.racers.len() >= MAX_RACERS {
+            return Err("This race room is full");
+        }
+        // Placements are saved per player, so a signed-in player holds one seat.
+        if user_id.is_some() && self.racers.iter().any(|racer| racer.user_id == user_id) {
+            return Err("You are already in this race");
+        }
+        let id = self.next_racer_id;
+        self.next_racer_id += 1;
+        if self.racers.is_empty() {
+            self.host = id;
+        }
+        self.racers.push(Racer {
+            id,
+            user_id,
+            name,
+            words_done: 0,
+            wpm: 0.0,
+            finished: false,
+            last_progress: None,
+            place: None,
+            run: None,
+            outbox: Some(outbox),
+        });
+        Ok(id)
+    }
+
+    /// Before the start a leaving racer gives up their seat and the host role;
+    /// afterwards the seat is kept so their place still counts.
+    fn leave(&mut self, racer_id: u32) {
+        if self.started() {
+            if let Some(racer) = self.racer_mut(racer_id) {
+                racer.outbox = None;
+            }
+            return;
+        }
+        self.racers.retain(|racer| racer.id != racer_id);
+        if self.host == racer_id
+            && let Some(next_host) = self.racers.first()
+        {
+            self.host = next_host.id;
+        }
+    }
+
+    fn is_abandoned(&self) -> bool {
+        self.racers.iter().all(|racer| racer.outbox.is_none())
+    }
+
+    fn started(&self) -> bool {
+        self.starts_at.is_some()
+    }
+
+    /// Starts the countdown at `now`.
+    fn start(&mut self, racer_id: u32, now: DateTime<Utc>) -> Result<(), &'static str> {
+        if racer_id != self.host {
+            return Err("Only the host can start the race");
+        }
+        if self.started() {
+            return Err("This race has already started");
+        }
+        self.starts_at = Some(now + chrono::Duration::milliseconds(i64::from(RACE_COUNTDOWN_MS)));
+        Ok(())
+    }
+
+    /// Shows how far the racer got. Reports arriving faster than
+    /// [`PROGRESS_INTERVAL`] are dropped; returns whether this one counted.
+    fn progress(&mut self, racer_id: u32, words_done: u32, wpm: f64, now: Instant) -> bool {
+        if !self.started() {
+            return false;
+        }
+        let Some(racer) = self
+            .racer_mut(racer_id)
+            .filter(|racer| !racer.finished)
+            .filter(|racer| {
+                racer
+                    .last_progress
+                    .is_none_or(|last| now.duration_since(last) >= PROGRESS_INTERVAL)
+            })
+        else {
+            return false;
+        };
+        racer.words_done = words_done;
+        racer.wpm = wpm.max(0.0);
+        racer.last_progress = Some(now);
+        true
+    }
+
+    /// Shows the racer's last progress and ignores any after it. Only a saved
+    /// run gives them a place.
+    fn finish(&mut self, racer_id: u32, words_done: u32, wpm: f64) {
+        if !self.started() {
+            return;
+        }
+        if let Some(racer) = self.racer_mut(racer_id).filter(|racer| !racer.finished) {
+            racer.words_done = words_done;
+            racer.wpm = wpm.max(0.0);
+            racer.finished = true;
+        }
+    }
+
+    /// Records the signed-in racer's first saved run and ranks every saved run
+    /// by correct words, then by how long after the start it finished. Returns
+    /// the racers whose place changed.
+    fn record_run(&mut self, user_id: &str, run: RaceRun) -> Vec<u32> {
+        let Some(starts_at) = self.starts_at else {
+            return Vec::new();
+        };
+        // A ticket from before the start could have been typed on during the
+        // countdown.
+        if run.issued_at < starts_at {
+            return Vec::new();
+        }
+        let Some(racer) = self
+            .racers
+            .iter_mut()
+            .find(|racer| racer.user_id.as_deref() == Some(user_id))
+            .filter(|racer| racer.run.is_none())
+        else {
+            return Vec::new();
+        };
+        racer.run = Some(FinishedRun {
+            run_id: run.run_id,
+            correct_words: run.correct_words,
+            finish_ms: (run.issued_at - starts_at).num_milliseconds() + run.duration_ms,
+        });
+
+        let mut ranked = self
+            .racers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, racer)| {
+                let run = racer.run.as_ref()?;
+                Some((Reverse(run.correct_words), run.finish_ms, index))
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_unstable();
+        let mut moved = Vec::new();
+        for (place, (_, _, index)) in (1..).zip(ranked) {
+            let racer = &mut self.racers[index];
+            if racer.place != Some(place) {
+                racer.place = Some(place);
+                moved.push(racer.id);
+            }
+        }
+        moved
+    }
+
+    fn racer_mut(&mut self, racer_id: u32) -> Option<&mut Racer> {
+        self.racers.iter_mut().find(|racer| racer.id == racer_id)
+    }
+
+    fn standings(&self) -> Vec<RacerProgress> {
+        self.racers
+            .iter()
+            .map(|racer| RacerProgress {
+                racer_id: racer.id,
+                name: racer.name.clone(),
+                words_done: racer.words_done,
+                wpm: racer.wpm,
+                place: racer.place,
+            })
+            .collect()
+    }
+
+    fn broadcast(&self, message: RaceServerMessage) {
+        for outbox in self.racers.iter().filter_map(|racer| racer.outbox.as_ref()) {
+            let _ = outbox.try_send(message.clone());
+        }
+    }
+
+    fn broadcast_standings(&self) {
+        self.broadcast(RaceServerMessage::Racers {
+            racers: self.standings(),
+        });
+    }
+}
+
+/// Places `user_id`'s saved `run` in race `code` if it was typed on `spec`,
+/// stores every place that moved and returns the run's own. A later run can
+/// still push it down; its stored place follows.
+pub(crate) async fn place_run(
+    storage: &dyn Storage,
+    code: &str,
+    user_id: &str,
+    spec: &RaceSpec,
+    run: RaceRun,
+) -> StorageResult<Option<RacePlacement>> {
+    let _writing = PLACEMENT_WRITES.lock().await;
+    let moved = {
+        let mut rooms = rooms();
+        let Some(room) = rooms.get_mut(code).filter(|room| room.spec == *spec) else {
+            return Ok(None);
+        };
+        let moved = room.record_run(user_id, run);
+        if !moved.is_empty() {
+            room.broadcast_standings();
+        }
+        let racers = room.racers.len() as u32;
+        room.racers
+            .iter()
+            .filter(|racer| moved.contains(&racer.id))
+            .filter_map(|racer| {
+                Some((
+                    racer.user_id.clone()?,
+                    racer.run.as_ref()?.run_id.clone(),
+                    RacePlacement {
+                        code: code.to_string(),
+                        place: racer.place?,
+                        racers,
+                    },
+                ))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut own = None;
+    for (racer_user_id, run_id, placement) in moved {
+        storage
+            .set_race_placement(&racer_user_id, &run_id, &placement)
+            .await?;
+        if racer_user_id == user_id {
+            own = Some(placement);
+        }
+    }
+    Ok(own)
+}
+
+/// One open race socket.
+struct Connection {
+    user_id: Option<String>,
+    name: String,
+    outbox: Sender<RaceServerMessage>,
+}
+
+pub(crate) async fn race_socket(upgrade: WebSocketUpgrade, headers: HeaderMap) -> Response {
+    let user_id = authenticated_user_id(&headers).await.ok().flatten();
+    let name = match &user_id {
+        Some(user_id) => storage()
+            .await
+            .get_user(user_id)
+            .await
+            .ok()
+            .flatten()
+            .map(|user| user.login),
+        None => None,
+    }
+    .unwrap_or_else(|| GUEST_NAME.to_string());
+    upgrade.on_upgrade(move |socket| serve_racer(socket, user_id, name))
+}
+
+async fn serve_racer(socket: WebSocket, user_id: Option<String>, name: String) {
+    let (mut sink, mut stream) = socket.split();
+    let (outbox, mut inbox) = channel::<RaceServerMessage>(OUTBOX_CAPACITY);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = inbox.recv().await {
+            let Ok(json) = serde_json::to_string(&message) else {
+                continue;
+            };
+            if sink.send(Message::Text(json.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Connection {
+        user_id,
+        name,
+        outbox,
+    };
+    let mut seat = None::<(String, u32)>;
+    while let Some(Ok(message)) = stream.next().await {
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let handled = serde_json::from_str::<RaceClientMessage>(text.as_str())
+            .map_err(|_| "Unreadable race message")
+            .and_then(|message| handle_message(&mut seat, &connection, message));
+        if let Err(message) = handled {
+            let _ = connection.outbox.try_send(RaceServerMessage::Error {
+                message: message.to_string(),
+            });
+        }
+    }
+
+    if let Some((code, racer_id)) = seat {
+        let mut rooms = rooms();
+        if let Some(room) = rooms.get_mut(&code) {
+            room.leave(racer_id);
+            if room.is_abandoned() {
+                rooms.remove(&code);
+            } else {
+                room.broadcast_standings();
+            }
+        }
+    }
+    writer.abort();
+}
+
+/// Applies one message from `connection`, seated at `seat` once it created or
+/// joined a room.
+fn handle_message(
+    seat: &mut Option<(String, u32)>,
+    connection: &Connection,
+    message: RaceClientMessage,
+) -> Result<(), &'static str> {
+    let mut rooms = rooms();
+    match message {
+        RaceClientMessage::Create { spec } => {
+            if seat.is_some() {
+                return Err("Leave your current race first");
+            }
+            validate_spec(&spec)?;
+            let code = loop {
+                let code = new_room_code();
+                if !rooms.contains_key(&code) {
+                    break code;
+                }
+            };
+            let mut room = RaceRoom::new(spec);
+            let racer_id = room.join(
+                connection.user_id.clone(),
+                connection.name.clone(),
+                connection.outbox.clone(),
+            )?;
+            let _ = connection.outbox.try_send(RaceServerMessage::Joined {
+                code: code.clone(),
+                racer_id,
+                host: true,
+                spec: room.spec.clone(),
+            });
+            room.broadcast_standings();
+            rooms.insert(code.clone(), room);
+            *seat = Some((code, racer_id));
+        }
+        RaceClientMessage::Join { code } => {
+            if seat.is_some() {
+                return Err("Leave your current race first");
+            }
+            let code = code.trim().to_ascii_uppercase();
+            let room = rooms.get_mut(&code).ok_or("No race room has this code")?;
+            let racer_id = room.join(
+                connection.user_id.clone(),
+                connection.name.clone(),
+                connection.outbox.clone(),
+            )?;
+            let _ = connection.outbox.try_send(RaceServerMessage::Joined {
+                code: code.clone(),
+                racer_id,
+                host: false,
+                spec: room.spec.clone(),
+            });
+            room.broadcast_standings();
+            *seat = Some((code, racer_id));
+        }
+        RaceClientMessage::Start => {
+            let (room, racer_id) = seated_room(&mut rooms, seat)?;
+            room.start(racer_id, Utc::now())?;
+            room.broadcast(RaceServerMessage::Countdown {
+                starts_in_ms: RACE_COUNTDOWN_MS,
+            });
+            room.broadcast_standings();
+        }
+        RaceClientMessage::Progress { words_done, wpm } => {
+            let (room, racer_id) = seated_room(&mut rooms, seat)?;
+            if room.progress(racer_id, words_done, wpm, Instant::now()) {
+                room.broadcast_standings();
+            }
+        }
+        RaceClientMessage::Finish { words_done, wpm } => {
+            let (room, racer_id) = seated_room(&mut rooms, seat)?;
+            room.finish(racer_id, words_done, wpm);
+            room.broadcast_standings();
+        }
+    }
+    Ok(())
+}
+
+fn seated_room<'a>(
+    rooms: &'a mut HashMap<String, RaceRoom>,
+    seat: &Option<(String, u32)>,
+) -> Result<(&'a mut RaceRoom, u32), &'static str> {
+    let (code, racer_id) = seat.as_ref().ok_or("Join a race first")?;
+    let room = rooms.get_mut(code).ok_or("This race room is gone")?;
+    Ok((room, *racer_id))
+}
+
+/// Races are run on the daily texts of a playable challenge day, so every
+/// racer fetches the same story and the results can be saved.
+fn validate_spec(spec: &RaceSpec) -> Result<(), &'static str> {
+    if !spec.text.is_ranked() {
+        return Err("Races use the daily texts");
+    }
+    let challenge_date =
+        parse_challenge_date(&spec.challenge_date).ok_or("Challenge day must use YYYY-MM-DD")?;
+    if !is_allowed_recent_day(challenge_date, Utc::now().date_naive()) {
+        return Err("This challenge day can no longer be raced");
+    }
+    Ok(())
+}
+
+/// Uppercase letters and digits, easy to read out to a friend.
+fn new_room_code() -> String {
+    std::iter::repeat_with(|| CsrfToken::new_random().secret().clone())
+        .flat_map(|random| random.into_bytes())
+        .filter(u8::is_ascii_alphanumeric)
+        .map(|byte| char::from(byte.to_ascii_uppercase()))
+        .filter(|character| !matches!(character, '0' | 'O' | '1' | 'I'))
+        .take(ROOM_CODE_LEN)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::Utc;
+    use tokio::sync::mpsc::channel;
+
+    use super::{
+        OUTBOX_CAPACITY, PROGRESS_INTERVAL, RaceRoom, RaceRun, new_room_code, place_run, rooms,
+    };
+    use crate::{
+        models::{
+            MAX_RACERS, RACE_COUNTDOWN_MS, RaceServerMessage, RaceSpec, TestMode, TextKind,
+            challenge_date_string,
+        },
+        storage::MemoryStorage,
+    };
+
+    fn spec() -> RaceSpec {
+        RaceSpec {
+            challenge_date: challenge_date_string(Utc::now().date_naive()),
+            language: Default::default(),
+            mode: TestMode::default(),
+            text: TextKind::Plain,
+        }
+    }
+
+    fn seat(room: &mut RaceRoom, user_id: &str) -> u32 {
+        let (outbox, _) = channel(OUTBOX_CAPACITY);
+        room.join(Some(user_id.to_string()), user_id.to_string(), outbox)
+            .unwrap()
+    }
+
+    /// A run whose ticket was issued `ticket_delay_ms` after the race started.
+    fn run(room: &RaceRoom, run_id: &str, correct_words: i64, ticket_delay_ms: i64) -> RaceRun {
+        RaceRun {
+            run_id: run_id.to_string(),
+            issued_at: room.starts_at.unwrap() + chrono::Duration::milliseconds(ticket_delay_ms),
+            correct_words,
+            duration_ms: 20_000,
+        }
+    }
+
+    #[test]
+    fn only_the_host_starts_and_nobody_joins_afterwards() {
+        let mut room = RaceRoom::new(spec());
+        let host = seat(&mut room, "1");
+        let guest = seat(&mut room, "2");
+        let (outbox, _) = channel(OUTBOX_CAPACITY);
+        assert!(
+            room.join(Some("2".to_string()), "2".to_string(), outbox)
+                .is_err()
+        );
+
+        let now = Utc::now();
+        assert!(room.start(guest, now).is_err());
+        assert!(room.start(host, now).is_ok());
+        assert!(room.start(host, now).is_err());
+        assert_eq!(
+            room.starts_at,
+            Some(now + chrono::Duration::milliseconds(i64::from(RACE_COUNTDOWN_MS)))
+        );
+        let (outbox, _) = channel(OUTBOX_CAPACITY);
+        assert!(room.join(None, "Guest".to_string(), outbox).is_err());
+    }
+
+    #[test]
+    fn rooms_fill_up_and_hand_over_the_host_before_the_start() {
+        let mut room = RaceRoom::new(spec());
+        let ids = (0..MAX_RACERS)
+            .map(|index| seat(&mut room, &index.to_string()))
+            .collect::<Vec<_>>();
+        let (outbox, _) = channel(OUTBOX_CAPACITY);
+        assert!(room.join(None, "Guest".to_string(), outbox).is_err());
+
+        room.leave(ids[0]);
+        assert_eq!(room.racers.len(), MAX_RACERS - 1);
+        assert_eq!(room.host, ids[1]);
+        assert!(room.start(ids[1], Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn progress_is_rate_limited_and_stops_at_the_finish() {
+        let mut room = RaceRoom::new(spec());
+        let host = seat(&mut room, "1");
+        let now = Instant::now();
+        assert!(!room.progress(host, 1, 10.0, now));
+        room.start(host, Utc::now()).unwrap();
+
+        assert!(room.progress(host, 2, 20.0, now));
+        assert!(!room.progress(host, 3, 30.0, now + Duration::from_millis(50)));
+        assert!(room.progress(host, 4, 40.0, now + PROGRESS_INTERVAL));
+        room.finish(host, 5, 50.0);
+        assert!(!room.progress(host, 1, 10.0, now + PROGRESS_INTERVAL * 2));
+
+        let standings = room.standings();
+        assert_eq!((standings[0].words_done, standings[0].place), (5, None));
+    }
+
+    #[test]
+    fn saved_runs_rank_by_words_then_finish_time() {
+        let mut room = RaceRoom::new(spec());
+        let first = seat(&mut room, "1");
+        let second = seat(&mut room, "2");
+        let third = seat(&mut room, "3");
+        let before_start = RaceRun {
+            run_id: "run-early".to_string(),
+            issued_at: Utc::now(),
+            correct_words: 50,
+            duration_ms: 1_000,
+        };
+        assert!(room.record_run("1", before_start).is_empty());
+        room.start(first, Utc::now()).unwrap();
+
+        // A ticket issued during the countdown never places.
+        let early = run(&room, "run-early", 50, -1);
+        assert!(room.record_run("1", early).is_empty());
+
+        let slow = run(&room, "run-a", 30, 900);
+        assert_eq!(room.record_run("1", slow), vec![first]);
+        let faster = run(&room, "run-b", 30, 300);
+        assert_eq!(room.record_run("2", faster), vec![second, first]);
+        let more_words = run(&room, "run-c", 31, 5_000);
+        assert_eq!(room.record_run("3", more_words), vec![third, second, first]);
+        // Only a racer's first saved run counts.
+        let again = run(&room, "run-d", 60, 0);
+        assert!(room.record_run("1", again).is_empty());
+
+        let places = room
+            .standings()
+            .iter()
+            .map(|racer| racer.place)
+            .collect::<Vec<_>>();
+        assert_eq!(places, vec![Some(3), Some(2), Some(1)]);
+        assert_eq!(
+            room.racers[0].run.as_ref().map(|run| run.run_id.as_str()),
+            Some("run-a")
+        );
+        // Placed racers keep their seat after their socket closes.
+        room.leave(second);
+        assert_eq!(room.racers.len(), 3);
+        room.leave(first);
+        assert!(!room.is_abandoned());
+        room.leave(third);
+        assert!(room.is_abandoned());
+    }
+
+    #[tokio::test]
+    async fn saved_runs_store_their_place_in_a_matching_race() {
+        let storage = MemoryStorage::default();
+        let code = new_room_code();
+        assert_eq!(code.len(), 6);
+        let mut room = RaceRoom::new(spec());
+        let (outbox, mut inbox) = channel(OUTBOX_CAPACITY);
+        let host = room
+            .join(Some("1".to_string()), "player1".to_string(), outbox)
+            .unwrap();
+        seat(&mut room, "2");
+        room.start(host, Utc::now()).unwrap();
+        let runs = [
+            run(&room, "run-a", 10, 0),
+            run(&room, "run-b", 10, 0),
+            run(&room, "run-c", 20, 0),
+        ];
+        rooms().insert(code.clone(), room);
+        let [run_a, run_b, run_c] = runs;
+
+        let mut other_mode = spec();
+        other_mode.mode = TestMode::Timed(15);
+        assert_eq!(
+            place_run(&storage, &code, "1", &other_mode, run_a)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            place_run(&storage, &code, "3", &spec(), run_b)
+                .await
+                .unwrap(),
+            None
+        );
+        let placement = place_run(&storage, &code, "1", &spec(), run_c)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((placement.place, placement.racers), (1, 2));
+        assert!(matches!(
+            inbox.try_recv(),
+            Ok(RaceServerMessage::Racers { .. })
+        ));
+        rooms().remove(&code);
+    }
+}
@@ -0,0 +1,377 @@
+//! Behavior every [`Storage`] has to share, checked against the memory and
+//! SQLite backends. Firestore needs a project, so it is left out.
+
+use chrono::{Duration, Utc};
+
+use super::{MemoryStorage, ResultWrite, Storage, TeamJoin, sqlite_store::SqliteStorage};
+use crate::{
+    auth::{OAuthStateRecord, SessionRecord},
+    models::{
+        FollowedPlayer, GhostTimeline, KeyStat, KeyStats, KeyboardLayout, Language,
+        LeaderboardCursor, LeaderboardStoredEntry, MAX_TEAM_MEMBERS, MAX_TEAMS_PER_PLAYER, Story,
+        Team, TestMode, TextKind, TypingResult, UserProfile,
+    },
+};
+
+fn backends() -> Vec<Box<dyn Storage>> {
+    vec![
+        Box::new(MemoryStorage::default()),
+        Box::new(SqliteStorage::open_in_memory().unwrap()),
+    ]
+}
+
+pub(super) fn profile(github_id: &str) -> UserProfile {
+    UserProfile {
+        github_id: github_id.to_string(),
+        login: format!("player{github_id}"),
+        login_lowercase: format!("player{github_id}"),
+        display_name: None,
+        avatar_url: String::new(),
+        created_at: Utc::now(),
+        last_login_at: Utc::now(),
+        total_runs: 0,
+        best_wpm: 0.0,
+        best_accuracy: 0.0,
+        best_score: 0,
+        keyboard_layout: None,
+        hide_ghosts: false,
+    }
+}
+
+pub(super) fn result(run_id: &str, score: i64, wpm: f64) -> TypingResult {
+    let created_at = Utc::now();
+    TypingResult {
+        run_id: run_id.to_string(),
+        story_title: "Daily story".to_string(),
+        story_when: Story::default().when,
+        correct_words: 40,
+        wrong_words: 0,
+        duration_seconds: 60,
+        accuracy: 1.0,
+        wpm,
+        raw_wpm: wpm,
+        cpm: wpm * 5.0,
+        consistency: 90.0,
+        corrected_errors: 0,
+        uncorrected_errors: 0,
+        score,
+        created_at,
+        created_at_epoch_seconds: created_at.timestamp(),
+        mode: TestMode::default(),
+        text: TextKind::Plain,
+        language: Language::English,
+        keyboard_layout: KeyboardLayout::Qwerty,
+        timeline: Vec::new(),
+        race: None,
+    }
+}
+
+/// A run of player `1` on the 2026-07-20 day board and the global board.
+pub(super) fn write(run_id: &str, score: i64) -> ResultWrite {
+    ResultWrite {
+        user_id: "1".to_string(),
+        result: result(run_id, score, score as f64),
+        challenge_date: "2026-07-20".to_string(),
+        board_ids: vec!["day-2026-07-20".to_string(), "global".to_string()],
+        key_stats: KeyStats {
+            keys: vec![KeyStat {
+                key: "a".to_string(),
+                hits: 3,
+                misses: 1,
+                latency_ms_total: 600,
+                latency_samples: 3,
+            }],
+            bigrams: Vec::new(),
+        },
+        ghost: Some(GhostTimeline {
+            steps: vec![(0, 0, 1), (400, 1, 0)],
+        }),
+        ghost_expires_at: Utc::now() + Duration::days(10),
+        ticket_expires_at: Utc::now() + Duration::minutes(15),
+    }
+}
+
+#[tokio::test]
+async fn oauth_states_are_single_use_and_sessions_round_trip() {
+    for storage in backends() {
+        let expires_at = Utc::now() + Duration::minutes(10);
+        storage
+            .put_oauth_state(
+                "state",
+                &OAuthStateRecord {
+                    pkce_verifier: "verifier".to_string(),
+                    expires_at,
+                    import_following: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let state = storage.take_oauth_state("state").await.unwrap().unwrap();
+        assert_eq!(state.pkce_verifier, "verifier");
+        assert_eq!(state.expires_at.timestamp(), expires_at.timestamp());
+        assert!(state.import_following);
+        assert!(storage.take_oauth_state("state").await.unwrap().is_none());
+        assert!(storage.take_oauth_state("unknown").await.unwrap().is_none());
+
+        storage
+            .put_session(
+                "session",
+                &SessionRecord {
+                    user_id: "1".to_string(),
+                    expires_at,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .get_session("session")
+                .await
+                .unwrap()
+                .unwrap()
+                .user_id,
+            "1"
+        );
+        storage.delete_session("session").await.unwrap();
+        assert!(storage.get_session("session").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn team_joins_respect_both_caps() {
+    for storage in backends() {
+        for id in ["1", "2"] {
+            storage.create_user(&profile(id)).await.unwrap();
+        }
+        let team = Team {
+            id: "k3v9x2".to_string(),
+            name: "Platform".to_string(),
+            invite_code: "invite".to_string(),
+            owner_id: "1".to_string(),
+            member_ids: vec!["1".to_string()],
+            created_at: Utc::now(),
+        };
+        storage.create_team(&team).await.unwrap();
+
+        assert_eq!(
+            storage.team_by_invite("invite").await.unwrap(),
+            Some(team.clone())
+        );
+        assert!(storage.teams_for_user("2").await.unwrap().is_empty());
+        let mut joined = team.clone();
+        joined.member_ids.push("2".to_string());
+        for _ in 0..2 {
+            assert_eq!(
+                storage.add_team_member("k3v9x2", "2").await.unwrap(),
+                TeamJoin::Joined(joined.clone())
+            );
+        }
+        let teams = storage.teams_for_user("2").await.unwrap();
+        assert_eq!(teams.len(), 1);
+        assert_eq!(teams[0].member_ids, ["1", "2"]);
+        assert_eq!(
+            storage.add_team_member("missing", "2").await.unwrap(),
+            TeamJoin::NoSuchTeam
+        );
+
+        let mut full = Team {
+            id: "full".to_string(),
+            invite_code: "full-invite".to_string(),
+            ..team.clone()
+        };
+        for index in 1..MAX_TEAM_MEMBERS {
+            let member_id = format!("m{index}");
+            storage.create_user(&profile(&member_id)).await.unwrap();
+            full.member_ids.push(member_id);
+        }
+        storage.create_team(&full).await.unwrap();
+        assert_eq!(
+            storage.add_team_member("full", "2").await.unwrap(),
+            TeamJoin::TeamFull
+        );
+        assert_eq!(
+            storage.add_team_member("full", "m1").await.unwrap(),
+            TeamJoin::Joined(full)
+        );
+
+        for index in 0..MAX_TEAMS_PER_PLAYER - 1 {
+            let other = Team {
+                id: format!("team{index}"),
+                invite_code: format!("invite{index}"),
+                ..team.clone()
+            };
+            storage.create_team(&other).await.unwrap();
+            assert!(matches!(
+                storage.add_team_member(&other.id, "2").await.unwrap(),
+                TeamJoin::Joined(_)
+            ));
+        }
+        assert_eq!(
+            storage.teams_for_user("2").await.unwrap().len(),
+            MAX_TEAMS_PER_PLAYER
+        );
+        assert_eq!(
+            storage.add_team_member("team0", "2").await.unwrap(),
+            TeamJoin::Joined(storage.team_by_invite("invite0").await.unwrap().unwrap())
+        );
+        let one_more = Team {
+            id: "one-more".to_string(),
+            invite_code: "one-more-invite".to_string(),
+            ..team.clone()
+        };
+        storage.create_team(&one_more).await.unwrap();
+        assert_eq!(
+            storage.add_team_member("one-more", "2").await.unwrap(),
+            TeamJoin::TooManyTeams
+        );
+    }
+}
+
+#[tokio::test]
+async fn follows_pick_the_entries_of_a_friends_board() {
+    for storage in backends() {
+        storage.create_user(&profile("1")).await.unwrap();
+        for login in ["player1", "PLAYER1"] {
+            assert_eq!(
+                storage
+                    .user_by_login(login)
+                    .await
+                    .unwrap()
+                    .map(|user| user.github_id),
+                Some("1".to_string())
+            );
+        }
+        assert!(storage.user_by_login("player2").await.unwrap().is_none());
+
+        let followed = |github_id: &str, login: &str| FollowedPlayer {
+            github_id: github_id.to_string(),
+            login: login.to_string(),
+        };
+        assert_eq!(
+            storage
+                .follow_players("1", vec![followed("3", "zed"), followed("2", "Amy")])
+                .await
+                .unwrap(),
+            [followed("3", "zed"), followed("2", "Amy")]
+        );
+        assert_eq!(
+            storage
+                .follow_players("1", vec![followed("3", "Bob"), followed("1", "player1")])
+                .await
+                .unwrap(),
+            [followed("3", "Bob")]
+        );
+        assert_eq!(
+            storage.followed_players("1").await.unwrap(),
+            [followed("2", "Amy"), followed("3", "Bob")]
+        );
+        storage.unfollow_player("1", "2").await.unwrap();
+        assert_eq!(
+            storage.followed_players("1").await.unwrap(),
+            [followed("3", "Bob")]
+        );
+
+        for (github_id, score) in [("1", 40), ("2", 60), ("3", 50)] {
+            if github_id != "1" {
+                storage.create_user(&profile(github_id)).await.unwrap();
+            }
+            storage
+                .save_typing_result(ResultWrite {
+                    user_id: github_id.to_string(),
+                    result: result(&format!("run-0000000{github_id}"), score, score as f64),
+                    ..write("", score)
+                })
+                .await
+                .unwrap();
+        }
+        let entries = storage
+            .leaderboard_entries_for("global", &["1".to_string(), "3".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.github_id.as_str())
+                .collect::<Vec<_>>(),
+            ["3", "1"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn boards_page_by_cursor_and_break_ties_by_github_id() {
+    for storage in backends() {
+        for (github_id, score) in [("1", 50), ("2", 40), ("3", 40), ("4", 30), ("5", 40)] {
+            storage.create_user(&profile(github_id)).await.unwrap();
+            storage
+                .save_typing_result(ResultWrite {
+                    user_id: github_id.to_string(),
+                    ..write(&format!("run-0000000{github_id}"), score)
+                })
+                .await
+                .unwrap();
+        }
+        let ids = |entries: Vec<LeaderboardStoredEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.github_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(storage.top_leaderboard_entries("global", 50).await.unwrap()),
+            ["1", "5", "3", "2", "4"]
+        );
+
+        // Two entries a page, each page following the last entry of the one before.
+        let first_page = storage.top_leaderboard_entries("global", 2).await.unwrap();
+        let mut cursor = first_page.last().map(LeaderboardCursor::of);
+        let mut pages = vec![ids(first_page)];
+        while let Some(after) = cursor {
+            let page = storage
+                .leaderboard_entries_after("global", &after, 2)
+                .await
+                .unwrap();
+            cursor = page.last().map(LeaderboardCursor::of);
+            pages.push(ids(page));
+        }
+        assert_eq!(pages, [vec!["1", "5"], vec!["3", "2"], vec!["4"], vec![]]);
+
+        let third = LeaderboardCursor::of(
+            &storage
+                .leaderboard_entry("global", "3")
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+        assert_eq!(
+            storage
+                .count_leaderboard_entries("global", Some(&third))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            storage
+                .count_leaderboard_entries("global", None)
+                .await
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            ids(storage
+                .leaderboard_entries_before("global", &third, 3)
+                .await
+                .unwrap()),
+            ["1", "5"]
+        );
+        assert_eq!(
+            ids(storage
+                .leaderboard_entries_after("global", &third, 3)
+                .await
+                .unwrap()),
+            ["2", "4"]
+        );
+    }
+}
@@ -0,0 +1,889 @@
+use std::env;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use firestore::{
+    FirestoreDb, FirestoreDbOptions, FirestoreQueryCursor, FirestoreQueryDirection,
+    FirestoreQueryFilter, FirestoreQueryFilterBuilder, FirestoreTimestamp, errors::FirestoreError,
+    path, paths,
+};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ChallengeCache, ResultWrite, Storage, StorageError, StorageResult, TeamJoin,
+    apply_result_to_profile, follows_within_limit, improves_entry, leaderboard_candidate,
+    profile_missing, run_ghost, sort_in_board_order, team_join_refusal,
+};
+use crate::{
+    auth::{OAuthStateRecord, SessionRecord},
+    models::{
+        CodeSnippet, FollowedPlayer, KeyStats, KeyboardLayout, Language, LeaderboardCursor,
+        LeaderboardStoredEntry, RacePlacement, RunGhost, Story, Team, TypingResult, UserProfile,
+    },
+};
+
+const TEXTS_COLLECTION: &str = "texts";
+const SNIPPETS_COLLECTION: &str = "snippets";
+const USERS_COLLECTION: &str = "users";
+const TYPING_RESULTS_COLLECTION: &str = "typing_results";
+const FOLLOWING_COLLECTION: &str = "following";
+const KEY_STATS_COLLECTION: &str = "key_stats";
+const RUN_GHOSTS_COLLECTION: &str = "run_ghosts";
+const TEAMS_COLLECTION: &str = "teams";
+const LEADERBOARDS_COLLECTION: &str = "leaderboards";
+const LEADERBOARD_ENTRIES_COLLECTION: &str = "entries";
+const OAUTH_STATES_COLLECTION: &str = "oauth_states";
+const SESSIONS_COLLECTION: &str = "sessions";
+const RUN_TICKETS_COLLECTION: &str = "run_tickets";
+
+/// Marker for a run ticket that was already presented, written with the run it
+/// saved. `expires_at` can back a Firestore TTL policy.
+#[derive(Debug, Serialize, Deserialize)]
+struct UsedRunTicket {
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    expires_at: DateTime<Utc>,
+}
+
+/// A ghost as stored under `run_ghosts/{run_id}`. `expires_at` can back a
+/// Firestore TTL policy; until it runs, expired ghosts are not served.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRunGhost {
+    ghost: RunGhost,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    expires_at: DateTime<Utc>,
+}
+
+/// Result row of a count aggregation.
+#[derive(Debug, Deserialize)]
+struct EntryCount {
+    count: u32,
+}
+
+impl From<FirestoreError> for StorageError {
+    fn from(error: FirestoreError) -> Self {
+        StorageError::new(error.to_string())
+    }
+}
+
+pub(crate) struct FirestoreStorage {
+    db: FirestoreDb,
+    challenge_cache: ChallengeCache,
+}
+
+impl FirestoreStorage {
+    pub(crate) async fn connect() -> Self {
+        let project_id = env::var("PROJECT_ID").expect("PROJECT_ID not set");
+        let database_id = env::var("DATABASE_ID").expect("DATABASE_ID not set");
+
+        // Check for the "IAMTHEDEV" environment variable
+        if env::var("IAMTHEDEV").is_err() {
+            let db = FirestoreDb::with_options(
+                FirestoreDbOptions::new(project_id).with_database_id(database_id),
+            )
+            .await
+            .expect("Failed to initialize FirestoreDb using PROJECT_ID and DATABASE_ID");
+            return Self::with_db(db);
+        }
+
+        // Initialize Firestore client using service account key file
+        let db = FirestoreDb::with_options_service_account_key_file(
+            FirestoreDbOptions::new(project_id).with_database_id(database_id),
+            "key.json".into(),
+        )
+        .await
+        .expect("Failed to initialize FirestoreDb using service account key file");
+
+        Self::with_db(db)
+    }
+
+    fn with_db(db: FirestoreDb) -> Self {
+        Self {
+            db,
+            challenge_cache: ChallengeCache::default(),
+        }
+    }
+
+    /// Counts the board's entries that pass `filter`.
+    async fn count_entries<F>(&self, board_id: &str, filter: F) -> StorageResult<u32>
+    where
+        F: Fn(FirestoreQueryFilterBuilder) -> Option<FirestoreQueryFilter> + Send + Sync,
+    {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        let counts: Vec<EntryCount> = self
+            .db
+            .fluent()
+            .select()
+            .from(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .filter(filter)
+            .aggregate(|a| a.fields([a.field(path!(EntryCount::count)).count()]))
+            .obj()
+            .query()
+            .await?;
+        Ok(counts.first().map_or(0, |counts| counts.count))
+    }
+}
+
+#[async_trait]
+impl Storage for FirestoreStorage {
+    fn challenge_cache(&self) -> &ChallengeCache {
+        &self.challenge_cache
+    }
+
+    async fn latest_story_before(
+        &self,
+        cutoff: DateTime<Utc>,
+        language: Language,
+    ) -> StorageResult<Option<Story>> {
+        // Needs the `texts` composite index on `language` and `when` descending.
+        let mut story_stream = self
+            .db
+            .fluent()
+            .select()
+            .from(TEXTS_COLLECTION)
+            .filter(|q| {
+                q.for_all([
+                    q.field("language").eq(language.id()),
+                    q.field("when").less_than(FirestoreTimestamp(cutoff)),
+                ])
+            })
+            .order_by([("when", FirestoreQueryDirection::Descending)])
+            .limit(1)
+            .obj::<Story>()
+            .stream_query()
+            .await?;
+
+        Ok(story_stream.next().await)
+    }
+
+    async fn latest_snippet_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> StorageResult<Option<CodeSnippet>> {
+        let mut snippet_stream = self
+            .db
+            .fluent()
+            .select()
+            .from(SNIPPETS_COLLECTION)
+            .filter(|q| q.field("when").less_than(FirestoreTimestamp(cutoff)))
+            .order_by([("when", FirestoreQueryDirection::Descending)])
+            .limit(1)
+            .obj::<CodeSnippet>()
+            .stream_query()
+            .await?;
+
+        Ok(snippet_stream.next().await)
+    }
+
+    async fn get_user(&self, user_id: &str) -> StorageResult<Option<UserProfile>> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(USERS_COLLECTION)
+            .obj::<UserProfile>()
+            .one(user_id)
+            .await?)
+    }
+
+    async fn create_user(&self, profile: &UserProfile) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .update()
+            .in_col(USERS_COLLECTION)
+            .document_id(&profile.github_id)
+            .object(profile)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    /// Profiles saved before `login_lowercase` existed get it when their
+    /// player next signs in, or from `content_updater --backfill-logins`.
+    async fn user_by_login(&self, login: &str) -> StorageResult<Option<UserProfile>> {
+        let mut user_stream = self
+            .db
+            .fluent()
+            .select()
+            .from(USERS_COLLECTION)
+            .filter(|q| q.field("login_lowercase").eq(login.to_lowercase()))
+            .limit(1)
+            .obj::<UserProfile>()
+            .stream_query()
+            .await?;
+
+        Ok(user_stream.next().await)
+    }
+
+    async fn update_user_identity(&self, profile: &UserProfile) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .update()
+            .fields(paths!(UserProfile::{
+                login,
+                login_lowercase,
+                display_name,
+                avatar_url,
+                last_login_at
+            }))
+            .in_col(USERS_COLLECTION)
+            .document_id(&profile.github_id)
+            .object(profile)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn set_keyboard_layout(
+        &self,
+        user_id: &str,
+        layout: KeyboardLayout,
+    ) -> StorageResult<()> {
+        let mut profile = self
+            .get_user(user_id)
+            .await?
+            .ok_or_else(|| profile_missing(user_id))?;
+        profile.keyboard_layout = Some(layout);
+        self.db
+            .fluent()
+            .update()
+            .fields(paths!(UserProfile::{keyboard_layout}))
+            .in_col(USERS_COLLECTION)
+            .document_id(user_id)
+            .object(&profile)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn set_hide_ghosts(&self, user_id: &str, hide_ghosts: bool) -> StorageResult<()> {
+        let mut profile = self
+            .get_user(user_id)
+            .await?
+            .ok_or_else(|| profile_missing(user_id))?;
+        profile.hide_ghosts = hide_ghosts;
+        self.db
+            .fluent()
+            .update()
+            .fields(paths!(UserProfile::{hide_ghosts}))
+            .in_col(USERS_COLLECTION)
+            .document_id(user_id)
+            .object(&profile)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn recent_typing_results(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<TypingResult>> {
+        let parent = self.db.parent_path(USERS_COLLECTION, user_id)?;
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .from(TYPING_RESULTS_COLLECTION)
+            .parent(&parent)
+            .order_by([(
+                "created_at_epoch_seconds",
+                FirestoreQueryDirection::Descending,
+            )])
+            .limit(limit)
+            .obj::<TypingResult>()
+            .query()
+            .await?)
+    }
+
+    async fn top_leaderboard_entries(
+        &self,
+        board_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .from(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .order_by([
+                ("sort_key", FirestoreQueryDirection::Descending),
+                ("github_id", FirestoreQueryDirection::Descending),
+            ])
+            .limit(limit)
+            .obj::<LeaderboardStoredEntry>()
+            .query()
+            .await?)
+    }
+
+    async fn leaderboard_entry(
+        &self,
+        board_id: &str,
+        github_id: &str,
+    ) -> StorageResult<Option<LeaderboardStoredEntry>> {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .obj::<LeaderboardStoredEntry>()
+            .one(github_id)
+            .await?)
+    }
+
+    async fn count_leaderboard_entries(
+        &self,
+        board_id: &str,
+        ahead_of: Option<&LeaderboardCursor>,
+    ) -> StorageResult<u32> {
+        let Some(cursor) = ahead_of else {
+            return self.count_entries(board_id, |_| None).await;
+        };
+        // Firestore has no tuple comparison, so ties on `sort_key` are counted
+        // separately.
+        let higher = self
+            .count_entries(board_id, |q| {
+                q.field("sort_key").greater_than(cursor.sort_key)
+            })
+            .await?;
+        let tied = self
+            .count_entries(board_id, |q| {
+                q.for_all([
+                    q.field("sort_key").eq(cursor.sort_key),
+                    q.field("github_id").greater_than(&cursor.github_id),
+                ])
+            })
+            .await?;
+        Ok(higher + tied)
+    }
+
+    async fn leaderboard_entries_after(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .from(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .order_by([
+                ("sort_key", FirestoreQueryDirection::Descending),
+                ("github_id", FirestoreQueryDirection::Descending),
+            ])
+            .start_at(FirestoreQueryCursor::AfterValue(vec![
+                (&cursor.sort_key).into(),
+                (&cursor.github_id).into(),
+            ]))
+            .limit(limit)
+            .obj::<LeaderboardStoredEntry>()
+            .query()
+            .await?)
+    }
+
+    async fn leaderboard_entries_before(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        // Read nearest first, returned in board order.
+        let mut entries: Vec<LeaderboardStoredEntry> = self
+            .db
+            .fluent()
+            .select()
+            .from(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .order_by([
+                ("sort_key", FirestoreQueryDirection::Ascending),
+                ("github_id", FirestoreQueryDirection::Ascending),
+            ])
+            .start_at(FirestoreQueryCursor::AfterValue(vec![
+                (&cursor.sort_key).into(),
+                (&cursor.github_id).into(),
+            ]))
+            .limit(limit)
+            .obj::<LeaderboardStoredEntry>()
+            .query()
+            .await?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    async fn leaderboard_entries_for(
+        &self,
+        board_id: &str,
+        github_ids: &[String],
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let parent = self.db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+        // Entries are stored under the player's ID, so this is one batch read.
+        let mut entries: Vec<LeaderboardStoredEntry> = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(LEADERBOARD_ENTRIES_COLLECTION)
+            .parent(&parent)
+            .obj::<LeaderboardStoredEntry>()
+            .batch(github_ids)
+            .await?
+            .filter_map(|(_, entry)| futures::future::ready(entry))
+            .collect()
+            .await;
+        sort_in_board_order(&mut entries);
+        Ok(entries)
+    }
+
+    async fn get_run_ghost(&self, run_id: &str) -> StorageResult<Option<RunGhost>> {
+        let stored = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(RUN_GHOSTS_COLLECTION)
+            .obj::<StoredRunGhost>()
+            .one(run_id)
+            .await?;
+        Ok(stored
+            .filter(|stored| stored.expires_at > Utc::now())
+            .map(|stored| stored.ghost))
+    }
+
+    async fn create_team(&self, team: &Team) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .insert()
+            .into(TEAMS_COLLECTION)
+            .document_id(&team.id)
+            .object(team)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn team_by_invite(&self, invite_code: &str) -> StorageResult<Option<Team>> {
+        let mut team_stream = self
+            .db
+            .fluent()
+            .select()
+            .from(TEAMS_COLLECTION)
+            .filter(|q| q.field("invite_code").eq(invite_code))
+            .limit(1)
+            .obj::<Team>()
+            .stream_query()
+            .await?;
+
+        Ok(team_stream.next().await)
+    }
+
+    async fn add_team_member(&self, team_id: &str, user_id: &str) -> StorageResult<TeamJoin> {
+        let team_id = team_id.to_string();
+        let user_id = user_id.to_string();
+        // Read and written in one transaction so concurrent joins keep each
+        // other and cannot overfill the team or the player's team count.
+        Ok(self
+            .db
+            .run_transaction(move |db, transaction| {
+                let team_id = team_id.clone();
+                let user_id = user_id.clone();
+                Box::pin(async move {
+                    let Some(mut team) = db
+                        .fluent()
+                        .select()
+                        .by_id_in(TEAMS_COLLECTION)
+                        .obj::<Team>()
+                        .one(&team_id)
+                        .await?
+                    else {
+                        return Ok(TeamJoin::NoSuchTeam);
+                    };
+                    if !team.member_ids.contains(&user_id) {
+                        let teams_joined: Vec<Team> = db
+                            .fluent()
+                            .select()
+                            .from(TEAMS_COLLECTION)
+                            .filter(|q| q.field("member_ids").array_contains(user_id.as_str()))
+                            .obj::<Team>()
+                            .query()
+                            .await?;
+                        if let Some(refusal) = team_join_refusal(&team, teams_joined.len()) {
+                            return Ok(refusal);
+                        }
+                        team.member_ids.push(user_id);
+                        db.fluent()
+                            .update()
+                            .fields(paths!(Team::{member_ids}))
+                            .in_col(TEAMS_COLLECTION)
+                            .document_id(&team_id)
+                            .object(&team)
+                            .add_to_transaction(transaction)?;
+                    }
+                    Ok(TeamJoin::Joined(team))
+                })
+            })
+            .await?)
+    }
+
+    async fn teams_for_user(&self, user_id: &str) -> StorageResult<Vec<Team>> {
+        let mut teams: Vec<Team> = self
+            .db
+            .fluent()
+            .select()
+            .from(TEAMS_COLLECTION)
+            .filter(|q| q.field("member_ids").array_contains(user_id))
+            .obj::<Team>()
+            .query()
+            .await?;
+        teams.sort_by_key(|team| team.created_at);
+        Ok(teams)
+    }
+
+    async fn followed_players(&self, user_id: &str) -> StorageResult<Vec<FollowedPlayer>> {
+        let parent = self.db.parent_path(USERS_COLLECTION, user_id)?;
+        let mut players: Vec<FollowedPlayer> = self
+            .db
+            .fluent()
+            .select()
+            .from(FOLLOWING_COLLECTION)
+            .parent(&parent)
+            .obj::<FollowedPlayer>()
+            .query()
+            .await?;
+        players.sort_by_key(|player| player.login.to_lowercase());
+        Ok(players)
+    }
+
+    async fn follow_players(
+        &self,
+        user_id: &str,
+        players: Vec<FollowedPlayer>,
+    ) -> StorageResult<Vec<FollowedPlayer>> {
+        let user_id = user_id.to_string();
+        let parent = self.db.parent_path(USERS_COLLECTION, &user_id)?;
+        // One transaction counts the follows and commits every write at once;
+        // MAX_FOLLOWS keeps it within Firestore's 500 writes per commit.
+        Ok(self
+            .db
+            .run_transaction(move |db, transaction| {
+                let user_id = user_id.clone();
+                let parent = parent.clone();
+                let players = players.clone();
+                Box::pin(async move {
+                    let followed: Vec<FollowedPlayer> = db
+                        .fluent()
+                        .select()
+                        .from(FOLLOWING_COLLECTION)
+                        .parent(&parent)
+                        .obj::<FollowedPlayer>()
+                        .query()
+                        .await?;
+                    let followed = followed
+                        .into_iter()
+                        .map(|player| player.github_id)
+                        .collect::<Vec<_>>();
+                    let accepted = follows_within_limit(&user_id, &followed, players);
+                    for player in &accepted {
+                        db.fluent()
+                            .update()
+                            .in_col(FOLLOWING_COLLECTION)
+                            .document_id(&player.github_id)
+                            .parent(&parent)
+                            .object(player)
+                            .add_to_transaction(transaction)?;
+                    }
+                    Ok(accepted)
+                })
+            })
+            .await?)
+    }
+
+    async fn unfollow_player(&self, user_id: &str, github_id: &str) -> StorageResult<()> {
+        let parent = self.db.parent_path(USERS_COLLECTION, user_id)?;
+        self.db
+            .fluent()
+            .delete()
+            .from(FOLLOWING_COLLECTION)
+            .document_id(github_id)
+            .parent(&parent)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_key_stats(&self, user_id: &str) -> StorageResult<KeyStats> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(KEY_STATS_COLLECTION)
+            .obj::<KeyStats>()
+            .one(user_id)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn save_typing_result(
+        &self,
+        write: ResultWrite,
+    ) -> StorageResult<Option<(TypingResult, Vec<String>)>> {
+        Ok(self
+            .db
+            .run_transaction(move |db, transaction| {
+                let write = write.clone();
+                Box::pin(async move {
+                    let ResultWrite {
+                        user_id,
+                        result,
+                        challenge_date,
+                        board_ids,
+                        key_stats,
+                        ghost,
+                        ghost_expires_at,
+                        ticket_expires_at,
+                    } = write;
+                    let used_ticket = db
+                        .fluent()
+                        .select()
+                        .by_id_in(RUN_TICKETS_COLLECTION)
+                        .obj::<UsedRunTicket>()
+                        .one(&result.run_id)
+                        .await?;
+                    if used_ticket.is_some() {
+                        return Ok(None);
+                    }
+                    let parent = db.parent_path(USERS_COLLECTION, &user_id)?;
+
+                    let mut user = db
+                        .fluent()
+                        .select()
+                        .by_id_in(USERS_COLLECTION)
+                        .obj::<UserProfile>()
+                        .one(&user_id)
+                        .await?
+                        .ok_or_else(|| {
+                            FirestoreError::DataNotFoundError(
+                                firestore::errors::FirestoreDataNotFoundError {
+                                    public: firestore::errors::FirestoreErrorPublicGenericDetails {
+                                        code: "profile_missing".to_string(),
+                                    },
+                                    data_detail_message: format!(
+                                        "Authenticated profile {user_id} no longer exists"
+                                    ),
+                                },
+                            )
+                        })?;
+
+                    apply_result_to_profile(&mut user, &result);
+                    let mut key_totals = db
+                        .fluent()
+                        .select()
+                        .by_id_in(KEY_STATS_COLLECTION)
+                        .obj::<KeyStats>()
+                        .one(&user_id)
+                        .await?
+                        .unwrap_or_default();
+                    key_totals.merge(&key_stats);
+
+                    db.fluent()
+                        .update()
+                        .in_col(RUN_TICKETS_COLLECTION)
+                        .document_id(&result.run_id)
+                        .object(&UsedRunTicket {
+                            expires_at: ticket_expires_at,
+                        })
+                        .add_to_transaction(transaction)?;
+                    db.fluent()
+                        .update()
+                        .in_col(TYPING_RESULTS_COLLECTION)
+                        .document_id(&result.run_id)
+                        .parent(&parent)
+                        .object(&result)
+                        .add_to_transaction(transaction)?;
+                    db.fluent()
+                        .update()
+                        .in_col(USERS_COLLECTION)
+                        .document_id(&user_id)
+                        .object(&user)
+                        .add_to_transaction(transaction)?;
+                    db.fluent()
+                        .update()
+                        .in_col(KEY_STATS_COLLECTION)
+                        .document_id(&user_id)
+                        .object(&key_totals)
+                        .add_to_transaction(transaction)?;
+
+                    let candidate = leaderboard_candidate(&user, &result, &challenge_date);
+                    let mut touched_boards = Vec::new();
+                    for board_id in board_ids {
+                        if upsert_leaderboard_entry(&db, transaction, &board_id, &candidate).await?
+                        {
+                            touched_boards.push(board_id);
+                        }
+                    }
+                    if let Some(timeline) = ghost.filter(|_| !touched_boards.is_empty()) {
+                        db.fluent()
+                            .update()
+                            .in_col(RUN_GHOSTS_COLLECTION)
+                            .document_id(&result.run_id)
+                            .object(&StoredRunGhost {
+                                ghost: run_ghost(&user, &result, &challenge_date, timeline),
+                                expires_at: ghost_expires_at,
+                            })
+                            .add_to_transaction(transaction)?;
+                    }
+
+                    Ok(Some((result, touched_boards)))
+                })
+            })
+            .await?)
+    }
+
+    async fn set_race_placement(
+        &self,
+        user_id: &str,
+        run_id: &str,
+        placement: &RacePlacement,
+    ) -> StorageResult<()> {
+        let parent = self.db.parent_path(USERS_COLLECTION, user_id)?;
+        let Some(mut result) = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(TYPING_RESULTS_COLLECTION)
+            .parent(&parent)
+            .obj::<TypingResult>()
+            .one(run_id)
+            .await?
+        else {
+            return Ok(());
+        };
+        result.race = Some(placement.clone());
+        self.db
+            .fluent()
+            .update()
+            .fields(paths!(TypingResult::{race}))
+            .in_col(TYPING_RESULTS_COLLECTION)
+            .document_id(run_id)
+            .parent(&parent)
+            .object(&result)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn put_session(&self, token_hash: &str, session: &SessionRecord) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .update()
+            .in_col(SESSIONS_COLLECTION)
+            .document_id(token_hash)
+            .object(session)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> StorageResult<Option<SessionRecord>> {
+        Ok(self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(SESSIONS_COLLECTION)
+            .obj::<SessionRecord>()
+            .one(token_hash)
+            .await?)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .delete()
+            .from(SESSIONS_COLLECTION)
+            .document_id(token_hash)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    async fn put_oauth_state(
+        &self,
+        state_hash: &str,
+        state: &OAuthStateRecord,
+    ) -> StorageResult<()> {
+        self.db
+            .fluent()
+            .update()
+            .in_col(OAUTH_STATES_COLLECTION)
+            .document_id(state_hash)
+            .object(state)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn take_oauth_state(&self, state_hash: &str) -> StorageResult<Option<OAuthStateRecord>> {
+        // Reading in the transaction makes a concurrent take of the same state
+        // retry, see the deletion and come back empty.
+        let state_hash = state_hash.to_string();
+        Ok(self
+            .db
+            .run_transaction(move |db, transaction| {
+                let state_hash = state_hash.clone();
+                Box::pin(async move {
+                    let state = db
+                        .fluent()
+                        .select()
+                        .by_id_in(OAUTH_STATES_COLLECTION)
+                        .obj::<OAuthStateRecord>()
+                        .one(&state_hash)
+                        .await?;
+                    if state.is_some() {
+                        db.fluent()
+                            .delete()
+                            .from(OAUTH_STATES_COLLECTION)
+                            .document_id(&state_hash)
+                            .add_to_transaction(transaction)?;
+                    }
+                    Ok(state)
+                })
+            })
+            .await?)
+    }
+}
+
+async fn upsert_leaderboard_entry(
+    db: &FirestoreDb,
+    transaction: &mut firestore::FirestoreTransaction<'_>,
+    board_id: &str,
+    candidate: &LeaderboardStoredEntry,
+) -> firestore::FirestoreResult<bool> {
+    let parent = db.parent_path(LEADERBOARDS_COLLECTION, board_id)?;
+    let existing = db
+        .fluent()
+        .select()
+        .by_id_in(LEADERBOARD_ENTRIES_COLLECTION)
+        .parent(&parent)
+        .obj::<LeaderboardStoredEntry>()
+        .one(&candidate.github_id)
+        .await?;
+
+    if !improves_entry(existing.as_ref(), candidate) {
+        return Ok(false);
+    }
+
+    db.fluent()
+        .update()
+        .in_col(LEADERBOARD_ENTRIES_COLLECTION)
+        .document_id(&candidate.github_id)
+        .parent(&parent)
+        .object(candidate)
+        .add_to_transaction(transaction)?;
+    Ok(true)
+}
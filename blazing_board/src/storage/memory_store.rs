@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+
+use super::{
+    ChallengeCache, ResultWrite, Storage, StorageResult, TeamJoin, apply_result_to_profile,
+    follows_within_limit, improves_entry, leaderboard_candidate, profile_missing, run_ghost,
+    sort_in_board_order, team_join_refusal,
+};
+use crate::{
+    auth::{OAuthStateRecord, SessionRecord},
+    models::{
+        CodeSnippet, FollowedPlayer, KeyStats, KeyboardLayout, Language, LeaderboardCursor,
+        LeaderboardStoredEntry, RacePlacement, RunGhost, Story, Team, TypingResult, UserProfile,
+    },
+};
+
+#[derive(Default)]
+struct MemoryState {
+    stories: Vec<Story>,
+    snippets: Vec<CodeSnippet>,
+    users: HashMap<String, UserProfile>,
+    /// `user_id -> run_id -> result`
+    typing_results: HashMap<String, HashMap<String, TypingResult>>,
+    /// `user_id -> totals`
+    key_stats: HashMap<String, KeyStats>,
+    /// `run_id -> (ghost, expires_at)`
+    run_ghosts: HashMap<String, (RunGhost, DateTime<Utc>)>,
+    /// `team_id -> team`
+    teams: HashMap<String, Team>,
+    /// `user_id -> github_id -> followed player`
+    following: HashMap<String, HashMap<String, FollowedPlayer>>,
+    /// `board_id -> github_id -> entry`
+    leaderboards: HashMap<String, HashMap<String, LeaderboardStoredEntry>>,
+    sessions: HashMap<String, SessionRecord>,
+    oauth_states: HashMap<String, OAuthStateRecord>,
+    /// `run_id -> expires_at`
+    run_tickets: HashMap<String, DateTime<Utc>>,
+}
+
+/// Process-local storage for offline development and tests. Every operation
+/// holds a single lock, so multi-document writes are atomic.
+pub(crate) struct MemoryStorage {
+    state: Mutex<MemoryState>,
+    challenge_cache: ChallengeCache,
+}
+
+impl MemoryStorage {
+    pub(crate) fn with_stories(stories: Vec<Story>) -> Self {
+        Self {
+            state: Mutex::new(MemoryState {
+                stories,
+                snippets: vec![CodeSnippet::default()],
+                ..MemoryState::default()
+            }),
+            challenge_cache: ChallengeCache::default(),
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::with_stories(Language::all().map(Story::fallback).to_vec())
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn challenge_cache(&self) -> &ChallengeCache {
+        &self.challenge_cache
+    }
+
+    async fn latest_story_before(
+        &self,
+        cutoff: DateTime<Utc>,
+        language: Language,
+    ) -> StorageResult<Option<Story>> {
+        let state = self.state.lock().await;
+        Ok(state
+            .stories
+            .iter()
+            .filter(|story| story.when < cutoff && story.language == language)
+            .max_by_key(|story| story.when)
+            .cloned())
+    }
+
+    async fn latest_snippet_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> StorageResult<Option<CodeSnippet>> {
+        let state = self.state.lock().await;
+        Ok(state
+            .snippets
+            .iter()
+            .filter(|snippet| snippet.when < cutoff)
+            .max_by_key(|snippet| snippet.when)
+            .cloned())
+    }
+
+    async fn get_user(&self, user_id: &str) -> StorageResult<Option<UserProfile>> {
+        Ok(self.state.lock().await.users.get(user_id).cloned())
+    }
+
+    async fn create_user(&self, profile: &UserProfile) -> StorageResult<()> {
+        self.state
+            .lock()
+            .await
+            .users
+            .insert(profile.github_id.clone(), profile.clone());
+        Ok(())
+    }
+
+    async fn user_by_login(&self, login: &str) -> StorageResult<Option<UserProfile>> {
+        Ok(self
+            .state
+            .lock()
+            .await
+            .users
+            .values()
+            .find(|user| user.login_lowercase == login.to_lowercase())
+            .cloned())
+    }
+
+    async fn update_user_identity(&self, profile: &UserProfile) -> StorageResult<()> {
+        let mut state = self.state.lock().await;
+        let user = state
+            .users
+            .get_mut(&profile.github_id)
+            .ok_or_else(|| profile_missing(&profile.github_id))?;
+        user.login = profile.login.clone();
+        user.login_lowercase = profile.login_lowercase.clone();
+        user.display_name = profile.display_name.clone();
+        user.avatar_url = profile.avatar_url.clone();
+        user.last_login_at = profile.last_login_at;
+        Ok(())
+    }
+
+    async fn set_keyboard_layout(
+        &self,
+        user_id: &str,
+        layout: KeyboardLayout,
+    ) -> StorageResult<()> {
+        let mut state = self.state.lock().await;
+        let user = state
+            .users
+            .get_mut(user_id)
+            .ok_or_else(|| profile_missing(user_id))?;
+        user.keyboard_layout = Some(layout);
+        Ok(())
+    }
+
+    async fn set_hide_ghosts(&self, user_id: &str, hide_ghosts: bool) -> StorageResult<()> {
+        let mut state = self.state.lock().await;
+        let user = state
+            .users
+            .get_mut(user_id)
+            .ok_or_else(|| profile_missing(user_id))?;
+        user.hide_ghosts = hide_ghosts;
+        Ok(())
+    }
+
+    async fn recent_typing_results(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<TypingResult>> {
+        let state = self.state.lock().await;
+        let mut history = state
+            .typing_results
+            .get(user_id)
+            .map(|results| results.values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        history.sort_by_key(|result| std::cmp::Reverse(result.created_at_epoch_seconds));
+        history.truncate(limit as usize);
+        Ok(history)
+    }
+
+    async fn top_leaderboard_entries(
+        &self,
+        board_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let state = self.state.lock().await;
+        let mut entries = state
+            .leaderboards
+            .get(board_id)
+            .map(|entries| entries.values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        sort_in_board_order(&mut entries);
+        entries.truncate(limit as usize);
+        Ok(entries)
+    }
+
+    async fn leaderboard_entry(
+        &self,
+        board_id: &str,
+        github_id: &str,
+    ) -> StorageResult<Option<LeaderboardStoredEntry>> {
+        let state = self.state.lock().await;
+        Ok(state
+            .leaderboards
+            .get(board_id)
+            .and_then(|entries| entries.get(github_id))
+            .cloned())
+    }
+
+    async fn count_leaderboard_entries(
+        &self,
+        board_id: &str,
+        ahead_of: Option<&LeaderboardCursor>,
+    ) -> StorageResult<u32> {
+        let state = self.state.lock().await;
+        let count = state.leaderboards.get(board_id).map_or(0, |entries| {
+            entries
+                .values()
+                .filter(|entry| {
+                    ahead_of.is_none_or(|cursor| &LeaderboardCursor::of(entry) > cursor)
+                })
+                .count()
+        });
+        Ok(count as u32)
+    }
+
+    async fn leaderboard_entries_after(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let mut entries = self.top_leaderboard_entries(board_id, u32::MAX).await?;
+        entries.retain(|entry| &LeaderboardCursor::of(entry) < cursor);
+        entries.truncate(limit as usize);
+        Ok(entries)
+    }
+
+    async fn leaderboard_entries_before(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let mut entries = self.top_leaderboard_entries(board_id, u32::MAX).await?;
+        entries.retain(|entry| &LeaderboardCursor::of(entry) > cursor);
+        let skipped = entries.len().saturating_sub(limit as usize);
+        Ok(entries.split_off(skipped))
+    }
+
+    async fn leaderboard_entries_for(
+        &self,
+        board_id: &str,
+        github_ids: &[String],
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let state = self.state.lock().await;
+        let Some(board) = state.leaderboards.get(board_id) else {
+            return Ok(Vec::new());
+        };
+        let mut entries = github_ids
+            .iter()
+            .filter_map(|github_id| board.get(github_id).cloned())
+            .collect::<Vec<_>>();
+        sort_in_board_order(&mut entries);
+        Ok(entries)
+    }
+
+    async fn get_run_ghost(&self, run_id: &str) -> StorageResult<Option<RunGhost>> {
+        Ok(self
+            .state
+            .lock()
+            .await
+            .run_ghosts
+            .get(run_id)
+            .filter(|(_, expires_at)| *expires_at > Utc::now())
+            .map(|(ghost, _)| ghost.clone()))
+    }
+
+    async fn create_team(&self, team: &Team) -> StorageResult<()> {
+        self.state
+            .lock()
+            .await
+            .teams
+            .insert(team.id.clone(), team.clone());
+        Ok(())
+    }
+
+    async fn team_by_invite(&self, invite_code: &str) -> StorageResult<Option<Team>> {
+        Ok(self
+            .state
+            .lock()
+            .await
+            .teams
+            .values()
+            .find(|team| team.invite_code == invite_code)
+            .cloned())
+    }
+
+    async fn add_team_member(&self, team_id: &str, user_id: &str) -> StorageResult<TeamJoin> {
+        let mut state = self.state.lock().await;
+        let teams_joined = state
+            .teams
+            .values()
+            .filter(|team| team.member_ids.iter().any(|member| member == user_id))
+            .count();
+        let Some(team) = state.teams.get_mut(team_id) else {
+            return Ok(TeamJoin::NoSuchTeam);
+        };
+        if !team.member_ids.iter().any(|member| member == user_id) {
+            if let Some(refusal) = team_join_refusal(team, teams_joined) {
+                return Ok(refusal);
+            }
+            team.member_ids.push(user_id.to_string());
+        }
+        Ok(TeamJoin::Joined(team.clone()))
+    }
+
+    async fn teams_for_user(&self, user_id: &str) -> StorageResult<Vec<Team>> {
+        let state = self.state.lock().await;
+        let mut teams = state
+            .teams
+            .values()
+            .filter(|team| team.member_ids.iter().any(|member| member == user_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        teams.sort_by_key(|team| team.created_at);
+        Ok(teams)
+    }
+
+    async fn followed_players(&self, user_id: &str) -> StorageResult<Vec<FollowedPlayer>> {
+        let state = self.state.lock().await;
+        let mut players = state
+            .following
+            .get(user_id)
+            .map(|players| players.values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        players.sort_by_key(|player| player.login.to_lowercase());
+        Ok(players)
+    }
+
+    async fn follow_players(
+        &self,
+        user_id: &str,
+        players: Vec<FollowedPlayer>,
+    ) -> StorageResult<Vec<FollowedPlayer>> {
+        let mut state = self.state.lock().await;
+        let following = state.following.entry(user_id.to_string()).or_default();
+        let followed = following.keys().cloned().collect::<Vec<_>>();
+        let accepted = follows_within_limit(user_id, &followed, players);
+        for player in &accepted {
+            following.insert(player.github_id.clone(), player.clone());
+        }
+        Ok(accepted)
+    }
+
+    async fn unfollow_player(&self, user_id: &str, github_id: &str) -> StorageResult<()> {
+        if let Some(following) = self.state.lock().await.following.get_mut(user_id) {
+            following.remove(github_id);
+        }
+        Ok(())
+    }
+
+    async fn get_key_stats(&self, user_id: &str) -> StorageResult<KeyStats> {
+        Ok(self
+            .state
+            .lock()
+            .await
+            .key_stats
+            .get(user_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn save_typing_result(
+        &self,
+        write: ResultWrite,
+    ) -> StorageResult<Option<(TypingResult, Vec<String>)>> {
+        let mut state = self.state.lock().await;
+        let ResultWrite {
+            user_id,
+            result,
+            challenge_date,
+            board_ids,
+            key_stats,
+            ghost,
+            ghost_expires_at,
+            ticket_expires_at,
+        } = write;
+
+        let now = Utc::now();
+        state.run_tickets.retain(|_, expires_at| *expires_at > now);
+        state
+            .run_ghosts
+            .retain(|_, (_, expires_at)| *expires_at > now);
+        if state.run_tickets.contains_key(&result.run_id) {
+            return Ok(None);
+        }
+
+        let mut user = state
+            .users
+            .get(&user_id)
+            .cloned()
+            .ok_or_else(|| profile_missing(&user_id))?;
+        apply_result_to_profile(&mut user, &result);
+        let candidate = leaderboard_candidate(&user, &result, &challenge_date);
+
+        let mut touched_boards = Vec::new();
+        for board_id in board_ids {
+            let entries = state.leaderboards.entry(board_id.clone()).or_default();
+            if improves_entry(entries.get(&candidate.github_id), &candidate) {
+                entries.insert(candidate.github_id.clone(), candidate.clone());
+                touched_boards.push(board_id);
+            }
+        }
+
+        state
+            .typing_results
+            .entry(user_id.clone())
+            .or_default()
+            .insert(result.run_id.clone(), result.clone());
+        state
+            .key_stats
+            .entry(user_id.clone())
+            .or_default()
+            .merge(&key_stats);
+        if let Some(timeline) = ghost.filter(|_| !touched_boards.is_empty()) {
+            state.run_ghosts.insert(
+                result.run_id.clone(),
+                (
+                    run_ghost(&user, &result, &challenge_date, timeline),
+                    ghost_expires_at,
+                ),
+            );
+        }
+        state.users.insert(user_id, user);
+        state
+            .run_tickets
+            .insert(result.run_id.clone(), ticket_expires_at);
+
+        Ok(Some((result, touched_boards)))
+    }
+
+    async fn set_race_placement(
+        &self,
+        user_id: &str,
+        run_id: &str,
+        placement: &RacePlacement,
+    ) -> StorageResult<()> {
+        if let Some(result) = self
+            .state
+            .lock()
+            .await
+            .typing_results
+            .get_mut(user_id)
+            .and_then(|results| results.get_mut(run_id))
+        {
+            result.race = Some(placement.clone());
+        }
+        Ok(())
+    }
+
+    async fn put_session(&self, token_hash: &str, session: &SessionRecord) -> StorageResult<()> {
+        self.state
+            .lock()
+            .await
+            .sessions
+            .insert(token_hash.to_string(), session.clone());
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> StorageResult<Option<SessionRecord>> {
+        Ok(self.state.lock().await.sessions.get(token_hash).cloned())
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        self.state.lock().await.sessions.remove(token_hash);
+        Ok(())
+    }
+
+    async fn put_oauth_state(
+        &self,
+        state_hash: &str,
+        state: &OAuthStateRecord,
+    ) -> StorageResult<()> {
+        self.state
+            .lock()
+            .await
+            .oauth_states
+            .insert(state_hash.to_string(), state.clone());
+        Ok(())
+    }
+
+    async fn take_oauth_state(&self, state_hash: &str) -> StorageResult<Option<OAuthStateRecord>> {
+        Ok(self.state.lock().await.oauth_states.remove(state_hash))
+    }
+}
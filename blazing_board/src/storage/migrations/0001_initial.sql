@@ -0,0 +1,54 @@
+CREATE TABLE texts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT,
+    story TEXT NOT NULL,
+    -- JSON array of source URLs
+    sources TEXT NOT NULL DEFAULT '[]',
+    when_epoch_seconds INTEGER NOT NULL
+);
+CREATE INDEX texts_by_when ON texts (when_epoch_seconds DESC);
+
+-- Profiles, results and leaderboard entries are stored as the same JSON
+-- documents Firestore holds; the extra columns exist for keys and ordering.
+CREATE TABLE users (
+    github_id TEXT PRIMARY KEY,
+    -- Players are looked up by login ignoring case, without scanning every
+    -- profile's JSON.
+    login_lowercase TEXT NOT NULL DEFAULT '',
+    data TEXT NOT NULL
+);
+CREATE INDEX users_by_login ON users (login_lowercase);
+
+CREATE TABLE typing_results (
+    user_id TEXT NOT NULL REFERENCES users (github_id) ON DELETE CASCADE,
+    run_id TEXT NOT NULL,
+    created_at_epoch_seconds INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (user_id, run_id)
+);
+CREATE INDEX typing_results_by_recency
+    ON typing_results (user_id, created_at_epoch_seconds DESC);
+
+CREATE TABLE leaderboard_entries (
+    board_id TEXT NOT NULL,
+    github_id TEXT NOT NULL,
+    sort_key INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (board_id, github_id)
+);
+-- Boards break `sort_key` ties by the higher `github_id`, so ranks are exact
+-- and can be paged through.
+CREATE INDEX leaderboard_entries_by_rank
+    ON leaderboard_entries (board_id, sort_key DESC, github_id DESC);
+
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    expires_at_epoch_seconds INTEGER NOT NULL
+);
+
+CREATE TABLE oauth_states (
+    state_hash TEXT PRIMARY KEY,
+    pkce_verifier TEXT NOT NULL,
+    expires_at_epoch_seconds INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Run tickets already presented with a result; rows are useless once expired.
+CREATE TABLE run_tickets (
+    run_id TEXT PRIMARY KEY,
+    expires_at_epoch_seconds INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- The story as written, with punctuation and casing, for punctuated runs.
+ALTER TABLE texts ADD COLUMN raw_story TEXT;
@@ -0,0 +1,11 @@
+-- Code snippets typed in the code text mode, one published per day like texts.
+CREATE TABLE snippets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    language TEXT NOT NULL,
+    title TEXT,
+    code TEXT NOT NULL,
+    -- JSON array of source URLs
+    sources TEXT NOT NULL DEFAULT '[]',
+    when_epoch_seconds INTEGER NOT NULL
+);
+CREATE INDEX snippets_by_when ON snippets (when_epoch_seconds DESC);
@@ -0,0 +1,5 @@
+-- Per-key and per-bigram totals over a player's saved runs, as JSON.
+CREATE TABLE key_stats (
+    user_id TEXT PRIMARY KEY REFERENCES users (github_id) ON DELETE CASCADE,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Stories are generated per language; the ones stored before are English.
+ALTER TABLE texts ADD COLUMN language TEXT NOT NULL DEFAULT 'en';
+CREATE INDEX texts_by_language ON texts (language, when_epoch_seconds DESC);
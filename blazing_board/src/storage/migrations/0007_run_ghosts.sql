@@ -0,0 +1,10 @@
+-- Timelines of ranked runs, raced as ghosts by other players. Ghosts are only
+-- raced while their challenge day is one of the latest 10, so each row expires
+-- then.
+CREATE TABLE run_ghosts (
+    run_id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (github_id) ON DELETE CASCADE,
+    expires_at_epoch_seconds INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX run_ghosts_by_expiry ON run_ghosts (expires_at_epoch_seconds);
@@ -0,0 +1,16 @@
+-- Private teams. Members are also kept in their own table so a player's
+-- teams can be found without scanning every team.
+CREATE TABLE teams (
+    id TEXT PRIMARY KEY,
+    invite_code TEXT NOT NULL UNIQUE,
+    created_at_epoch_seconds INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE team_members (
+    team_id TEXT NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users (github_id) ON DELETE CASCADE,
+    PRIMARY KEY (team_id, user_id)
+);
+
+CREATE INDEX team_members_by_user ON team_members (user_id);
@@ -0,0 +1,11 @@
+-- Players each player follows. A followed GitHub account may never have
+-- signed in, so `github_id` does not reference `users`.
+CREATE TABLE follows (
+    user_id TEXT NOT NULL REFERENCES users (github_id) ON DELETE CASCADE,
+    github_id TEXT NOT NULL,
+    login TEXT NOT NULL,
+    PRIMARY KEY (user_id, github_id)
+);
+
+-- Set when the login was started to import the player's GitHub follows.
+ALTER TABLE oauth_states ADD COLUMN import_following INTEGER NOT NULL DEFAULT 0;
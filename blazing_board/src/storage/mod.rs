@@ -0,0 +1,387 @@
+#![cfg(feature = "server")]
+
+//! Persistence behind the server functions and GitHub login handlers.
+//!
+//! `STORAGE_BACKEND` selects the implementation at startup:
+//!
+//! - `firestore` (default) talks to the Firestore project from `PROJECT_ID`/`DATABASE_ID`
+//! - `memory` keeps everything in process memory and serves the bundled fallback stories
+//!   and snippet, which lets `dx serve` and the tests run without a GCP project
+//! - `sqlite` stores everything in the single file at `SQLITE_PATH` for self-hosting
+
+#[cfg(test)]
+mod conformance;
+mod firestore_store;
+mod memory_store;
+mod sqlite_store;
+
+use std::{collections::HashMap, env, fmt};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::{
+    auth::{OAuthStateRecord, SessionRecord},
+    models::{
+        CodeSnippet, DailyChallenge, FollowedPlayer, GhostTimeline, KeyStats, KeyboardLayout,
+        Language, LeaderboardCursor, LeaderboardStoredEntry, MAX_FOLLOWS, MAX_TEAM_MEMBERS,
+        MAX_TEAMS_PER_PLAYER, RacePlacement, RunGhost, Story, Team, TypingResult, UserProfile,
+        leaderboard_sort_key,
+    },
+};
+
+pub(crate) use memory_store::MemoryStorage;
+
+const DEFAULT_SQLITE_PATH: &str = "blazing_board.sqlite3";
+
+static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::const_new();
+
+#[derive(Debug)]
+pub(crate) struct StorageError(String);
+
+impl StorageError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+pub(crate) type StorageResult<T> = Result<T, StorageError>;
+
+/// Challenges already served, by day and story language. Each storage keeps
+/// its own, so instances never see each other's stories.
+pub(crate) type ChallengeCache = Mutex<HashMap<(NaiveDate, Language), DailyChallenge>>;
+
+/// Everything written when a signed-in player finishes a run.
+#[derive(Debug, Clone)]
+pub(crate) struct ResultWrite {
+    pub user_id: String,
+    pub result: TypingResult,
+    /// UTC challenge day (`YYYY-MM-DD`) stored on each leaderboard entry.
+    pub challenge_date: String,
+    /// Boards the run competes on; each keeps only the player's best entry.
+    pub board_ids: Vec<String>,
+    /// The run's per-key counts, added to the player's totals.
+    pub key_stats: KeyStats,
+    /// Kept under the run's ID so other players can race it, but only when the
+    /// run improves one of its boards. Runs that never reach a leaderboard
+    /// have none.
+    pub ghost: Option<GhostTimeline>,
+    /// When the ghost can be deleted: it cannot be raced once its challenge
+    /// day leaves the recent days.
+    pub ghost_expires_at: DateTime<Utc>,
+    /// The run's ticket, keyed by `result.run_id`, is marked used until then.
+    pub ticket_expires_at: DateTime<Utc>,
+}
+
+#[async_trait]
+pub(crate) trait Storage: Send + Sync {
+    fn challenge_cache(&self) -> &ChallengeCache;
+
+    /// Latest story in `language` published strictly before `cutoff`.
+    async fn latest_story_before(
+        &self,
+        cutoff: DateTime<Utc>,
+        language: Language,
+    ) -> StorageResult<Option<Story>>;
+
+    /// Latest code snippet published strictly before `cutoff`.
+    async fn latest_snippet_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> StorageResult<Option<CodeSnippet>>;
+
+    async fn get_user(&self, user_id: &str) -> StorageResult<Option<UserProfile>>;
+
+    async fn create_user(&self, profile: &UserProfile) -> StorageResult<()>;
+
+    /// The player whose GitHub login is `login`, ignoring letter case.
+    async fn user_by_login(&self, login: &str) -> StorageResult<Option<UserProfile>>;
+
+    /// Refreshes the GitHub identity fields without touching run totals.
+    async fn update_user_identity(&self, profile: &UserProfile) -> StorageResult<()>;
+
+    async fn set_keyboard_layout(&self, user_id: &str, layout: KeyboardLayout)
+    -> StorageResult<()>;
+
+    async fn set_hide_ghosts(&self, user_id: &str, hide_ghosts: bool) -> StorageResult<()>;
+
+    /// Most recent runs first.
+    async fn recent_typing_results(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<TypingResult>>;
+
+    /// In board order (see [`LeaderboardCursor`]).
+    async fn top_leaderboard_entries(
+        &self,
+        board_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>>;
+
+    async fn leaderboard_entry(
+        &self,
+        board_id: &str,
+        github_id: &str,
+    ) -> StorageResult<Option<LeaderboardStoredEntry>>;
+
+    /// Entries on the board, or only those ranked ahead of `ahead_of`.
+    async fn count_leaderboard_entries(
+        &self,
+        board_id: &str,
+        ahead_of: Option<&LeaderboardCursor>,
+    ) -> StorageResult<u32>;
+
+    /// Up to `limit` entries ranked right after `cursor`, in board order.
+    async fn leaderboard_entries_after(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>>;
+
+    /// Up to `limit` entries ranked right before `cursor`, in board order.
+    async fn leaderboard_entries_before(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>>;
+
+    /// The entries of `github_ids` on the board, in board order. Players
+    /// without an entry are left out.
+    async fn leaderboard_entries_for(
+        &self,
+        board_id: &str,
+        github_ids: &[String],
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>>;
+
+    /// The ghost saved with `run_id`, whoever typed it, unless it expired.
+    async fn get_run_ghost(&self, run_id: &str) -> StorageResult<Option<RunGhost>>;
+
+    async fn create_team(&self, team: &Team) -> StorageResult<()>;
+
+    async fn team_by_invite(&self, invite_code: &str) -> StorageResult<Option<Team>>;
+
+    /// Adds `user_id` to the team's members unless it already is one. The
+    /// team size and the player's team count are checked in the same
+    /// transaction as the write, so concurrent joins cannot overfill either.
+    async fn add_team_member(&self, team_id: &str, user_id: &str) -> StorageResult<TeamJoin>;
+
+    /// Teams `user_id` is a member of, oldest first.
+    async fn teams_for_user(&self, user_id: &str) -> StorageResult<Vec<Team>>;
+
+    /// Players `user_id` follows, sorted by login ignoring case.
+    async fn followed_players(&self, user_id: &str) -> StorageResult<Vec<FollowedPlayer>>;
+
+    /// Follows the players in `players` that [`follows_within_limit`] keeps,
+    /// counting the players already followed in the same transaction as the
+    /// writes. Players already followed get their login refreshed. Returns the
+    /// players from the list that are now followed.
+    async fn follow_players(
+        &self,
+        user_id: &str,
+        players: Vec<FollowedPlayer>,
+    ) -> StorageResult<Vec<FollowedPlayer>>;
+
+    async fn unfollow_player(&self, user_id: &str, github_id: &str) -> StorageResult<()>;
+
+    /// Per-key totals over every saved run; empty before the first one.
+    async fn get_key_stats(&self, user_id: &str) -> StorageResult<KeyStats>;
+
+    /// Marks the run ticket used, stores the run and its ghost, updates the
+    /// profile totals and key stats and upserts every board in
+    /// `write.board_ids` as one atomic unit. Returns the saved result and the
+    /// boards whose entry improved, or `None` without touching anything when
+    /// the ticket was already used.
+    async fn save_typing_result(
+        &self,
+        write: ResultWrite,
+    ) -> StorageResult<Option<(TypingResult, Vec<String>)>>;
+
+    /// Records where a saved run finished its race.
+    async fn set_race_placement(
+        &self,
+        user_id: &str,
+        run_id: &str,
+        placement: &RacePlacement,
+    ) -> StorageResult<()>;
+
+    async fn put_session(&self, token_hash: &str, session: &SessionRecord) -> StorageResult<()>;
+
+    async fn get_session(&self, token_hash: &str) -> StorageResult<Option<SessionRecord>>;
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()>;
+
+    async fn put_oauth_state(
+        &self,
+        state_hash: &str,
+        state: &OAuthStateRecord,
+    ) -> StorageResult<()>;
+
+    /// Loads and deletes an OAuth state so it can only be used once.
+    async fn take_oauth_state(&self, state_hash: &str) -> StorageResult<Option<OAuthStateRecord>>;
+}
+
+pub(crate) async fn storage() -> &'static dyn Storage {
+    STORAGE
+        .get_or_init(|| async {
+            dotenvy::dotenv().ok();
+
+            let backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| "firestore".to_string());
+            let storage: Box<dyn Storage> = match backend.trim().to_ascii_lowercase().as_str() {
+                "memory" => Box::new(MemoryStorage::default()),
+                "sqlite" => {
+                    let path =
+                        env::var("SQLITE_PATH").unwrap_or_else(|_| DEFAULT_SQLITE_PATH.to_string());
+                    Box::new(
+                        sqlite_store::SqliteStorage::open(&path)
+                            .unwrap_or_else(|error| panic!("Unable to open {path}: {error}")),
+                    )
+                }
+                "firestore" => Box::new(firestore_store::FirestoreStorage::connect().await),
+                other => {
+                    panic!("Unknown STORAGE_BACKEND {other:?}; use firestore, memory or sqlite")
+                }
+            };
+            storage
+        })
+        .await
+        .as_ref()
+}
+
+/// Applies a finished run to the profile totals and personal bests. Custom
+/// text and drills stay in the private history and leave both alone.
+pub(crate) fn apply_result_to_profile(user: &mut UserProfile, result: &TypingResult) {
+    if !result.text.is_ranked() {
+        return;
+    }
+    user.total_runs += 1;
+    user.best_wpm = user.best_wpm.max(result.wpm);
+    user.best_accuracy = user.best_accuracy.max(result.accuracy);
+    user.best_score = user.best_score.max(result.score);
+}
+
+pub(crate) fn leaderboard_candidate(
+    user: &UserProfile,
+    result: &TypingResult,
+    challenge_date: &str,
+) -> LeaderboardStoredEntry {
+    LeaderboardStoredEntry {
+        github_id: user.github_id.clone(),
+        login: user.login.clone(),
+        avatar_url: user.avatar_url.clone(),
+        score: result.score,
+        wpm: result.wpm,
+        raw_wpm: result.raw_wpm,
+        cpm: result.cpm,
+        consistency: result.consistency,
+        accuracy: result.accuracy,
+        run_id: result.run_id.clone(),
+        challenge_date: challenge_date.to_string(),
+        mode: result.mode,
+        text: result.text,
+        keyboard_layout: result.keyboard_layout,
+        sort_key: leaderboard_sort_key(result.score, result.wpm),
+        updated_at: result.created_at,
+    }
+}
+
+pub(crate) fn run_ghost(
+    user: &UserProfile,
+    result: &TypingResult,
+    challenge_date: &str,
+    timeline: GhostTimeline,
+) -> RunGhost {
+    RunGhost {
+        run_id: result.run_id.clone(),
+        github_id: user.github_id.clone(),
+        login: user.login.clone(),
+        challenge_date: challenge_date.to_string(),
+        mode: result.mode,
+        text: result.text,
+        language: result.language,
+        score: result.score,
+        wpm: result.wpm,
+        timeline,
+    }
+}
+
+/// Sorts entries the way boards rank them: highest `sort_key` first, then the
+/// higher `github_id`.
+pub(crate) fn sort_in_board_order(entries: &mut [LeaderboardStoredEntry]) {
+    entries.sort_by(|a, b| (b.sort_key, &b.github_id).cmp(&(a.sort_key, &a.github_id)));
+}
+
+/// A board keeps a player's entry until a strictly better run replaces it.
+pub(crate) fn improves_entry(
+    existing: Option<&LeaderboardStoredEntry>,
+    candidate: &LeaderboardStoredEntry,
+) -> bool {
+    existing.is_none_or(|entry| entry.sort_key < candidate.sort_key)
+}
+
+/// The players from `players` that `user_id`, who follows `followed` (GitHub
+/// IDs), can follow: not themselves, each once, and no new follow past
+/// [`MAX_FOLLOWS`].
+pub(crate) fn follows_within_limit(
+    user_id: &str,
+    followed: &[String],
+    players: Vec<FollowedPlayer>,
+) -> Vec<FollowedPlayer> {
+    let mut free_slots = MAX_FOLLOWS.saturating_sub(followed.len());
+    let mut accepted: Vec<FollowedPlayer> = Vec::new();
+    for player in players {
+        if player.github_id == user_id
+            || accepted
+                .iter()
+                .any(|accepted| accepted.github_id == player.github_id)
+        {
+            continue;
+        }
+        if !followed.contains(&player.github_id) {
+            if free_slots == 0 {
+                continue;
+            }
+            free_slots -= 1;
+        }
+        accepted.push(player);
+    }
+    accepted
+}
+
+/// What [`Storage::add_team_member`] did.
+#[derive(Debug, PartialEq)]
+pub(crate) enum TeamJoin {
+    /// The player is now, or already was, a member of this team.
+    Joined(Team),
+    NoSuchTeam,
+    TeamFull,
+    /// The player is already in [`MAX_TEAMS_PER_PLAYER`] teams.
+    TooManyTeams,
+}
+
+/// Why a player in `teams_joined` teams cannot join `team`, which they are not
+/// a member of yet.
+pub(crate) fn team_join_refusal(team: &Team, teams_joined: usize) -> Option<TeamJoin> {
+    if team.member_ids.len() >= MAX_TEAM_MEMBERS {
+        Some(TeamJoin::TeamFull)
+    } else if teams_joined >= MAX_TEAMS_PER_PLAYER {
+        Some(TeamJoin::TooManyTeams)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn profile_missing(user_id: &str) -> StorageError {
+    StorageError::new(format!("Authenticated profile {user_id} no longer exists"))
+}
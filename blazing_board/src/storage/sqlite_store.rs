@@ -0,0 +1,1126 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::{
+    ChallengeCache, ResultWrite, Storage, StorageError, StorageResult, TeamJoin,
+    apply_result_to_profile, follows_within_limit, improves_entry, leaderboard_candidate,
+    profile_missing, run_ghost, team_join_refusal,
+};
+use crate::{
+    auth::{OAuthStateRecord, SessionRecord},
+    models::{
+        CodeSnippet, FollowedPlayer, KeyStats, KeyboardLayout, Language, LeaderboardCursor,
+        LeaderboardStoredEntry, RacePlacement, RunGhost, Story, Team, TypingResult, UserProfile,
+    },
+};
+
+/// Applied in order; `PRAGMA user_version` records how many already ran.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_initial.sql"),
+    include_str!("migrations/0002_run_tickets.sql"),
+    include_str!("migrations/0003_raw_story.sql"),
+    include_str!("migrations/0004_snippets.sql"),
+    include_str!("migrations/0005_key_stats.sql"),
+    include_str!("migrations/0006_story_language.sql"),
+    include_str!("migrations/0007_run_ghosts.sql"),
+    include_str!("migrations/0008_teams.sql"),
+    include_str!("migrations/0009_follows.sql"),
+];
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(error: rusqlite::Error) -> Self {
+        StorageError::new(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(error: serde_json::Error) -> Self {
+        StorageError::new(error.to_string())
+    }
+}
+
+/// Single-file storage for self-hosted deployments. The connection is shared
+/// behind a mutex and every call runs on the blocking thread pool.
+pub(crate) struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+    challenge_cache: ChallengeCache,
+}
+
+impl SqliteStorage {
+    pub(crate) fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> StorageResult<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> StorageResult<Self> {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        migrate(&mut connection)?;
+        seed_fallback_story(&connection)?;
+        seed_fallback_snippet(&connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            challenge_cache: ChallengeCache::default(),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, operation: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> StorageResult<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| StorageError::new("SQLite connection lock is poisoned"))?;
+            operation(&mut connection)
+        })
+        .await
+        .map_err(|error| StorageError::new(format!("SQLite task failed: {error}")))?
+    }
+}
+
+fn migrate(connection: &mut Connection) -> StorageResult<()> {
+    let applied: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied.max(0) as usize) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", (version + 1) as i64)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// A fresh database has no generated stories yet; serve the bundled ones so
+/// every challenge day is playable in each language until stories in that
+/// language are inserted into `texts`.
+fn seed_fallback_story(connection: &Connection) -> StorageResult<()> {
+    for language in Language::all() {
+        let story_count: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM texts WHERE language = ?1",
+            params![language.id()],
+            |row| row.get(0),
+        )?;
+        if story_count == 0 {
+            insert_story(connection, &Story::fallback(language))?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_story(connection: &Connection, story: &Story) -> StorageResult<()> {
+    connection.execute(
+        "INSERT INTO texts (title, story, raw_story, sources, when_epoch_seconds, language)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            story.title,
+            story.story,
+            story.raw_story,
+            serde_json::to_string(&story.sources)?,
+            story.when.timestamp(),
+            story.language.id(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Same as [`seed_fallback_story`] for the `snippets` table.
+fn seed_fallback_snippet(connection: &Connection) -> StorageResult<()> {
+    let snippet_count: i64 =
+        connection.query_row("SELECT COUNT(*) FROM snippets", [], |row| row.get(0))?;
+    if snippet_count == 0 {
+        let snippet = CodeSnippet::default();
+        connection.execute(
+            "INSERT INTO snippets (language, title, code, sources, when_epoch_seconds)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                snippet.language,
+                snippet.title,
+                snippet.code,
+                serde_json::to_string(&snippet.sources)?,
+                snippet.when.timestamp(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> StorageResult<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn from_json<T: DeserializeOwned>(data: &str) -> StorageResult<T> {
+    Ok(serde_json::from_str(data)?)
+}
+
+fn from_epoch_seconds(seconds: i64) -> StorageResult<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| StorageError::new(format!("Invalid stored timestamp {seconds}")))
+}
+
+fn load_user(connection: &Connection, user_id: &str) -> StorageResult<Option<UserProfile>> {
+    connection
+        .query_row(
+            "SELECT data FROM users WHERE github_id = ?1",
+            params![user_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+}
+
+fn store_user(connection: &Connection, user: &UserProfile) -> StorageResult<()> {
+    connection.execute(
+        "INSERT INTO users (github_id, login_lowercase, data) VALUES (?1, ?2, ?3)
+         ON CONFLICT (github_id) DO UPDATE
+         SET login_lowercase = excluded.login_lowercase, data = excluded.data",
+        params![user.github_id, user.login.to_lowercase(), to_json(user)?],
+    )?;
+    Ok(())
+}
+
+fn load_key_stats(connection: &Connection, user_id: &str) -> StorageResult<KeyStats> {
+    connection
+        .query_row(
+            "SELECT data FROM key_stats WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+fn load_team(connection: &Connection, team_id: &str) -> StorageResult<Option<Team>> {
+    connection
+        .query_row(
+            "SELECT data FROM teams WHERE id = ?1",
+            params![team_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+}
+
+fn load_leaderboard_entry(
+    connection: &Connection,
+    board_id: &str,
+    github_id: &str,
+) -> StorageResult<Option<LeaderboardStoredEntry>> {
+    connection
+        .query_row(
+            "SELECT data FROM leaderboard_entries WHERE board_id = ?1 AND github_id = ?2",
+            params![board_id, github_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| from_json(&data))
+        .transpose()
+}
+
+fn store_leaderboard_entry(
+    connection: &Connection,
+    board_id: &str,
+    entry: &LeaderboardStoredEntry,
+) -> StorageResult<()> {
+    connection.execute(
+        "INSERT INTO leaderboard_entries (board_id, github_id, sort_key, data)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (board_id, github_id)
+         DO UPDATE SET sort_key = excluded.sort_key, data = excluded.data",
+        params![board_id, entry.github_id, entry.sort_key, to_json(entry)?],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn challenge_cache(&self) -> &ChallengeCache {
+        &self.challenge_cache
+    }
+
+    async fn latest_story_before(
+        &self,
+        cutoff: DateTime<Utc>,
+        language: Language,
+    ) -> StorageResult<Option<Story>> {
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT title, story, sources, when_epoch_seconds, raw_story FROM texts
+                     WHERE when_epoch_seconds < ?1 AND language = ?2
+                     ORDER BY when_epoch_seconds DESC, id DESC
+                     LIMIT 1",
+                    params![cutoff.timestamp(), language.id()],
+                    |row| {
+                        Ok((
+                            row.get::<_, Option<String>>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, i64>(3)?,
+                            row.get::<_, Option<String>>(4)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            row.map(|(title, story, sources, when, raw_story)| {
+                Ok(Story {
+                    sources: from_json(&sources)?,
+                    story,
+                    raw_story,
+                    title,
+                    when: from_epoch_seconds(when)?,
+                    language,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    async fn latest_snippet_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> StorageResult<Option<CodeSnippet>> {
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT language, title, code, sources, when_epoch_seconds FROM snippets
+                     WHERE when_epoch_seconds < ?1
+                     ORDER BY when_epoch_seconds DESC, id DESC
+                     LIMIT 1",
+                    params![cutoff.timestamp()],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, String>(3)?,
+                            row.get::<_, i64>(4)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            row.map(|(language, title, code, sources, when)| {
+                Ok(CodeSnippet {
+                    language,
+                    title,
+                    code,
+                    sources: from_json(&sources)?,
+                    when: from_epoch_seconds(when)?,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    async fn get_user(&self, user_id: &str) -> StorageResult<Option<UserProfile>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| load_user(connection, &user_id))
+            .await
+    }
+
+    async fn create_user(&self, profile: &UserProfile) -> StorageResult<()> {
+        let profile = profile.clone();
+        self.with_connection(move |connection| store_user(connection, &profile))
+            .await
+    }
+
+    async fn user_by_login(&self, login: &str) -> StorageResult<Option<UserProfile>> {
+        let login = login.to_lowercase();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT data FROM users WHERE login_lowercase = ?1 LIMIT 1",
+                    params![login],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .map(|data| from_json(&data))
+                .transpose()
+        })
+        .await
+    }
+
+    async fn update_user_identity(&self, profile: &UserProfile) -> StorageResult<()> {
+        let profile = profile.clone();
+        self.with_connection(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut user = load_user(&transaction, &profile.github_id)?
+                .ok_or_else(|| profile_missing(&profile.github_id))?;
+            user.login = profile.login;
+            user.login_lowercase = profile.login_lowercase;
+            user.display_name = profile.display_name;
+            user.avatar_url = profile.avatar_url;
+            user.last_login_at = profile.last_login_at;
+            store_user(&transaction, &user)?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_keyboard_layout(
+        &self,
+        user_id: &str,
+        layout: KeyboardLayout,
+    ) -> StorageResult<()> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut user =
+                load_user(&transaction, &user_id)?.ok_or_else(|| profile_missing(&user_id))?;
+            user.keyboard_layout = Some(layout);
+            store_user(&transaction, &user)?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_hide_ghosts(&self, user_id: &str, hide_ghosts: bool) -> StorageResult<()> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut user =
+                load_user(&transaction, &user_id)?.ok_or_else(|| profile_missing(&user_id))?;
+            user.hide_ghosts = hide_ghosts;
+            store_user(&transaction, &user)?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn recent_typing_results(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<TypingResult>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM typing_results WHERE user_id = ?1
+                 ORDER BY created_at_epoch_seconds DESC
+                 LIMIT ?2",
+            )?;
+            let rows = statement
+                .query_map(params![user_id, limit], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.iter().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn top_leaderboard_entries(
+        &self,
+        board_id: &str,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let board_id = board_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM leaderboard_entries WHERE board_id = ?1
+                 ORDER BY sort_key DESC, github_id DESC
+                 LIMIT ?2",
+            )?;
+            let rows = statement
+                .query_map(params![board_id, limit], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.iter().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn leaderboard_entry(
+        &self,
+        board_id: &str,
+        github_id: &str,
+    ) -> StorageResult<Option<LeaderboardStoredEntry>> {
+        let board_id = board_id.to_string();
+        let github_id = github_id.to_string();
+        self.with_connection(move |connection| {
+            load_leaderboard_entry(connection, &board_id, &github_id)
+        })
+        .await
+    }
+
+    async fn count_leaderboard_entries(
+        &self,
+        board_id: &str,
+        ahead_of: Option<&LeaderboardCursor>,
+    ) -> StorageResult<u32> {
+        let board_id = board_id.to_string();
+        let ahead_of = ahead_of.cloned();
+        self.with_connection(move |connection| {
+            let count = match ahead_of {
+                Some(cursor) => connection.query_row(
+                    "SELECT COUNT(*) FROM leaderboard_entries
+                     WHERE board_id = ?1
+                         AND (sort_key > ?2 OR (sort_key = ?2 AND github_id > ?3))",
+                    params![board_id, cursor.sort_key, cursor.github_id],
+                    |row| row.get(0),
+                )?,
+                None => connection.query_row(
+                    "SELECT COUNT(*) FROM leaderboard_entries WHERE board_id = ?1",
+                    params![board_id],
+                    |row| row.get(0),
+                )?,
+            };
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn leaderboard_entries_after(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let board_id = board_id.to_string();
+        let cursor = cursor.clone();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM leaderboard_entries
+                 WHERE board_id = ?1
+                     AND (sort_key < ?2 OR (sort_key = ?2 AND github_id < ?3))
+                 ORDER BY sort_key DESC, github_id DESC
+                 LIMIT ?4",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![board_id, cursor.sort_key, cursor.github_id, limit],
+                    |row| row.get::<_, String>(0),
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.iter().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn leaderboard_entries_before(
+        &self,
+        board_id: &str,
+        cursor: &LeaderboardCursor,
+        limit: u32,
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let board_id = board_id.to_string();
+        let cursor = cursor.clone();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM leaderboard_entries
+                 WHERE board_id = ?1
+                     AND (sort_key > ?2 OR (sort_key = ?2 AND github_id > ?3))
+                 ORDER BY sort_key ASC, github_id ASC
+                 LIMIT ?4",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![board_id, cursor.sort_key, cursor.github_id, limit],
+                    |row| row.get::<_, String>(0),
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+            // Read nearest first, returned in board order.
+            rows.iter().rev().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn leaderboard_entries_for(
+        &self,
+        board_id: &str,
+        github_ids: &[String],
+    ) -> StorageResult<Vec<LeaderboardStoredEntry>> {
+        let board_id = board_id.to_string();
+        let github_ids = to_json(&github_ids)?;
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data FROM leaderboard_entries
+                 WHERE board_id = ?1 AND github_id IN (SELECT value FROM json_each(?2))
+                 ORDER BY sort_key DESC, github_id DESC",
+            )?;
+            let rows = statement
+                .query_map(params![board_id, github_ids], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.iter().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn get_run_ghost(&self, run_id: &str) -> StorageResult<Option<RunGhost>> {
+        let run_id = run_id.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT data FROM run_ghosts
+                     WHERE run_id = ?1 AND expires_at_epoch_seconds > ?2",
+                    params![run_id, Utc::now().timestamp()],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .map(|data| from_json(&data))
+                .transpose()
+        })
+        .await
+    }
+
+    async fn create_team(&self, team: &Team) -> StorageResult<()> {
+        let team = team.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO teams (id, invite_code, created_at_epoch_seconds, data)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    team.id,
+                    team.invite_code,
+                    team.created_at.timestamp(),
+                    to_json(&team)?
+                ],
+            )?;
+            for member_id in &team.member_ids {
+                transaction.execute(
+                    "INSERT INTO team_members (team_id, user_id) VALUES (?1, ?2)",
+                    params![team.id, member_id],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn team_by_invite(&self, invite_code: &str) -> StorageResult<Option<Team>> {
+        let invite_code = invite_code.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT data FROM teams WHERE invite_code = ?1",
+                    params![invite_code],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .map(|data| from_json(&data))
+                .transpose()
+        })
+        .await
+    }
+
+    async fn add_team_member(&self, team_id: &str, user_id: &str) -> StorageResult<TeamJoin> {
+        let team_id = team_id.to_string();
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let Some(mut team) = load_team(&transaction, &team_id)? else {
+                return Ok(TeamJoin::NoSuchTeam);
+            };
+            if !team.member_ids.contains(&user_id) {
+                let teams_joined = transaction.query_row(
+                    "SELECT COUNT(*) FROM team_members WHERE user_id = ?1",
+                    params![user_id],
+                    |row| row.get::<_, i64>(0),
+                )?;
+                if let Some(refusal) = team_join_refusal(&team, teams_joined as usize) {
+                    return Ok(refusal);
+                }
+                team.member_ids.push(user_id.clone());
+                transaction.execute(
+                    "UPDATE teams SET data = ?2 WHERE id = ?1",
+                    params![team_id, to_json(&team)?],
+                )?;
+                transaction.execute(
+                    "INSERT INTO team_members (team_id, user_id) VALUES (?1, ?2)",
+                    params![team_id, user_id],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(TeamJoin::Joined(team))
+        })
+        .await
+    }
+
+    async fn teams_for_user(&self, user_id: &str) -> StorageResult<Vec<Team>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT teams.data FROM teams
+                 JOIN team_members ON team_members.team_id = teams.id
+                 WHERE team_members.user_id = ?1
+                 ORDER BY teams.created_at_epoch_seconds, teams.id",
+            )?;
+            let rows = statement
+                .query_map(params![user_id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.iter().map(|data| from_json(data)).collect()
+        })
+        .await
+    }
+
+    async fn followed_players(&self, user_id: &str) -> StorageResult<Vec<FollowedPlayer>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT github_id, login FROM follows WHERE user_id = ?1
+                 ORDER BY login COLLATE NOCASE",
+            )?;
+            let players = statement
+                .query_map(params![user_id], |row| {
+                    Ok(FollowedPlayer {
+                        github_id: row.get(0)?,
+                        login: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(players)
+        })
+        .await
+    }
+
+    async fn follow_players(
+        &self,
+        user_id: &str,
+        players: Vec<FollowedPlayer>,
+    ) -> StorageResult<Vec<FollowedPlayer>> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let followed = transaction
+                .prepare("SELECT github_id FROM follows WHERE user_id = ?1")?
+                .query_map(params![user_id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let accepted = follows_within_limit(&user_id, &followed, players);
+            for player in &accepted {
+                transaction.execute(
+                    "INSERT INTO follows (user_id, github_id, login) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (user_id, github_id) DO UPDATE SET login = excluded.login",
+                    params![user_id, player.github_id, player.login],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(accepted)
+        })
+        .await
+    }
+
+    async fn unfollow_player(&self, user_id: &str, github_id: &str) -> StorageResult<()> {
+        let user_id = user_id.to_string();
+        let github_id = github_id.to_string();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "DELETE FROM follows WHERE user_id = ?1 AND github_id = ?2",
+                params![user_id, github_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_key_stats(&self, user_id: &str) -> StorageResult<KeyStats> {
+        let user_id = user_id.to_string();
+        self.with_connection(move |connection| load_key_stats(connection, &user_id))
+            .await
+    }
+
+    async fn save_typing_result(
+        &self,
+        write: ResultWrite,
+    ) -> StorageResult<Option<(TypingResult, Vec<String>)>> {
+        self.with_connection(move |connection| {
+            let ResultWrite {
+                user_id,
+                result,
+                challenge_date,
+                board_ids,
+                key_stats,
+                ghost,
+                ghost_expires_at,
+                ticket_expires_at,
+            } = write;
+            // IMMEDIATE takes the write lock up front, so the reads below
+            // cannot be invalidated by a concurrent save before we commit.
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let now = Utc::now().timestamp();
+            transaction.execute(
+                "DELETE FROM run_tickets WHERE expires_at_epoch_seconds <= ?1",
+                params![now],
+            )?;
+            transaction.execute(
+                "DELETE FROM run_ghosts WHERE expires_at_epoch_seconds <= ?1",
+                params![now],
+            )?;
+            let first_use = transaction.execute(
+                "INSERT OR IGNORE INTO run_tickets (run_id, expires_at_epoch_seconds)
+                 VALUES (?1, ?2)",
+                params![result.run_id, ticket_expires_at.timestamp()],
+            )? == 1;
+            if !first_use {
+                return Ok(None);
+            }
+
+            let mut user =
+                load_user(&transaction, &user_id)?.ok_or_else(|| profile_missing(&user_id))?;
+            apply_result_to_profile(&mut user, &result);
+
+            transaction.execute(
+                "INSERT INTO typing_results (user_id, run_id, created_at_epoch_seconds, data)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    user_id,
+                    result.run_id,
+                    result.created_at_epoch_seconds,
+                    to_json(&result)?
+                ],
+            )?;
+            store_user(&transaction, &user)?;
+
+            let mut totals = load_key_stats(&transaction, &user_id)?;
+            totals.merge(&key_stats);
+            transaction.execute(
+                "INSERT INTO key_stats (user_id, data) VALUES (?1, ?2)
+                 ON CONFLICT (user_id) DO UPDATE SET data = excluded.data",
+                params![user_id, to_json(&totals)?],
+            )?;
+
+            let candidate = leaderboard_candidate(&user, &result, &challenge_date);
+            let mut touched_boards = Vec::new();
+            for board_id in board_ids {
+                let existing = load_leaderboard_entry(&transaction, &board_id, &user_id)?;
+                if improves_entry(existing.as_ref(), &candidate) {
+                    store_leaderboard_entry(&transaction, &board_id, &candidate)?;
+                    touched_boards.push(board_id);
+                }
+            }
+            if let Some(timeline) = ghost.filter(|_| !touched_boards.is_empty()) {
+                let ghost = run_ghost(&user, &result, &challenge_date, timeline);
+                transaction.execute(
+                    "INSERT INTO run_ghosts (run_id, user_id, data, expires_at_epoch_seconds)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        result.run_id,
+                        user_id,
+                        to_json(&ghost)?,
+                        ghost_expires_at.timestamp()
+                    ],
+                )?;
+            }
+
+            transaction.commit()?;
+            Ok(Some((result, touched_boards)))
+        })
+        .await
+    }
+
+    async fn set_race_placement(
+        &self,
+        user_id: &str,
+        run_id: &str,
+        placement: &RacePlacement,
+    ) -> StorageResult<()> {
+        let user_id = user_id.to_string();
+        let run_id = run_id.to_string();
+        let placement = to_json(placement)?;
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE typing_results SET data = json_set(data, '$.race', json(?3))
+                 WHERE user_id = ?1 AND run_id = ?2",
+                params![user_id, run_id, placement],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_session(&self, token_hash: &str, session: &SessionRecord) -> StorageResult<()> {
+        let token_hash = token_hash.to_string();
+        let session = session.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO sessions (token_hash, user_id, expires_at_epoch_seconds)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (token_hash) DO UPDATE SET
+                     user_id = excluded.user_id,
+                     expires_at_epoch_seconds = excluded.expires_at_epoch_seconds",
+                params![token_hash, session.user_id, session.expires_at.timestamp()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_session(&self, token_hash: &str) -> StorageResult<Option<SessionRecord>> {
+        let token_hash = token_hash.to_string();
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT user_id, expires_at_epoch_seconds FROM sessions WHERE token_hash = ?1",
+                    params![token_hash],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+                )
+                .optional()?;
+            row.map(|(user_id, expires_at)| {
+                Ok(SessionRecord {
+                    user_id,
+                    expires_at: from_epoch_seconds(expires_at)?,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        let token_hash = token_hash.to_string();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "DELETE FROM sessions WHERE token_hash = ?1",
+                params![token_hash],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_oauth_state(
+        &self,
+        state_hash: &str,
+        state: &OAuthStateRecord,
+    ) -> StorageResult<()> {
+        let state_hash = state_hash.to_string();
+        let state = state.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO oauth_states
+                     (state_hash, pkce_verifier, expires_at_epoch_seconds, import_following)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (state_hash) DO UPDATE SET
+                     pkce_verifier = excluded.pkce_verifier,
+                     expires_at_epoch_seconds = excluded.expires_at_epoch_seconds,
+                     import_following = excluded.import_following",
+                params![
+                    state_hash,
+                    state.pkce_verifier,
+                    state.expires_at.timestamp(),
+                    state.import_following
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn take_oauth_state(&self, state_hash: &str) -> StorageResult<Option<OAuthStateRecord>> {
+        let state_hash = state_hash.to_string();
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "DELETE FROM oauth_states WHERE state_hash = ?1
+                     RETURNING pkce_verifier, expires_at_epoch_seconds, import_following",
+                    params![state_hash],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, bool>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            row.map(|(pkce_verifier, expires_at, import_following)| {
+                Ok(OAuthStateRecord {
+                    pkce_verifier,
+                    expires_at: from_epoch_seconds(expires_at)?,
+                    import_following,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{MIGRATIONS, SqliteStorage};
+    use crate::{
+        models::{CodeSnippet, Language, RacePlacement, Story},
+        storage::{
+            ResultWrite, Storage,
+            conformance::{profile, write},
+        },
+    };
+
+    #[tokio::test]
+    async fn migrates_once_and_serves_the_fallback_story() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let version: i64 = storage
+            .connection
+            .lock()
+            .unwrap()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+
+        for language in Language::all() {
+            let story = storage
+                .latest_story_before(Utc::now(), language)
+                .await
+                .unwrap();
+            assert_eq!(story, Some(Story::fallback(language)));
+        }
+        let snippet = storage.latest_snippet_before(Utc::now()).await.unwrap();
+        assert_eq!(snippet, Some(CodeSnippet::default()));
+        assert_eq!(
+            storage
+                .latest_story_before(Story::default().when, Language::English)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_results_atomically_and_keeps_only_better_entries() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.create_user(&profile("1")).await.unwrap();
+
+        let (_, touched) = storage
+            .save_typing_result(write("run-00000001", 40))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(touched.len(), 2);
+        let (_, touched) = storage
+            .save_typing_result(write("run-00000002", 30))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(touched.is_empty());
+        assert!(
+            storage
+                .save_typing_result(write("run-00000001", 99))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let user = storage.get_user("1").await.unwrap().unwrap();
+        assert_eq!(user.total_runs, 2);
+        assert_eq!(user.best_score, 40);
+        let entries = storage.top_leaderboard_entries("global", 50).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].run_id, "run-00000001");
+        let placement = RacePlacement {
+            code: "ABCDE".to_string(),
+            place: 2,
+            racers: 3,
+        };
+        storage
+            .set_race_placement("1", "run-00000002", &placement)
+            .await
+            .unwrap();
+        let recent = storage.recent_typing_results("1", 20).await.unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(
+            recent
+                .iter()
+                .find(|result| result.run_id == "run-00000002")
+                .and_then(|result| result.race.clone()),
+            Some(placement)
+        );
+        let key_stats = storage.get_key_stats("1").await.unwrap();
+        assert_eq!(key_stats.for_key('a').hits, 6);
+        assert_eq!(key_stats.for_key('a').misses, 2);
+        let ghost = storage
+            .get_run_ghost("run-00000001")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((ghost.login.as_str(), ghost.score), ("player1", 40));
+        assert_eq!(ghost.timeline.steps.len(), 2);
+        assert!(
+            storage
+                .get_run_ghost("run-00000002")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_profile_rolls_back_the_whole_save() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+
+        assert!(
+            storage
+                .save_typing_result(write("run-00000001", 40))
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .top_leaderboard_entries("global", 50)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn run_tickets_are_single_use() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        for user_id in ["1", "2"] {
+            storage.create_user(&profile(user_id)).await.unwrap();
+        }
+
+        assert!(
+            storage
+                .save_typing_result(write("run-00000001", 40))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let replayed = storage
+            .save_typing_result(ResultWrite {
+                user_id: "2".to_string(),
+                ..write("run-00000001", 40)
+            })
+            .await
+            .unwrap();
+        assert!(replayed.is_none());
+        assert_eq!(storage.get_user("2").await.unwrap().unwrap().total_runs, 0);
+        assert!(
+            storage
+                .save_typing_result(write("run-00000002", 30))
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,190 @@
+#![cfg(feature = "server")]
+
+//! Signed run tickets. `start_run` issues one when a run begins and
+//! `save_typing_result` only accepts a result that presents an unused ticket
+//! for the same challenge day and story, at least as old as the run itself.
+
+use std::{env, sync::LazyLock};
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use oauth2::CsrfToken;
+use sha2::{Digest, Sha256};
+
+use crate::models::RunTicket;
+
+/// How long a ticket can be presented after it was issued.
+const TICKET_TTL_MINUTES: i64 = 15;
+/// Request latency tolerated between issuing the ticket and the first keystroke.
+const ELAPSED_SLACK_MS: i64 = 2_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+static SECRET: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    dotenvy::dotenv().ok();
+    env::var("RUN_TICKET_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .map(String::into_bytes)
+        .unwrap_or_else(|| {
+            eprintln!("RUN_TICKET_SECRET is not set; run tickets will not survive a restart");
+            CsrfToken::new_random_len(32).secret().clone().into_bytes()
+        })
+});
+
+/// What a valid ticket vouches for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TicketClaims {
+    pub(crate) run_id: String,
+    pub(crate) challenge_date: String,
+    pub(crate) story_hash: String,
+    pub(crate) issued_at: DateTime<Utc>,
+}
+
+impl TicketClaims {
+    pub(crate) fn expires_at(&self) -> DateTime<Utc> {
+        self.issued_at + Duration::minutes(TICKET_TTL_MINUTES)
+    }
+
+    /// Checks that a run of `run_ms` milliseconds could have started after
+    /// the ticket was issued and finished by `now`.
+    pub(crate) fn check_elapsed(&self, run_ms: i64, now: DateTime<Utc>) -> Result<(), String> {
+        if now >= self.expires_at() {
+            return Err("Run ticket has expired".to_string());
+        }
+        let elapsed_ms = (now - self.issued_at).num_milliseconds();
+        if run_ms > elapsed_ms + ELAPSED_SLACK_MS {
+            return Err("Run lasted longer than its ticket has existed".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn secret() -> &'static [u8] {
+    &SECRET
+}
+
+pub(crate) fn story_hash(story: &str) -> String {
+    format!("{:x}", Sha256::digest(story.as_bytes()))
+}
+
+/// Issues a ticket for a new run with a fresh server-side `run_id`.
+pub(crate) fn issue(
+    secret: &[u8],
+    challenge_date: &str,
+    story_hash: &str,
+    issued_at: DateTime<Utc>,
+) -> RunTicket {
+    let run_id = format!("run-{}", CsrfToken::new_random().secret());
+    let payload = format!(
+        "{run_id}.{challenge_date}.{story_hash}.{}",
+        issued_at.timestamp_millis()
+    );
+    let token = format!("{payload}.{}", signature(secret, &payload));
+    RunTicket { run_id, token }
+}
+
+/// Verifies the signature and returns the claims the ticket carries.
+pub(crate) fn verify(secret: &[u8], token: &str) -> Result<TicketClaims, String> {
+    let invalid = || "Run ticket is invalid".to_string();
+    let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+    let signature = decode_hex(signature).ok_or_else(invalid)?;
+    let mut mac = HmacSha256::new_from_slice(secret).map_err(|_| invalid())?;
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+    let mut parts = payload.split('.');
+    let (Some(run_id), Some(challenge_date), Some(story_hash), Some(issued_at), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(invalid());
+    };
+    let issued_at = issued_at
+        .parse::<i64>()
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .ok_or_else(invalid)?;
+
+    Ok(TicketClaims {
+        run_id: run_id.to_string(),
+        challenge_date: challenge_date.to_string(),
+        story_hash: story_hash.to_string(),
+        issued_at,
+    })
+}
+
+fn signature(secret: &[u8], payload: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{issue, story_hash, verify};
+    use crate::models::validate_run_id;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    #[test]
+    fn round_trips_signed_claims() {
+        let issued_at = Utc::now();
+        let hash = story_hash("once upon a time");
+        let ticket = issue(SECRET, "2026-07-20", &hash, issued_at);
+
+        let claims = verify(SECRET, &ticket.token).unwrap();
+        assert!(validate_run_id(&ticket.run_id).is_ok());
+        assert_eq!(claims.run_id, ticket.run_id);
+        assert_eq!(claims.challenge_date, "2026-07-20");
+        assert_eq!(claims.story_hash, hash);
+        assert_eq!(
+            claims.issued_at.timestamp_millis(),
+            issued_at.timestamp_millis()
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_or_foreign_tickets() {
+        let ticket = issue(SECRET, "2026-07-20", &story_hash("story"), Utc::now());
+
+        assert!(verify(b"other-secret", &ticket.token).is_err());
+        assert!(verify(SECRET, &ticket.token.replace("2026-07-20", "2026-07-21")).is_err());
+        assert!(verify(SECRET, "run-1.2026-07-20").is_err());
+    }
+
+    #[test]
+    fn run_must_fit_between_issue_and_submission() {
+        let now = Utc::now();
+        let ticket = issue(
+            SECRET,
+            "2026-07-20",
+            &story_hash("story"),
+            now - Duration::seconds(61),
+        );
+        let claims = verify(SECRET, &ticket.token).unwrap();
+
+        assert!(claims.check_elapsed(60_000, now).is_ok());
+        assert!(claims.check_elapsed(90_000, now).is_err());
+        assert!(
+            claims
+                .check_elapsed(60_000, now + Duration::minutes(20))
+                .is_err()
+        );
+    }
+}
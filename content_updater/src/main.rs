@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
-use firestore::{FirestoreDb, FirestoreDbOptions, FirestoreQueryDirection, FirestoreResult};
+use firestore::{paths, FirestoreDb, FirestoreDbOptions, FirestoreQueryDirection, FirestoreResult};
 use genai::chat::{ChatMessage, ChatRequest};
 use genai::Client;
 use headless_chrome::{Browser, LaunchOptions};
@@ -9,6 +9,8 @@ use std::env;
 use std::{thread, time};
 
 const MODEL: &str = "gpt-4o";
+/// Story languages the app knows, as `(id, name used in the prompt)`.
+const LANGUAGES: [(&str, &str); 3] = [("en", "English"), ("fr", "French"), ("de", "German")];
 
 struct HNQueryResult {
     link: String,
@@ -20,9 +22,48 @@ struct HNQueryResult {
 struct Story {
     sources: Vec<String>,
     story: String,
+    raw_story: String,
     title: String,
     #[serde(with = "firestore::serialize_as_timestamp")]
     when: DateTime<Utc>,
+    /// ISO 639-1 code; the app reads stories without one as English.
+    language: String,
+}
+
+/// A `texts` document as far as the language backfill is concerned.
+#[derive(Debug, Deserialize, Serialize)]
+struct StoredText {
+    #[serde(alias = "_firestore_id", skip_serializing)]
+    id: String,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// A `users` document as far as the login backfill is concerned.
+#[derive(Debug, Deserialize, Serialize)]
+struct StoredUser {
+    #[serde(alias = "_firestore_id", skip_serializing)]
+    id: String,
+    login: String,
+    #[serde(default)]
+    login_lowercase: String,
+}
+
+/// Languages listed in `STORY_LANGUAGES` (comma separated ids, `en` by default).
+fn story_languages() -> Result<Vec<(&'static str, &'static str)>> {
+    let requested = env::var("STORY_LANGUAGES").unwrap_or_else(|_| "en".to_string());
+    requested
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            LANGUAGES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(id))
+                .copied()
+                .ok_or_else(|| anyhow!("Unknown story language {id:?}"))
+        })
+        .collect()
 }
 
 async fn get_db() -> Result<FirestoreDb> {
@@ -66,6 +107,49 @@ async fn save_story(text_entry: &Story) -> Result<()> {
     Ok(())
 }
 
+/// Marks every story saved before languages existed as English, so the app's
+/// query on `language` finds it. Returns how many stories were updated.
+async fn backfill_languages() -> Result<usize> {
+    let db = get_db().await?;
+    let texts: Vec<StoredText> = db.fluent().select().from("texts").obj().query().await?;
+    let mut updated = 0;
+    for mut text in texts.into_iter().filter(|text| text.language.is_none()) {
+        text.language = Some("en".to_string());
+        db.fluent()
+            .update()
+            .fields(paths!(StoredText::{language}))
+            .in_col("texts")
+            .document_id(&text.id)
+            .object(&text)
+            .execute::<()>()
+            .await?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+async fn backfill_logins() -> Result<usize> {
+    let db = get_db().await?;
+    let users: Vec<StoredUser> = db.fluent().select().from("users").obj().query().await?;
+    let mut updated = 0;
+    for mut user in users
+        .into_iter()
+        .filter(|user| user.login_lowercase != user.login.to_lowercase())
+    {
+        user.login_lowercase = user.login.to_lowercase();
+        db.fluent()
+            .update()
+            .fields(paths!(StoredUser::{login_lowercase}))
+            .in_col("users")
+            .document_id(&user.id)
+            .object(&user)
+            .execute::<()>()
+            .await?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
 async fn query() -> Result<HNQueryResult> {
     let browser = Browser::new(
         LaunchOptions::default_builder()
@@ -105,9 +189,33 @@ async fn query() -> Result<HNQueryResult> {
     }
 }
 
-async fn generate_typing_text_entry(hn_query_result: &HNQueryResult) -> Result<Story> {
+/// Lowercase, punctuation-free version of the story used by the plain text mode.
+fn normalize_story(raw: &str) -> String {
+    raw.to_lowercase()
+        .replace('\u{2019}', "'")
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '\'' || c == '-' {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn generate_typing_text_entry(
+    hn_query_result: &HNQueryResult,
+    (language_id, language_name): (&str, &str),
+) -> Result<Story> {
     let client = Client::default();
-    let chat_req_str = format!("For a typing training program, you need to create a positive and interesting text based the hacker news article {}. You will need to infer the content of the article from the comments, please answer close to 250 words in lowercase and without ponctuation.", hn_query_result.title);
+    let chat_req_str = format!(
+        "For a typing training program, you need to create a positive and interesting text based the hacker news article {}. You will need to infer the content of the article from the comments, please answer close to 250 words of plain prose in {} with normal capitalization and punctuation, without markdown.",
+        hn_query_result.title, language_name
+    );
     let mut chat_req = ChatRequest::default().with_system(chat_req_str);
     // This is similar to sending initial system chat messages (which will be cumulative with system chat messages)
 
@@ -121,9 +229,11 @@ async fn generate_typing_text_entry(hn_query_result: &HNQueryResult) -> Result<S
     if let Some(text_result) = chat_res.content_text_as_str() {
         Ok(Story {
             sources: vec![hn_query_result.link.clone()],
-            story: text_result.to_string(),
+            story: normalize_story(text_result),
+            raw_story: text_result.trim().to_string(),
             title: hn_query_result.title.clone(),
             when: Utc::now(),
+            language: language_id.to_string(),
         })
     } else {
         Err(anyhow!("Issue with the LLM answers"))
@@ -132,18 +242,36 @@ async fn generate_typing_text_entry(hn_query_result: &HNQueryResult) -> Result<S
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+    if env::args().any(|arg| arg == "--backfill-languages") {
+        let updated = backfill_languages().await?;
+        println!("Marked {updated} stories as English");
+        return Ok(());
+    }
+    if env::args().any(|arg| arg == "--backfill-logins") {
+        let updated = backfill_logins().await?;
+        println!("Stored the lowercase login of {updated} players");
+        return Ok(());
+    }
+    let languages = story_languages()?;
     if let Ok(hn_qr) = query().await {
         println!("{}", hn_qr.title);
         println!("{}", hn_qr.link);
-        if let Ok(story_entry) = generate_typing_text_entry(&hn_qr).await {
-            println!("{}", story_entry.story);
-            match save_story(&story_entry).await {
-                Ok(()) => Ok(()),
-                _ => Err(anyhow!("Unable to save the story to the cloud")),
+        // Every language gets its own story about the same article.
+        for language in languages {
+            if let Ok(story_entry) = generate_typing_text_entry(&hn_qr, language).await {
+                println!("{}", story_entry.story);
+                if save_story(&story_entry).await.is_err() {
+                    return Err(anyhow!(
+                        "Unable to save the {} story to the cloud",
+                        language.1
+                    ));
+                }
+            } else {
+                return Err(anyhow!("Unable to generate the {} story", language.1));
             }
-        } else {
-            Err(anyhow!("Unable to generate the story"))
         }
+        Ok(())
     } else {
         Err(anyhow!("Issue while scraping"))
     }